@@ -0,0 +1,84 @@
+use clap::ValueEnum;
+use image::RgbaImage;
+
+use crate::color::ResizeFilter;
+
+// One render of `collage bench`.
+#[derive(Debug, Clone)]
+pub struct Row {
+    pub filter: ResizeFilter,
+    pub threads: usize,
+    pub total_ms: u128,
+    pub resize_ms: u128,
+    // How close the collage comes to the reference, `None` if it is a
+    // different size.
+    pub psnr: Option<f64>,
+}
+
+// The peak signal to noise ratio of `image` against `reference` in dB: the
+// higher, the closer. Identical images are infinitely close.
+pub fn psnr(image: &RgbaImage, reference: &RgbaImage) -> Option<f64> {
+    if image.dimensions() != reference.dimensions() {
+        return None;
+    }
+    let (sum, count) = image
+        .as_raw()
+        .iter()
+        .zip(reference.as_raw())
+        .fold((0.0, 0usize), |(sum, count), (&a, &b)| {
+            (sum + (a as f64 - b as f64).powi(2), count + 1)
+        });
+    let mse = sum / count.max(1) as f64;
+    Some(10.0 * (255.0 * 255.0 / mse).log10())
+}
+
+// Print the renders as a table, with the speed up of each over the same
+// filter on a single thread.
+pub fn print(rows: &[Row]) {
+    let secs = |ms: u128| ms as f64 / 1000.0;
+    println!(
+        "{:<12} {:>7} {:>8} {:>8} {:>8} {:>10}",
+        "filter", "threads", "total", "resize", "speedup", "PSNR"
+    );
+    for row in rows {
+        let single = rows
+            .iter()
+            .find(|r| r.filter == row.filter && r.threads == 1)
+            .map_or(row.total_ms, |r| r.total_ms);
+        let psnr = match row.psnr {
+            Some(psnr) if psnr.is_infinite() => "exact".to_string(),
+            Some(psnr) => format!("{psnr:.1} dB"),
+            None => "-".to_string(),
+        };
+        println!(
+            "{:<12} {:>7} {:>7.2}s {:>7.2}s {:>7.2}x {:>10}",
+            row.filter
+                .to_possible_value()
+                .map_or_else(String::new, |v| v.get_name().to_string()),
+            row.threads,
+            secs(row.total_ms),
+            secs(row.resize_ms),
+            single as f64 / row.total_ms.max(1) as f64,
+            psnr
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn psnr_of_images() {
+        let reference = RgbaImage::from_pixel(4, 4, Rgba([100, 100, 100, 255]));
+        assert_eq!(psnr(&reference, &reference), Some(f64::INFINITY));
+        // Off by 1 in every channel is a mean squared error of 1.
+        let close = RgbaImage::from_pixel(4, 4, Rgba([101, 99, 101, 254]));
+        let expected = 20.0 * 255f64.log10();
+        assert!((psnr(&close, &reference).unwrap() - expected).abs() < 1e-9);
+        let far = RgbaImage::from_pixel(4, 4, Rgba([200, 0, 200, 255]));
+        assert!(psnr(&far, &reference).unwrap() < psnr(&close, &reference).unwrap());
+        assert_eq!(psnr(&RgbaImage::new(4, 3), &reference), None);
+    }
+}
@@ -0,0 +1,102 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::PathBuf,
+};
+
+use crate::metadata;
+
+// Collapse every burst of photos, taken by the same camera at most `seconds`
+// apart from one to the next, to the largest file of the burst, which is
+// usually the sharpest. Photos without an EXIF date are all kept, and the
+// order of the photos is kept as it is.
+pub fn dedup(paths: Vec<PathBuf>, seconds: u32) -> Vec<PathBuf> {
+    let mut cameras: HashMap<Option<String>, Vec<(i64, usize)>> = HashMap::new();
+    for (i, path) in paths.iter().enumerate() {
+        if let Some(date) = metadata::date_taken(path) {
+            cameras
+                .entry(metadata::camera(path))
+                .or_default()
+                .push((date.timestamp(), i));
+        }
+    }
+    let size = |i: usize| fs::metadata(&paths[i]).map_or(0, |m| m.len());
+    let mut dropped: HashSet<usize> = HashSet::new();
+    for shots in cameras.values_mut() {
+        shots.sort();
+        let mut burst: Vec<usize> = Vec::new();
+        let mut last = None;
+        // A sentinel at the end closes the last burst.
+        for &(time, i) in shots.iter().chain([&(i64::MAX, usize::MAX)]) {
+            if last.is_some_and(|last| time.saturating_sub(last) > seconds as i64) {
+                if let Some(&keep) = burst.iter().max_by_key(|&&i| size(i)) {
+                    dropped.extend(burst.iter().filter(|&&i| i != keep));
+                }
+                burst.clear();
+            }
+            burst.push(i);
+            last = Some(time);
+        }
+    }
+    paths
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| !dropped.contains(i))
+        .map(|(_, path)| path)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::tests::exif_tiff;
+    use exif::Tag;
+    use std::path::Path;
+
+    // Write a TIFF to `dir` with the EXIF camera and capture time `date`
+    // ("YYYY:MM:DD HH:MM:SS"), padded out to `size` bytes.
+    fn photo(dir: &Path, name: &str, camera: &str, date: &str, size: usize) -> PathBuf {
+        let mut tiff = exif_tiff(&[
+            (Tag::Make, camera),
+            (Tag::Model, "X"),
+            (Tag::DateTimeOriginal, date),
+        ]);
+        assert!(tiff.len() <= size);
+        tiff.resize(size, 0);
+        let path = dir.join(name);
+        fs::write(&path, tiff).unwrap();
+        path
+    }
+
+    fn names(paths: &[PathBuf]) -> Vec<&str> {
+        paths
+            .iter()
+            .map(|p| p.file_name().unwrap().to_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn keeps_the_largest_photo_of_each_burst() {
+        let dir = std::env::temp_dir().join(format!("collage-bursts-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let paths = vec![
+            photo(&dir, "a1.tif", "A", "2024:05:01 10:00:00", 1000),
+            photo(&dir, "b1.tif", "B", "2024:05:01 10:00:01", 1000),
+            photo(&dir, "a2.tif", "A", "2024:05:01 10:00:01", 3000),
+            photo(&dir, "a3.tif", "A", "2024:05:01 10:00:03", 2000),
+            photo(&dir, "a4.tif", "A", "2024:05:01 10:00:10", 1000),
+            photo(&dir, "b2.tif", "B", "2024:05:01 10:00:02", 2000),
+        ];
+        let undated = dir.join("undated.tif");
+        fs::write(&undated, b"not an image").unwrap();
+        let mut all = paths.clone();
+        all.insert(1, undated);
+
+        // The burst runs on while each photo is within 2 seconds of the one
+        // before, so a3 belongs to it even though it's 3 seconds after a1.
+        let kept = dedup(all.clone(), 2);
+        assert_eq!(names(&kept), ["undated.tif", "a2.tif", "a4.tif", "b2.tif"]);
+        assert_eq!(names(&dedup(all, 0)).len(), 7);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
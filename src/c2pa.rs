@@ -0,0 +1,848 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use image::ImageFormat;
+use ring::{
+    rand::SystemRandom,
+    signature::{EcdsaKeyPair, ECDSA_P256_SHA256_FIXED_SIGNING},
+};
+use sha2::{Digest, Sha256};
+use std::{fs, path::Path};
+
+use crate::output;
+
+// A CBOR value, with just what a C2PA manifest needs.
+#[derive(Debug, Clone, PartialEq)]
+enum Cbor {
+    Uint(u64),
+    Int(i64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Cbor>),
+    Map(Vec<(Cbor, Cbor)>),
+    Tag(u64, Box<Cbor>),
+    Null,
+}
+
+impl Cbor {
+    fn text(s: &str) -> Cbor {
+        Cbor::Text(s.to_string())
+    }
+
+    // A map with text keys, in the order given.
+    fn map<const N: usize>(entries: [(&str, Cbor); N]) -> Cbor {
+        Cbor::Map(
+            entries
+                .into_iter()
+                .map(|(key, value)| (Cbor::text(key), value))
+                .collect(),
+        )
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.write(&mut out);
+        out
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        // The head of a data item: its major type and its length or value.
+        let head = |out: &mut Vec<u8>, major: u8, n: u64| match n {
+            0..=23 => out.push(major << 5 | n as u8),
+            24..=0xff => out.extend([major << 5 | 24, n as u8]),
+            0x100..=0xffff => {
+                out.push(major << 5 | 25);
+                out.extend((n as u16).to_be_bytes());
+            }
+            0x1_0000..=0xffff_ffff => {
+                out.push(major << 5 | 26);
+                out.extend((n as u32).to_be_bytes());
+            }
+            _ => {
+                out.push(major << 5 | 27);
+                out.extend(n.to_be_bytes());
+            }
+        };
+        match self {
+            Cbor::Uint(n) => head(out, 0, *n),
+            Cbor::Int(n) if *n >= 0 => head(out, 0, *n as u64),
+            Cbor::Int(n) => head(out, 1, (-1 - n) as u64),
+            Cbor::Bytes(bytes) => {
+                head(out, 2, bytes.len() as u64);
+                out.extend(bytes);
+            }
+            Cbor::Text(text) => {
+                head(out, 3, text.len() as u64);
+                out.extend(text.as_bytes());
+            }
+            Cbor::Array(items) => {
+                head(out, 4, items.len() as u64);
+                items.iter().for_each(|item| item.write(out));
+            }
+            Cbor::Map(entries) => {
+                head(out, 5, entries.len() as u64);
+                for (key, value) in entries {
+                    key.write(out);
+                    value.write(out);
+                }
+            }
+            Cbor::Tag(tag, item) => {
+                head(out, 6, *tag);
+                item.write(out);
+            }
+            Cbor::Null => out.push(0xf6),
+        }
+    }
+}
+
+// An ISO BMFF style box: its length, its type and `payload`.
+fn jumbf_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(payload.len() + 8);
+    out.extend((payload.len() as u32 + 8).to_be_bytes());
+    out.extend(kind);
+    out.extend(payload);
+    out
+}
+
+// A JUMBF superbox labeled `label`, whose content type is the C2PA UUID
+// starting with `kind` (e.g. `c2cl` for a claim), holding `contents`.
+fn superbox(kind: &[u8; 4], label: &str, contents: &[Vec<u8>]) -> Vec<u8> {
+    let mut description = kind.to_vec();
+    description.extend([
+        0x00, 0x11, 0x00, 0x10, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
+    ]);
+    // Requestable, with a label.
+    description.push(0x03);
+    description.extend(label.as_bytes());
+    description.push(0);
+    let mut payload = jumbf_box(b"jumd", &description);
+    contents.iter().for_each(|content| payload.extend(content));
+    jumbf_box(b"jumb", &payload)
+}
+
+// A reference from the claim to the assertion `label` with the hash of its
+// box, which is taken over its contents without the box header.
+fn hashed_uri(label: &str, assertion: &[u8]) -> Cbor {
+    Cbor::map([
+        (
+            "url",
+            Cbor::Text(format!("self#jumbf=c2pa.assertions/{label}")),
+        ),
+        (
+            "hash",
+            Cbor::Bytes(Sha256::digest(&assertion[8..]).to_vec()),
+        ),
+    ])
+}
+
+// A random (version 4) UUID.
+fn uuid() -> String {
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[6] = bytes[6] & 0x0f | 0x40;
+    bytes[8] = bytes[8] & 0x3f | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+// The DER contents of every PEM block labeled `label` in `path`.
+fn pem_blocks(path: &Path, label: &str) -> Result<Vec<Vec<u8>>> {
+    let text =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let (begin, end) = (
+        format!("-----BEGIN {label}-----"),
+        format!("-----END {label}-----"),
+    );
+    let mut blocks = Vec::new();
+    let mut rest = text.as_str();
+    while let Some(start) = rest.find(&begin) {
+        let body = &rest[start + begin.len()..];
+        let stop = body
+            .find(&end)
+            .with_context(|| format!("Unterminated {label} in {}", path.display()))?;
+        let base64: String = body[..stop].split_whitespace().collect();
+        blocks.push(
+            STANDARD
+                .decode(base64)
+                .with_context(|| format!("Invalid {label} in {}", path.display()))?,
+        );
+        rest = &body[stop + end.len()..];
+    }
+    Ok(blocks)
+}
+
+// The signing identity of `--c2pa-cert` and `--c2pa-key`.
+pub struct Signer {
+    // The DER certificates, the signing certificate first.
+    certs: Vec<Vec<u8>>,
+    key: EcdsaKeyPair,
+}
+
+impl Signer {
+    // Load the certificate chain and the P-256 PKCS#8 private key.
+    pub fn load(cert: &Path, key: &Path) -> Result<Self> {
+        let certs = pem_blocks(cert, "CERTIFICATE")?;
+        ensure!(
+            !certs.is_empty(),
+            "There is no certificate in {}",
+            cert.display()
+        );
+        if !pem_blocks(key, "EC PRIVATE KEY")?.is_empty() {
+            bail!(
+                "{} is a SEC1 key; convert it to PKCS#8 with `openssl pkcs8 -topk8 -nocrypt`",
+                key.display()
+            );
+        }
+        let der = pem_blocks(key, "PRIVATE KEY")?
+            .into_iter()
+            .next()
+            .with_context(|| format!("There is no private key in {}", key.display()))?;
+        let key =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &der, &SystemRandom::new())
+                .map_err(|e| anyhow!("{} is not a P-256 private key ({e})", key.display()))?;
+        Ok(Signer { certs, key })
+    }
+
+    // A COSE_Sign1 signature (ES256) of `claim`, detached from it, with the
+    // certificate chain in the protected header.
+    fn sign(&self, claim: &[u8], placeholder: bool) -> Result<Vec<u8>> {
+        let protected = Cbor::Map(vec![
+            (Cbor::Uint(1), Cbor::Int(-7)),
+            (
+                Cbor::Uint(33),
+                Cbor::Array(self.certs.iter().cloned().map(Cbor::Bytes).collect()),
+            ),
+        ])
+        .encode();
+        let signature = if placeholder {
+            vec![0; 64]
+        } else {
+            let to_sign = Cbor::Array(vec![
+                Cbor::text("Signature1"),
+                Cbor::Bytes(protected.clone()),
+                Cbor::Bytes(Vec::new()),
+                Cbor::Bytes(claim.to_vec()),
+            ])
+            .encode();
+            self.key
+                .sign(&SystemRandom::new(), &to_sign)
+                .map_err(|_| anyhow!("Failed to sign the content credentials"))?
+                .as_ref()
+                .to_vec()
+        };
+        Ok(Cbor::Tag(
+            18,
+            Box::new(Cbor::Array(vec![
+                Cbor::Bytes(protected),
+                Cbor::Map(Vec::new()),
+                Cbor::Null,
+                Cbor::Bytes(signature),
+            ])),
+        )
+        .encode())
+    }
+}
+
+// What goes into the manifest of a collage, besides the hash binding it to
+// the file.
+struct Claim<'a> {
+    label: String,
+    instance_id: String,
+    format: &'static str,
+    // A JSON assertion of how the collage was made.
+    recipe: &'a serde_json::Value,
+}
+
+// The C2PA manifest store of a file of the `format`, whose manifest will sit
+// at `start` and take `length` bytes there, and whose other bytes hash to
+// `hash`. With a placeholder, the signature is left blank but has the same
+// size.
+fn manifest_store(
+    claim: &Claim,
+    signer: &Signer,
+    (start, length): (usize, usize),
+    hash: &[u8],
+    placeholder: bool,
+) -> Result<Vec<u8>> {
+    let version = env!("CARGO_PKG_VERSION");
+    let cbor_assertion =
+        |label: &str, value: Cbor| superbox(b"cbor", label, &[jumbf_box(b"cbor", &value.encode())]);
+    let actions = cbor_assertion(
+        "c2pa.actions",
+        Cbor::map([(
+            "actions",
+            Cbor::Array(vec![Cbor::map([
+                ("action", Cbor::text("c2pa.created")),
+                ("softwareAgent", Cbor::Text(format!("collage {version}"))),
+                (
+                    "digitalSourceType",
+                    Cbor::text("http://cv.iptc.org/newscodes/digitalsourcetype/composite"),
+                ),
+            ])]),
+        )]),
+    );
+    let data_hash = cbor_assertion(
+        "c2pa.hash.data",
+        Cbor::map([
+            (
+                "exclusions",
+                Cbor::Array(vec![Cbor::map([
+                    ("start", Cbor::Uint(start as u64)),
+                    ("length", Cbor::Uint(length as u64)),
+                ])]),
+            ),
+            ("name", Cbor::text("jumbf manifest")),
+            ("alg", Cbor::text("sha256")),
+            ("hash", Cbor::Bytes(hash.to_vec())),
+            ("pad", Cbor::Bytes(Vec::new())),
+        ]),
+    );
+    let recipe_label = "io.github.jeffreyrosenbluth.collage.recipe";
+    let recipe = superbox(
+        b"json",
+        recipe_label,
+        &[jumbf_box(b"json", claim.recipe.to_string().as_bytes())],
+    );
+
+    let claim_cbor = Cbor::map([
+        ("claim_generator", Cbor::Text(format!("collage/{version}"))),
+        (
+            "claim_generator_info",
+            Cbor::Array(vec![Cbor::map([
+                ("name", Cbor::text("collage")),
+                ("version", Cbor::text(version)),
+            ])]),
+        ),
+        ("signature", Cbor::text("self#jumbf=c2pa.signature")),
+        (
+            "assertions",
+            Cbor::Array(vec![
+                hashed_uri("c2pa.actions", &actions),
+                hashed_uri("c2pa.hash.data", &data_hash),
+                hashed_uri(recipe_label, &recipe),
+            ]),
+        ),
+        ("dc:format", Cbor::text(claim.format)),
+        ("instanceID", Cbor::Text(claim.instance_id.clone())),
+        ("alg", Cbor::text("sha256")),
+    ])
+    .encode();
+    let signature = signer.sign(&claim_cbor, placeholder)?;
+
+    let manifest = superbox(
+        b"c2ma",
+        &claim.label,
+        &[
+            superbox(b"c2as", "c2pa.assertions", &[actions, data_hash, recipe]),
+            superbox(b"c2cl", "c2pa.claim", &[jumbf_box(b"cbor", &claim_cbor)]),
+            superbox(b"c2cs", "c2pa.signature", &[jumbf_box(b"cbor", &signature)]),
+        ],
+    );
+    Ok(superbox(b"c2pa", "c2pa", &[manifest]))
+}
+
+// Where the manifest goes in a PNG: in a `caBX` chunk right after `IHDR`.
+fn png_offset(image: &[u8]) -> Result<usize> {
+    ensure!(
+        image.starts_with(b"\x89PNG\r\n\x1a\n") && image.get(12..16) == Some(b"IHDR"),
+        "Not a PNG"
+    );
+    Ok(8 + 12 + u32::from_be_bytes(image[8..12].try_into()?) as usize)
+}
+
+fn png_chunk(store: &[u8]) -> Vec<u8> {
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(b"caBX");
+    crc.update(store);
+    let mut chunk = (store.len() as u32).to_be_bytes().to_vec();
+    chunk.extend(b"caBX");
+    chunk.extend(store);
+    chunk.extend(crc.finalize().to_be_bytes());
+    chunk
+}
+
+// Where the manifest goes in a JPEG: after the start of image and the APP0
+// and APP1 (JFIF and EXIF) segments that have to come first.
+fn jpeg_offset(image: &[u8]) -> Result<usize> {
+    ensure!(image.starts_with(&[0xff, 0xd8]), "Not a JPEG");
+    let mut offset = 2;
+    while let [0xff, 0xe0 | 0xe1, high, low, ..] = image[offset.min(image.len())..] {
+        offset += 2 + u16::from_be_bytes([high, low]) as usize;
+    }
+    ensure!(offset <= image.len(), "Truncated JPEG");
+    Ok(offset)
+}
+
+// The manifest store as APP11 segments, split as in ISO 19566-5: each holds
+// the box instance and its sequence number, and every segment after the first
+// repeats the header of the store's box.
+fn jpeg_segments(store: &[u8]) -> Vec<u8> {
+    const MAX: usize = 0xffff - 2 - 2 - 2 - 4;
+    let mut segments = Vec::new();
+    let (mut rest, mut sequence) = (store, 1u32);
+    while !rest.is_empty() {
+        let repeat = if sequence == 1 { &[][..] } else { &store[..8] };
+        let (data, next) = rest.split_at(rest.len().min(MAX - repeat.len()));
+        let length = 2 + 2 + 2 + 4 + repeat.len() + data.len();
+        segments.extend([0xff, 0xeb]);
+        segments.extend((length as u16).to_be_bytes());
+        segments.extend(b"JP");
+        segments.extend(1u16.to_be_bytes());
+        segments.extend(sequence.to_be_bytes());
+        segments.extend(repeat);
+        segments.extend(data);
+        rest = next;
+        sequence += 1;
+    }
+    segments
+}
+
+// Wraps a manifest store in what holds it in an image format.
+type Wrap = fn(&[u8]) -> Vec<u8>;
+
+// Embed C2PA Content Credentials in the PNG or JPEG at `path`, signed by
+// `signer`: that collage made it, `recipe`, and a hash binding them to every
+// other byte of the file.
+pub fn embed(path: &Path, signer: &Signer, recipe: &serde_json::Value) -> Result<()> {
+    let image = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let (format, offset, wrap) = match ImageFormat::from_path(path) {
+        Ok(ImageFormat::Png) => ("image/png", png_offset(&image)?, png_chunk as Wrap),
+        Ok(ImageFormat::Jpeg) => ("image/jpeg", jpeg_offset(&image)?, jpeg_segments as Wrap),
+        _ => bail!(
+            "Content credentials can only be embedded in a PNG or JPEG, not {}",
+            path.display()
+        ),
+    };
+    let claim = Claim {
+        label: format!("urn:uuid:{}", uuid()),
+        instance_id: format!("xmp:iid:{}", uuid()),
+        format,
+        recipe,
+    };
+
+    // The length of the manifest is part of the manifest, so it's found by
+    // building it until it stops changing, which takes a step or two.
+    let mut length = 0;
+    loop {
+        let store = manifest_store(&claim, signer, (offset, length), &[0; 32], true)?;
+        let wrapped = wrap(&store).len();
+        if wrapped == length {
+            break;
+        }
+        length = wrapped;
+    }
+    // Without the manifest the file is just the image, so its hash is the
+    // hash of the image.
+    let hash = Sha256::digest(&image);
+    let store = manifest_store(&claim, signer, (offset, length), &hash, false)?;
+    let manifest = wrap(&store);
+    ensure!(
+        manifest.len() == length,
+        "The signed content credentials changed size"
+    );
+
+    output::write_atomic(path, |tmp| {
+        let mut file = Vec::with_capacity(image.len() + manifest.len());
+        file.extend(&image[..offset]);
+        file.extend(&manifest);
+        file.extend(&image[offset..]);
+        Ok(fs::write(tmp, file)?)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::signature::{KeyPair, UnparsedPublicKey, ECDSA_P256_SHA256_FIXED};
+    use std::collections::HashMap;
+
+    // The examples are from Appendix A of RFC 8949.
+    #[test]
+    fn cbor_integers() {
+        assert_eq!(Cbor::Uint(0).encode(), [0x00]);
+        assert_eq!(Cbor::Uint(23).encode(), [0x17]);
+        assert_eq!(Cbor::Uint(24).encode(), [0x18, 0x18]);
+        assert_eq!(Cbor::Uint(1000).encode(), [0x19, 0x03, 0xe8]);
+        assert_eq!(
+            Cbor::Uint(1_000_000).encode(),
+            [0x1a, 0x00, 0x0f, 0x42, 0x40]
+        );
+        assert_eq!(
+            Cbor::Uint(1_000_000_000_000).encode(),
+            [0x1b, 0x00, 0x00, 0x00, 0xe8, 0xd4, 0xa5, 0x10, 0x00]
+        );
+        assert_eq!(Cbor::Int(10).encode(), [0x0a]);
+        assert_eq!(Cbor::Int(-1).encode(), [0x20]);
+        assert_eq!(Cbor::Int(-100).encode(), [0x38, 0x63]);
+        assert_eq!(Cbor::Int(-1000).encode(), [0x39, 0x03, 0xe7]);
+    }
+
+    #[test]
+    fn cbor_bytes_and_text() {
+        assert_eq!(Cbor::Bytes(Vec::new()).encode(), [0x40]);
+        assert_eq!(
+            Cbor::Bytes(vec![1, 2, 3, 4]).encode(),
+            [0x44, 0x01, 0x02, 0x03, 0x04]
+        );
+        assert_eq!(Cbor::text("").encode(), [0x60]);
+        assert_eq!(Cbor::text("IETF").encode(), [0x64, 0x49, 0x45, 0x54, 0x46]);
+        assert_eq!(Cbor::text("ü").encode(), [0x62, 0xc3, 0xbc]);
+    }
+
+    #[test]
+    fn cbor_containers() {
+        assert_eq!(Cbor::Array(Vec::new()).encode(), [0x80]);
+        let nested = Cbor::Array(vec![
+            Cbor::Uint(1),
+            Cbor::Array(vec![Cbor::Uint(2), Cbor::Uint(3)]),
+            Cbor::Array(vec![Cbor::Uint(4), Cbor::Uint(5)]),
+        ]);
+        assert_eq!(
+            nested.encode(),
+            [0x83, 0x01, 0x82, 0x02, 0x03, 0x82, 0x04, 0x05]
+        );
+        let map = Cbor::map([
+            ("a", Cbor::Uint(1)),
+            ("b", Cbor::Array(vec![Cbor::Uint(2), Cbor::Uint(3)])),
+        ]);
+        assert_eq!(
+            map.encode(),
+            [0xa2, 0x61, 0x61, 0x01, 0x61, 0x62, 0x82, 0x02, 0x03]
+        );
+        let tag = Cbor::Tag(1, Box::new(Cbor::Uint(1_363_896_240)));
+        assert_eq!(tag.encode(), [0xc1, 0x1a, 0x51, 0x4b, 0x67, 0xb0]);
+        assert_eq!(Cbor::Null.encode(), [0xf6]);
+    }
+
+    // The data of the APP11 segments of `segments`, without their headers.
+    fn segment_data(segments: &[u8]) -> Vec<(u32, Vec<u8>)> {
+        let mut data = Vec::new();
+        let mut rest = segments;
+        while !rest.is_empty() {
+            assert_eq!(rest[..2], [0xff, 0xeb]);
+            let length = u16::from_be_bytes([rest[2], rest[3]]) as usize;
+            assert_eq!(&rest[4..8], b"JP\x00\x01");
+            let sequence = u32::from_be_bytes(rest[8..12].try_into().unwrap());
+            data.push((sequence, rest[12..2 + length].to_vec()));
+            rest = &rest[2 + length..];
+        }
+        data
+    }
+
+    #[test]
+    fn jpeg_segments_of_a_small_store() {
+        let store = jumbf_box(b"jumb", &[7; 100]);
+        assert_eq!(segment_data(&jpeg_segments(&store)), [(1, store)]);
+    }
+
+    #[test]
+    fn jpeg_segments_of_a_large_store() {
+        let payload: Vec<u8> = (0..150_000u32).map(|i| i as u8).collect();
+        let store = jumbf_box(b"jumb", &payload);
+        let data = segment_data(&jpeg_segments(&store));
+        assert_eq!(data.len(), 3);
+        let mut joined: Vec<u8> = Vec::new();
+        for (i, (sequence, data)) in data.iter().enumerate() {
+            assert_eq!(*sequence, i as u32 + 1);
+            if i == 0 {
+                joined.extend(data);
+            } else {
+                // Every segment after the first repeats the box header.
+                assert_eq!(data[..8], store[..8]);
+                joined.extend(&data[8..]);
+            }
+        }
+        assert_eq!(joined, store);
+    }
+
+    #[test]
+    fn jpeg_offset_skips_app0_and_app1() {
+        let image = [
+            0xff, 0xd8, 0xff, 0xe0, 0x00, 0x04, 0xaa, 0xbb, 0xff, 0xe1, 0x00, 0x02, 0xff, 0xdb,
+        ];
+        assert_eq!(jpeg_offset(&image).unwrap(), 12);
+        assert_eq!(jpeg_offset(&[0xff, 0xd8, 0xff, 0xdb]).unwrap(), 2);
+        assert!(jpeg_offset(&[0x89, b'P', b'N', b'G']).is_err());
+        assert!(jpeg_offset(&[0xff, 0xd8, 0xff, 0xe1, 0x01, 0x00]).is_err());
+    }
+
+    // Decode the CBOR value at `at`, moving past it.
+    fn decode(bytes: &[u8], at: &mut usize) -> Cbor {
+        let (major, info) = (bytes[*at] >> 5, bytes[*at] & 0x1f);
+        *at += 1;
+        let size = match info {
+            0..=23 => 0,
+            24 => 1,
+            25 => 2,
+            26 => 4,
+            _ => 8,
+        };
+        let n = if size == 0 {
+            info as u64
+        } else {
+            let mut n = [0; 8];
+            n[8 - size..].copy_from_slice(&bytes[*at..*at + size]);
+            *at += size;
+            u64::from_be_bytes(n)
+        };
+        let mut take = |len: u64| {
+            let data = bytes[*at..*at + len as usize].to_vec();
+            *at += len as usize;
+            data
+        };
+        match major {
+            0 => Cbor::Uint(n),
+            1 => Cbor::Int(-1 - n as i64),
+            2 => Cbor::Bytes(take(n)),
+            3 => Cbor::Text(String::from_utf8(take(n)).unwrap()),
+            4 => Cbor::Array((0..n).map(|_| decode(bytes, at)).collect()),
+            5 => Cbor::Map(
+                (0..n)
+                    .map(|_| (decode(bytes, at), decode(bytes, at)))
+                    .collect(),
+            ),
+            6 => Cbor::Tag(n, Box::new(decode(bytes, at))),
+            _ => {
+                assert_eq!((major, info), (7, 22), "unexpected CBOR");
+                Cbor::Null
+            }
+        }
+    }
+
+    // The value of the text `key` of a CBOR map.
+    fn get<'a>(map: &'a Cbor, key: &str) -> &'a Cbor {
+        let Cbor::Map(entries) = map else {
+            panic!("{map:?} is not a map");
+        };
+        let key = Cbor::text(key);
+        &entries.iter().find(|(k, _)| *k == key).unwrap().1
+    }
+
+    // The type, contents and whole of each box in `bytes`.
+    fn boxes(bytes: &[u8]) -> Vec<([u8; 4], &[u8], &[u8])> {
+        let mut boxes = Vec::new();
+        let mut rest = bytes;
+        while !rest.is_empty() {
+            let length = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let (whole, next) = rest.split_at(length);
+            boxes.push((whole[4..8].try_into().unwrap(), &whole[8..], whole));
+            rest = next;
+        }
+        boxes
+    }
+
+    // The first four bytes of the content type of a superbox, its boxes after
+    // the description, and its whole.
+    type Superbox<'a> = ([u8; 4], Vec<&'a [u8]>, &'a [u8]);
+
+    // The superboxes in `bytes` by label.
+    fn superboxes(bytes: &[u8]) -> HashMap<String, Superbox<'_>> {
+        boxes(bytes)
+            .into_iter()
+            .map(|(kind, payload, whole)| {
+                assert_eq!(&kind, b"jumb");
+                let inner = boxes(payload);
+                let (kind, description, _) = inner[0];
+                assert_eq!(&kind, b"jumd");
+                let label = &description[17..description.len() - 1];
+                let contents = inner[1..].iter().map(|(_, _, whole)| *whole).collect();
+                (
+                    String::from_utf8(label.to_vec()).unwrap(),
+                    (description[..4].try_into().unwrap(), contents, whole),
+                )
+            })
+            .collect()
+    }
+
+    // The manifest store of a file and where its container sits, as the start
+    // and length of the bytes the data hash leaves out.
+    fn find_store(file: &[u8]) -> (Vec<u8>, usize, usize) {
+        if file.starts_with(b"\x89PNG") {
+            let mut at = 8;
+            loop {
+                let length = u32::from_be_bytes(file[at..at + 4].try_into().unwrap()) as usize;
+                if &file[at + 4..at + 8] == b"caBX" {
+                    return (file[at + 8..at + 8 + length].to_vec(), at, 12 + length);
+                }
+                at += 12 + length;
+            }
+        }
+        let (mut at, mut start, mut end) = (2, None, 0);
+        let mut store = Vec::new();
+        while file[at + 1] != 0xda {
+            let length = u16::from_be_bytes([file[at + 2], file[at + 3]]) as usize;
+            if file[at + 1] == 0xeb {
+                start.get_or_insert(at);
+                let sequence = u32::from_be_bytes(file[at + 8..at + 12].try_into().unwrap());
+                let skip = if sequence == 1 { 12 } else { 20 };
+                store.extend(&file[at + skip..at + 2 + length]);
+                end = at + 2 + length;
+            }
+            at += 2 + length;
+        }
+        let start = start.unwrap();
+        (store, start, end - start)
+    }
+
+    // Check the content credentials of `file` as a validator does: the hash
+    // of each assertion, the data hash over every other byte and the
+    // signature of the claim. Returns the recipe.
+    fn verify(file: &[u8], public_key: &[u8]) -> serde_json::Value {
+        let (store, start, length) = find_store(file);
+        let stores = superboxes(&store);
+        let (kind, manifests, _) = &stores["c2pa"];
+        assert_eq!(kind, b"c2pa");
+        let manifests = superboxes(manifests[0]);
+        let (kind, parts, _) = manifests.values().next().unwrap();
+        assert_eq!(kind, b"c2ma");
+        let parts = parts.concat();
+        let parts = superboxes(&parts);
+
+        let assertions = parts["c2pa.assertions"].1.concat();
+        let assertions = superboxes(&assertions);
+        let claim_bytes = boxes(parts["c2pa.claim"].1[0])[0].1;
+        let claim = decode(claim_bytes, &mut 0);
+        let Cbor::Array(refs) = get(&claim, "assertions") else {
+            panic!("The claim has no assertions");
+        };
+        assert_eq!(refs.len(), assertions.len());
+        for r in refs {
+            let Cbor::Text(url) = get(r, "url") else {
+                panic!("Invalid url");
+            };
+            let label = url.rsplit('/').next().unwrap();
+            let whole = assertions[label].2;
+            assert_eq!(
+                *get(r, "hash"),
+                Cbor::Bytes(Sha256::digest(&whole[8..]).to_vec()),
+                "{label}"
+            );
+        }
+
+        let data_hash = decode(boxes(assertions["c2pa.hash.data"].1[0])[0].1, &mut 0);
+        let Cbor::Array(exclusions) = get(&data_hash, "exclusions") else {
+            panic!("The data hash has no exclusions");
+        };
+        assert_eq!(*get(&exclusions[0], "start"), Cbor::Uint(start as u64));
+        assert_eq!(*get(&exclusions[0], "length"), Cbor::Uint(length as u64));
+        let rest = [&file[..start], &file[start + length..]].concat();
+        assert_eq!(
+            *get(&data_hash, "hash"),
+            Cbor::Bytes(Sha256::digest(&rest).to_vec())
+        );
+
+        let signature = decode(boxes(parts["c2pa.signature"].1[0])[0].1, &mut 0);
+        let Cbor::Tag(18, sign1) = signature else {
+            panic!("Not a COSE_Sign1");
+        };
+        let Cbor::Array(sign1) = *sign1 else {
+            panic!("Not a COSE_Sign1");
+        };
+        let [Cbor::Bytes(protected), _, Cbor::Null, Cbor::Bytes(signature)] = &sign1[..] else {
+            panic!("Not a detached COSE_Sign1");
+        };
+        assert_eq!(*get_int(&decode(protected, &mut 0), 1), Cbor::Int(-7));
+        let to_sign = Cbor::Array(vec![
+            Cbor::text("Signature1"),
+            Cbor::Bytes(protected.clone()),
+            Cbor::Bytes(Vec::new()),
+            Cbor::Bytes(claim_bytes.to_vec()),
+        ])
+        .encode();
+        UnparsedPublicKey::new(&ECDSA_P256_SHA256_FIXED, public_key)
+            .verify(&to_sign, signature)
+            .expect("The signature doesn't verify");
+
+        let recipe = boxes(assertions["io.github.jeffreyrosenbluth.collage.recipe"].1[0])[0].1;
+        serde_json::from_slice(recipe).unwrap()
+    }
+
+    // The value of the integer `key` of a CBOR map.
+    fn get_int(map: &Cbor, key: u64) -> &Cbor {
+        let Cbor::Map(entries) = map else {
+            panic!("{map:?} is not a map");
+        };
+        &entries
+            .iter()
+            .find(|(k, _)| *k == Cbor::Uint(key))
+            .unwrap()
+            .1
+    }
+
+    // A signer with a new key and a stand-in certificate of `cert_len`
+    // bytes, which only the validation of the chain would look into.
+    fn signer(cert_len: usize) -> Signer {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+            .unwrap();
+        Signer {
+            certs: vec![vec![0x30; cert_len]],
+            key,
+        }
+    }
+
+    // Embed credentials signed with a certificate of `cert_len` bytes in an
+    // image saved as `name`, returning the file and the public key.
+    fn embedded(name: &str, cert_len: usize) -> (Vec<u8>, Vec<u8>) {
+        let dir = std::env::temp_dir().join(format!("collage-c2pa-{}-{name}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        let image = image::RgbImage::from_fn(32, 32, |x, y| {
+            image::Rgb([(x * 7) as u8, (y * 3) as u8, (x ^ y) as u8])
+        });
+        image.save(&path).unwrap();
+
+        let signer = signer(cert_len);
+        embed(&path, &signer, &recipe()).unwrap();
+        let reopened = image::open(&path).unwrap();
+        assert_eq!((reopened.width(), reopened.height()), (32, 32));
+        let file = fs::read(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        (file, signer.key.public_key().as_ref().to_vec())
+    }
+
+    fn recipe() -> serde_json::Value {
+        serde_json::json!({ "settings": { "spacing": 20 }, "seed": 7 })
+    }
+
+    #[test]
+    fn embed_round_trips_in_a_png() {
+        let (file, key) = embedded("collage.png", 500);
+        assert_eq!(verify(&file, &key), recipe());
+    }
+
+    #[test]
+    fn embed_round_trips_in_a_jpeg() {
+        let (file, key) = embedded("collage.jpg", 500);
+        assert_eq!(verify(&file, &key), recipe());
+    }
+
+    #[test]
+    fn embed_round_trips_in_a_jpeg_over_several_segments() {
+        let (file, key) = embedded("collage.jpg", 150_000);
+        assert_eq!(verify(&file, &key), recipe());
+    }
+
+    #[test]
+    #[should_panic]
+    fn verify_catches_a_changed_image() {
+        let (mut file, key) = embedded("collage.png", 500);
+        let last = file.len() - 20;
+        file[last] ^= 1;
+        verify(&file, &key);
+    }
+
+    #[test]
+    #[should_panic(expected = "The signature doesn't verify")]
+    fn verify_catches_another_key() {
+        let (file, _) = embedded("collage.jpg", 500);
+        let (_, key) = embedded("other.jpg", 500);
+        verify(&file, &key);
+    }
+}
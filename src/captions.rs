@@ -0,0 +1,164 @@
+use anyhow::{ensure, Context, Result};
+use std::{fs, path::Path};
+
+// The captions of a `--captions-from` CSV file, e.g.
+//
+// ```csv
+// file,caption
+// beach.jpg,"Low tide, early morning"
+// 2023/party.jpg,Sam's 40th
+// ```
+//
+// Each row is a file and its caption. A file is matched by the end of the
+// path of an image, so a bare file name matches that file in any folder. A
+// first row of `file` or `filename` is taken as a header.
+#[derive(Debug, Clone, Default)]
+pub struct Captions {
+    rows: Vec<(String, String)>,
+}
+
+impl Captions {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read captions file {}", path.display()))?;
+        let mut rows = Vec::new();
+        for (i, record) in parse(&text).into_iter().enumerate() {
+            let [file, caption, ..] = record.as_slice() else {
+                ensure!(
+                    record.iter().all(|field| field.trim().is_empty()),
+                    "Line {} of {} needs a file and a caption",
+                    i + 1,
+                    path.display()
+                );
+                continue;
+            };
+            let file = file.trim();
+            if i == 0 && ["file", "filename"].contains(&file.to_lowercase().as_str()) {
+                continue;
+            }
+            rows.push((file.to_string(), caption.trim().to_string()));
+        }
+        Ok(Captions { rows })
+    }
+
+    // The caption of the image at `path`, the last one that matches.
+    pub fn get(&self, path: &Path) -> Option<&str> {
+        self.rows
+            .iter()
+            .rev()
+            .find(|(file, _)| path.ends_with(file))
+            .map(|(_, caption)| caption.as_str())
+            .filter(|caption| !caption.is_empty())
+    }
+}
+
+// Split CSV text into records of fields. Fields may be quoted, with `""` for
+// a quote, to hold commas and line breaks.
+fn parse(text: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => record.push(std::mem::take(&mut field)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn captions(rows: &[(&str, &str)]) -> Captions {
+        Captions {
+            rows: rows
+                .iter()
+                .map(|(file, caption)| (file.to_string(), caption.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn parse_plain_fields() {
+        assert_eq!(
+            parse("file,caption\na.jpg,Beach\n"),
+            [["file", "caption"], ["a.jpg", "Beach"]]
+        );
+    }
+
+    #[test]
+    fn parse_without_a_final_line_break() {
+        assert_eq!(parse("a.jpg,Beach"), [["a.jpg", "Beach"]]);
+        assert_eq!(parse("a.jpg,"), [["a.jpg", ""]]);
+        assert!(parse("").is_empty());
+    }
+
+    #[test]
+    fn parse_crlf_line_breaks() {
+        assert_eq!(
+            parse("a.jpg,Beach\r\nb.jpg,Party\r\n"),
+            [["a.jpg", "Beach"], ["b.jpg", "Party"]]
+        );
+    }
+
+    #[test]
+    fn parse_quoted_fields() {
+        assert_eq!(
+            parse("a.jpg,\"Low tide, early morning\"\n"),
+            [["a.jpg", "Low tide, early morning"]]
+        );
+        assert_eq!(
+            parse("a.jpg,\"The \"\"big\"\" wave\"\n"),
+            [["a.jpg", "The \"big\" wave"]]
+        );
+        assert_eq!(
+            parse("a.jpg,\"Two\r\nlines\"\n"),
+            [["a.jpg", "Two\r\nlines"]]
+        );
+    }
+
+    #[test]
+    fn parse_blank_lines_as_one_empty_field() {
+        assert_eq!(
+            parse("a.jpg,A\n\nb.jpg,B\n"),
+            [vec!["a.jpg", "A"], vec![""], vec!["b.jpg", "B"]]
+        );
+    }
+
+    #[test]
+    fn get_matches_the_end_of_the_path() {
+        let captions = captions(&[("beach.jpg", "Beach"), ("2023/party.jpg", "Party")]);
+        assert_eq!(captions.get(Path::new("photos/beach.jpg")), Some("Beach"));
+        assert_eq!(
+            captions.get(Path::new("photos/2023/party.jpg")),
+            Some("Party")
+        );
+        assert_eq!(captions.get(Path::new("photos/2024/party.jpg")), None);
+        assert_eq!(captions.get(Path::new("photos/my_beach.jpg")), None);
+    }
+
+    #[test]
+    fn get_takes_the_last_match_and_skips_empty_captions() {
+        let captions = captions(&[("a.jpg", "First"), ("a.jpg", "Second"), ("b.jpg", "")]);
+        assert_eq!(captions.get(Path::new("a.jpg")), Some("Second"));
+        assert_eq!(captions.get(Path::new("b.jpg")), None);
+    }
+}
@@ -0,0 +1,177 @@
+use anyhow::{Context, Result};
+use directories::ProjectDirs;
+use image::RgbaImage;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    layout::{Layout, Tile},
+    Skipped,
+};
+
+// What has been computed so far in a run: the layout and which image goes in
+// which tile. The resized tiles are stored next to it as `tile_<i>.png`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct State {
+    // Every file in the image directory, to detect changes since the
+    // checkpoint was written.
+    pub files: Vec<PathBuf>,
+    // The images in the order of the tiles.
+    pub paths: Vec<PathBuf>,
+    // The size of each image before it was resized.
+    #[serde(default)]
+    pub sizes: Vec<(u32, u32)>,
+    pub skipped: Vec<Skipped>,
+    pub layout: Layout,
+    // The extra tile of the QR code, when `--qr` adds one.
+    pub qr_tile: Option<Tile>,
+    // Where the histogram of each image goes, with `--histogram per-image`.
+    pub histogram_tiles: Vec<Tile>,
+    pub seed: u64,
+}
+
+// The checkpoint of a run, in the user's cache directory. Runs are told apart
+// by `key`, which must capture every setting that affects the tiles.
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    dir: PathBuf,
+}
+
+impl Checkpoint {
+    pub fn new(key: &str) -> Result<Self> {
+        // The hash has to be the same in every build, for a run to be resumed
+        // by a newer collage.
+        let hash: String = Sha256::digest(key)
+            .iter()
+            .take(8)
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        let dir = ProjectDirs::from("", "", "collage")
+            .context("Failed to find the cache directory")?
+            .cache_dir()
+            .join("checkpoints")
+            .join(hash);
+        Ok(Checkpoint { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn load(&self) -> Option<State> {
+        let text = fs::read_to_string(self.dir.join("state.json")).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+
+    pub fn save_state(&self, state: &State) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        // Write then rename, like the tiles, so an interrupted write never
+        // leaves a truncated state behind.
+        let path = self.dir.join("state.json");
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string(state)?)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    pub fn tile(&self, index: usize) -> Option<RgbaImage> {
+        Some(image::open(self.tile_path(index)).ok()?.into_rgba8())
+    }
+
+    pub fn save_tile(&self, index: usize, tile: &RgbaImage) -> Result<()> {
+        // Write then rename, so an interrupted write never leaves a truncated
+        // tile behind.
+        let path = self.tile_path(index);
+        let tmp = path.with_extension("png.tmp");
+        tile.save_with_format(&tmp, image::ImageFormat::Png)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    pub fn remove(&self) -> Result<()> {
+        if self.dir.exists() {
+            fs::remove_dir_all(&self.dir)?;
+        }
+        Ok(())
+    }
+
+    fn tile_path(&self, index: usize) -> PathBuf {
+        self.dir.join(format!("tile_{index}.png"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn checkpoint(name: &str) -> Checkpoint {
+        Checkpoint {
+            dir: std::env::temp_dir().join(format!("collage-{name}-{}", std::process::id())),
+        }
+    }
+
+    #[test]
+    fn runs_are_told_apart_by_key() {
+        let a = Checkpoint::new("a").unwrap();
+        assert_eq!(a.dir(), Checkpoint::new("a").unwrap().dir());
+        assert_ne!(a.dir(), Checkpoint::new("b").unwrap().dir());
+        // The first 8 bytes of the SHA-256 of "a".
+        assert_eq!(a.dir().file_name().unwrap(), "ca978112ca1bbdca");
+    }
+
+    #[test]
+    fn saves_and_loads_the_state_and_tiles() {
+        let checkpoint = checkpoint("checkpoint");
+        assert!(checkpoint.load().is_none());
+        let state = State {
+            files: vec!["a.jpg".into(), "b.jpg".into()],
+            paths: vec!["b.jpg".into()],
+            sizes: vec![(40, 30)],
+            skipped: Vec::new(),
+            layout: Layout {
+                width: 4,
+                height: 3,
+                tiles: vec![Tile {
+                    x: 0,
+                    y: 0,
+                    width: 4,
+                    height: 3,
+                }],
+                labels: Vec::new(),
+            },
+            qr_tile: None,
+            histogram_tiles: Vec::new(),
+            seed: 9,
+        };
+        checkpoint.save_state(&state).unwrap();
+        let loaded = checkpoint.load().unwrap();
+        assert_eq!(loaded.paths, state.paths);
+        assert_eq!(loaded.sizes, state.sizes);
+        assert_eq!(loaded.layout, state.layout);
+        assert_eq!(loaded.seed, 9);
+
+        assert!(checkpoint.tile(0).is_none());
+        let tile = RgbaImage::from_pixel(4, 3, Rgba([1, 2, 3, 255]));
+        checkpoint.save_tile(0, &tile).unwrap();
+        assert_eq!(checkpoint.tile(0).unwrap(), tile);
+
+        checkpoint.remove().unwrap();
+        assert!(!checkpoint.dir().exists());
+        assert!(checkpoint.load().is_none());
+        checkpoint.remove().unwrap();
+    }
+
+    #[test]
+    fn a_damaged_state_is_ignored() {
+        let checkpoint = checkpoint("checkpoint-damaged");
+        fs::create_dir_all(checkpoint.dir()).unwrap();
+        fs::write(checkpoint.dir().join("state.json"), "{\"files\": [").unwrap();
+        assert!(checkpoint.load().is_none());
+        checkpoint.remove().unwrap();
+    }
+}
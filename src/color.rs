@@ -0,0 +1,484 @@
+use clap::ValueEnum;
+use image::{imageops, imageops::FilterType, DynamicImage, Rgba, Rgba32FImage, RgbaImage};
+use serde::Serialize;
+
+// Convert an sRGB encoded channel in [0, 1] to linear light.
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// Convert a linear light channel in [0, 1] to sRGB encoding.
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.003_130_8 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// Convert an 8 bit sRGB image to linear light. Alpha is left as is.
+pub fn to_linear(image: &RgbaImage) -> Rgba32FImage {
+    let table: Vec<f32> = (0..=255u8)
+        .map(|v| srgb_to_linear(v as f32 / 255.0))
+        .collect();
+    let mut out = Rgba32FImage::new(image.width(), image.height());
+    for (o, p) in out.pixels_mut().zip(image.pixels()) {
+        o.0 = [
+            table[p[0] as usize],
+            table[p[1] as usize],
+            table[p[2] as usize],
+            p[3] as f32 / 255.0,
+        ];
+    }
+    out
+}
+
+// Convert a linear light image back to 8 bit sRGB.
+pub fn from_linear(image: &Rgba32FImage) -> RgbaImage {
+    let encode = |c: f32| (linear_to_srgb(c.clamp(0.0, 1.0)) * 255.0).round() as u8;
+    let mut out = RgbaImage::new(image.width(), image.height());
+    for (o, p) in out.pixels_mut().zip(image.pixels()) {
+        o.0 = [
+            encode(p[0]),
+            encode(p[1]),
+            encode(p[2]),
+            (p[3].clamp(0.0, 1.0) * 255.0).round() as u8,
+        ];
+    }
+    out
+}
+
+// The filter images are resized with, from the fastest to the sharpest.
+#[derive(ValueEnum, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResizeFilter {
+    // The nearest pixel, blocky.
+    Nearest,
+    // Bilinear.
+    Triangle,
+    // Bicubic.
+    CatmullRom,
+    // Soft, with no ringing.
+    Gaussian,
+    // The sharpest, and the slowest.
+    Lanczos3,
+}
+
+impl ResizeFilter {
+    pub fn filter_type(self) -> FilterType {
+        match self {
+            ResizeFilter::Nearest => FilterType::Nearest,
+            ResizeFilter::Triangle => FilterType::Triangle,
+            ResizeFilter::CatmullRom => FilterType::CatmullRom,
+            ResizeFilter::Gaussian => FilterType::Gaussian,
+            ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+// Resize an image in linear light, which keeps fine detail from darkening.
+pub fn resize_linear(
+    image: &DynamicImage,
+    width: u32,
+    height: u32,
+    filter: ResizeFilter,
+) -> RgbaImage {
+    let linear = to_linear(&image.to_rgba8());
+    let resized = imageops::resize(&linear, width, height, filter.filter_type());
+    from_linear(&resized)
+}
+
+// Fade in the first `overlap` columns (or rows, if `vertical`) of `image`, its
+// alpha ramping up from nearly transparent, so that drawn over the image before
+// it the two cross-fade.
+pub fn fade_in(image: &mut RgbaImage, overlap: u32, vertical: bool) {
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let edge = if vertical { y } else { x };
+        if edge < overlap {
+            let weight = (edge as f32 + 0.5) / overlap as f32;
+            pixel[3] = (pixel[3] as f32 * weight).round() as u8;
+        }
+    }
+}
+
+// Scale the alpha of every pixel of `image` by `opacity`.
+pub fn fade(image: &mut RgbaImage, opacity: f32) {
+    for pixel in image.pixels_mut() {
+        pixel[3] = (pixel[3] as f32 * opacity).round() as u8;
+    }
+}
+
+// Make the pixels of `image` within `tolerance` of `key` (as a distance in
+// RGB) transparent, fading to opaque at twice the tolerance so that the edges
+// of the subject stay smooth.
+pub fn chroma_key(image: &mut RgbaImage, key: Rgba<u8>, tolerance: u32) {
+    let tolerance = tolerance.max(1) as f32;
+    for pixel in image.pixels_mut() {
+        let distance = (0..3)
+            .map(|c| (pixel[c] as f32 - key[c] as f32).powi(2))
+            .sum::<f32>()
+            .sqrt();
+        let weight = ((distance - tolerance) / tolerance).clamp(0.0, 1.0);
+        pixel[3] = (pixel[3] as f32 * weight).round() as u8;
+    }
+}
+
+// How `--auto-wb` estimates the color cast of an image.
+#[derive(ValueEnum, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum WhiteBalance {
+    // The average color of the image is taken to be gray.
+    GrayWorld,
+    // The brightest colors of the image are taken to be white.
+    Percentile,
+}
+
+// Remove the color cast of `image` by scaling its red, green and blue
+// channels, ignoring fully transparent pixels. The gains are limited, so that
+// an image that really is mostly one color isn't turned gray.
+pub fn white_balance(image: &mut RgbaImage, mode: WhiteBalance) {
+    let mut counts = [[0u64; 256]; 3];
+    let mut sums = [0u64; 3];
+    let mut n = 0u64;
+    for pixel in image.pixels().filter(|p| p[3] > 0) {
+        for c in 0..3 {
+            counts[c][pixel[c] as usize] += 1;
+            sums[c] += pixel[c] as u64;
+        }
+        n += 1;
+    }
+    if n == 0 {
+        return;
+    }
+    let levels: [f32; 3] = match mode {
+        WhiteBalance::GrayWorld => sums.map(|sum| sum as f32 / n as f32),
+        WhiteBalance::Percentile => counts.map(|count| {
+            // The value that 1% of the pixels are brighter than.
+            let mut above = 0;
+            let level = (0..256)
+                .rev()
+                .find(|&v| {
+                    above += count[v];
+                    above * 100 >= n
+                })
+                .unwrap_or(255);
+            level as f32
+        }),
+    };
+    let target = match mode {
+        WhiteBalance::GrayWorld => levels.iter().sum::<f32>() / 3.0,
+        WhiteBalance::Percentile => levels.iter().copied().fold(0.0, f32::max),
+    };
+    let gains = levels.map(|level| (target / level.max(1.0)).clamp(0.5, 2.0));
+    let tables = gains.map(|gain| {
+        let mut table = [0u8; 256];
+        for (v, t) in table.iter_mut().enumerate() {
+            *t = (v as f32 * gain).round().min(255.0) as u8;
+        }
+        table
+    });
+    for pixel in image.pixels_mut() {
+        for c in 0..3 {
+            pixel[c] = tables[c][pixel[c] as usize];
+        }
+    }
+}
+
+// What the padding of a `--contain` tile is filled with.
+#[derive(ValueEnum, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum Letterbox {
+    // Nothing, so the background of the collage shows through.
+    Background,
+    // The average color of the edges of the image next to the padding.
+    #[value(alias = "auto")]
+    Edge,
+    // The most common color of the image.
+    Dominant,
+}
+
+// The average color of the columns (if `vertical`) or rows at the edges of
+// `image`, ignoring fully transparent pixels.
+pub fn edge_color(image: &RgbaImage, vertical: bool) -> Rgba<u8> {
+    let (width, height) = image.dimensions();
+    let edges: Vec<(u32, u32)> = if vertical {
+        (0..height).flat_map(|y| [(0, y), (width - 1, y)]).collect()
+    } else {
+        (0..width).flat_map(|x| [(x, 0), (x, height - 1)]).collect()
+    };
+    average(edges.into_iter().map(|(x, y)| *image.get_pixel(x, y)))
+}
+
+// The most common color of `image`: the average of the largest group of
+// similar colors, ignoring fully transparent pixels.
+pub fn dominant_color(image: &RgbaImage) -> Rgba<u8> {
+    let bucket =
+        |p: &Rgba<u8>| (p[0] as usize >> 4) << 8 | (p[1] as usize >> 4) << 4 | p[2] as usize >> 4;
+    let mut counts = vec![0u32; 1 << 12];
+    for pixel in image.pixels().filter(|p| p[3] > 0) {
+        counts[bucket(pixel)] += 1;
+    }
+    let largest = (0..counts.len()).max_by_key(|&i| counts[i]).unwrap_or(0);
+    average(image.pixels().filter(|p| bucket(p) == largest).copied())
+}
+
+// The average of `colors` that aren't fully transparent, opaque black if
+// there are none.
+fn average(colors: impl Iterator<Item = Rgba<u8>>) -> Rgba<u8> {
+    let mut sum = [0u64; 3];
+    let mut n = 0u64;
+    for color in colors.filter(|c| c[3] > 0) {
+        for c in 0..3 {
+            sum[c] += color[c] as u64;
+        }
+        n += 1;
+    }
+    let [r, g, b] = sum.map(|s| (s / n.max(1)) as u8);
+    Rgba([r, g, b, 255])
+}
+
+// Alpha blend `top` over `bottom` at (x, y), in linear light if `linear`.
+pub fn overlay(bottom: &mut RgbaImage, top: &RgbaImage, x: u32, y: u32, linear: bool) {
+    let decode: Vec<f32> = (0..=255u8)
+        .map(|v| v as f32 / 255.0)
+        .map(|c| if linear { srgb_to_linear(c) } else { c })
+        .collect();
+    let encode = |c: f32| {
+        let c = c.clamp(0.0, 1.0);
+        let c = if linear { linear_to_srgb(c) } else { c };
+        (c * 255.0).round() as u8
+    };
+    for (tx, ty, src) in top.enumerate_pixels() {
+        let (bx, by) = (x + tx, y + ty);
+        if bx >= bottom.width() || by >= bottom.height() {
+            continue;
+        }
+        let dst = bottom.get_pixel_mut(bx, by);
+        match src[3] {
+            0 => continue,
+            255 => {
+                *dst = *src;
+                continue;
+            }
+            _ => {}
+        }
+        let a = src[3] as f32 / 255.0;
+        let b = dst[3] as f32 / 255.0;
+        let out_a = a + b * (1.0 - a);
+        for c in 0..3 {
+            let s = decode[src[c] as usize];
+            let d = decode[dst[c] as usize];
+            dst[c] = encode((s * a + d * b * (1.0 - a)) / out_a);
+        }
+        dst[3] = (out_a * 255.0).round() as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srgb_round_trips_through_linear() {
+        for v in 0..=255u8 {
+            let c = v as f32 / 255.0;
+            assert!((linear_to_srgb(srgb_to_linear(c)) - c).abs() < 1e-5);
+        }
+        assert_eq!(srgb_to_linear(0.0), 0.0);
+        assert!((srgb_to_linear(1.0) - 1.0).abs() < 1e-6);
+        assert!((srgb_to_linear(0.5) - 0.214).abs() < 1e-3);
+    }
+
+    #[test]
+    fn images_round_trip_through_linear() {
+        let image = RgbaImage::from_fn(16, 16, |x, y| Rgba([x as u8 * 16, y as u8 * 16, 7, 100]));
+        assert_eq!(from_linear(&to_linear(&image)), image);
+    }
+
+    #[test]
+    fn resizing_in_linear_light_keeps_the_brightness() {
+        // A checkerboard of black and white averages to half the light, which
+        // is 188 in sRGB, not 128.
+        let checkerboard = RgbaImage::from_fn(16, 16, |x, y| {
+            let v = if (x + y) % 2 == 0 { 255 } else { 0 };
+            Rgba([v, v, v, 255])
+        });
+        let image = DynamicImage::ImageRgba8(checkerboard);
+        let resized = resize_linear(&image, 1, 1, ResizeFilter::Triangle);
+        let p = resized.get_pixel(0, 0);
+        assert!(p[0].abs_diff(188) <= 1, "{p:?}");
+        assert_eq!(p[3], 255);
+    }
+
+    #[test]
+    fn overlay_copies_opaque_and_skips_transparent_pixels() {
+        let mut bottom = RgbaImage::from_pixel(2, 1, Rgba([1, 2, 3, 255]));
+        let top = RgbaImage::from_fn(2, 1, |x, _| {
+            Rgba([200, 100, 50, if x == 0 { 255 } else { 0 }])
+        });
+        overlay(&mut bottom, &top, 0, 0, false);
+        assert_eq!(bottom.get_pixel(0, 0), &Rgba([200, 100, 50, 255]));
+        assert_eq!(bottom.get_pixel(1, 0), &Rgba([1, 2, 3, 255]));
+    }
+
+    #[test]
+    fn overlay_blends_in_linear_light() {
+        let black = RgbaImage::from_pixel(1, 1, Rgba([0, 0, 0, 255]));
+        let half_white = RgbaImage::from_pixel(1, 1, Rgba([255, 255, 255, 128]));
+
+        let mut gamma = black.clone();
+        overlay(&mut gamma, &half_white, 0, 0, false);
+        assert_eq!(gamma.get_pixel(0, 0), &Rgba([128, 128, 128, 255]));
+
+        let mut linear = black;
+        overlay(&mut linear, &half_white, 0, 0, true);
+        let p = linear.get_pixel(0, 0);
+        assert!(p[0].abs_diff(188) <= 1, "{p:?}");
+        assert_eq!(p[3], 255);
+    }
+
+    #[test]
+    fn overlay_over_transparency_keeps_the_color() {
+        let mut bottom = RgbaImage::new(1, 1);
+        let top = RgbaImage::from_pixel(1, 1, Rgba([40, 80, 120, 128]));
+        overlay(&mut bottom, &top, 0, 0, true);
+        assert_eq!(bottom.get_pixel(0, 0), &Rgba([40, 80, 120, 128]));
+    }
+
+    #[test]
+    fn overlay_clips_to_the_bottom() {
+        let mut bottom = RgbaImage::new(3, 3);
+        let top = RgbaImage::from_pixel(2, 2, Rgba([9, 9, 9, 255]));
+        overlay(&mut bottom, &top, 2, 2, false);
+        let covered: Vec<_> = bottom
+            .enumerate_pixels()
+            .filter(|(_, _, p)| p[3] > 0)
+            .map(|(x, y, _)| (x, y))
+            .collect();
+        assert_eq!(covered, [(2, 2)]);
+    }
+
+    #[test]
+    fn fade_in_ramps_the_alpha_over_the_overlap() {
+        let mut image = RgbaImage::from_pixel(4, 2, Rgba([10, 20, 30, 200]));
+        fade_in(&mut image, 2, false);
+        let alphas: Vec<u8> = (0..4).map(|x| image.get_pixel(x, 1)[3]).collect();
+        assert_eq!(alphas, [50, 150, 200, 200]);
+        assert_eq!(image.get_pixel(0, 0).0[..3], [10, 20, 30]);
+
+        let mut image = RgbaImage::from_pixel(2, 4, Rgba([0, 0, 0, 255]));
+        fade_in(&mut image, 4, true);
+        let alphas: Vec<u8> = (0..4).map(|y| image.get_pixel(1, y)[3]).collect();
+        assert_eq!(alphas, [32, 96, 159, 223]);
+    }
+
+    #[test]
+    fn fade_scales_the_alpha() {
+        let mut image = RgbaImage::from_fn(2, 1, |x, _| Rgba([10, 20, 30, [255, 100][x as usize]]));
+        fade(&mut image, 0.5);
+        assert_eq!(image.get_pixel(0, 0), &Rgba([10, 20, 30, 128]));
+        assert_eq!(image.get_pixel(1, 0), &Rgba([10, 20, 30, 50]));
+        fade(&mut image, 0.0);
+        assert!(image.pixels().all(|p| p[3] == 0));
+    }
+
+    #[test]
+    fn chroma_key_fades_out_colors_near_the_key() {
+        let green = Rgba([0, 255, 0, 255]);
+        // At the key, within the tolerance, halfway to twice the tolerance,
+        // and past it.
+        let colors = [[0, 255, 0], [0, 235, 0], [0, 195, 0], [0, 100, 0]];
+        let mut image = RgbaImage::from_fn(4, 1, |x, _| {
+            let [r, g, b] = colors[x as usize];
+            Rgba([r, g, b, 255])
+        });
+        chroma_key(&mut image, green, 40);
+        let alphas: Vec<u8> = image.pixels().map(|p| p[3]).collect();
+        assert_eq!(alphas, [0, 0, 128, 255]);
+        assert_eq!(image.get_pixel(3, 0).0[..3], [0, 100, 0]);
+    }
+
+    #[test]
+    fn chroma_key_keeps_partial_transparency() {
+        let mut image = RgbaImage::from_pixel(1, 1, Rgba([0, 195, 0, 100]));
+        chroma_key(&mut image, Rgba([0, 255, 0, 255]), 40);
+        assert_eq!(image.get_pixel(0, 0)[3], 50);
+    }
+
+    #[test]
+    fn gray_world_makes_the_average_gray() {
+        let mut image = RgbaImage::from_pixel(4, 4, Rgba([150, 100, 50, 255]));
+        // Fully transparent pixels don't count.
+        image.put_pixel(0, 0, Rgba([0, 0, 255, 0]));
+        white_balance(&mut image, WhiteBalance::GrayWorld);
+        assert_eq!(image.get_pixel(1, 1), &Rgba([100, 100, 100, 255]));
+    }
+
+    #[test]
+    fn white_balance_limits_the_gains() {
+        let mut image = RgbaImage::from_pixel(2, 2, Rgba([250, 100, 10, 255]));
+        white_balance(&mut image, WhiteBalance::GrayWorld);
+        assert_eq!(image.get_pixel(0, 0), &Rgba([125, 120, 20, 255]));
+    }
+
+    #[test]
+    fn percentile_makes_the_highlights_white() {
+        let mut image = RgbaImage::from_pixel(10, 10, Rgba([40, 40, 40, 255]));
+        for x in 0..10 {
+            image.put_pixel(x, 0, Rgba([240, 200, 160, 255]));
+        }
+        white_balance(&mut image, WhiteBalance::Percentile);
+        assert_eq!(image.get_pixel(0, 0), &Rgba([240, 240, 240, 255]));
+        assert_eq!(image.get_pixel(0, 5), &Rgba([40, 48, 60, 255]));
+    }
+
+    #[test]
+    fn white_balance_leaves_transparent_images() {
+        let mut image = RgbaImage::from_pixel(2, 2, Rgba([200, 10, 10, 0]));
+        let before = image.clone();
+        white_balance(&mut image, WhiteBalance::Percentile);
+        assert_eq!(image, before);
+    }
+
+    #[test]
+    fn edge_color_averages_the_edges_next_to_the_padding() {
+        // Red on the left and right, blue on the top and bottom rows between.
+        let image = RgbaImage::from_fn(4, 4, |x, _| {
+            if x == 0 || x == 3 {
+                Rgba([200, 0, 0, 255])
+            } else {
+                Rgba([0, 0, 200, 255])
+            }
+        });
+        assert_eq!(edge_color(&image, true), Rgba([200, 0, 0, 255]));
+        assert_eq!(edge_color(&image, false), Rgba([100, 0, 100, 255]));
+    }
+
+    #[test]
+    fn dominant_color_is_the_largest_group() {
+        // Fully transparent pixels don't count, even though there are more.
+        let mut image = RgbaImage::from_fn(4, 8, |_, y| {
+            if y < 4 {
+                Rgba([10, 200, 20, 255])
+            } else {
+                Rgba([250, 0, 0, 0])
+            }
+        });
+        image.put_pixel(0, 0, Rgba([12, 202, 22, 255]));
+        for y in 0..4 {
+            image.put_pixel(3, y, Rgba([250, 0, 0, 255]));
+        }
+        assert_eq!(dominant_color(&image), Rgba([10, 200, 20, 255]));
+    }
+
+    #[test]
+    fn colors_of_transparent_images_are_black() {
+        let image = RgbaImage::new(2, 2);
+        assert_eq!(edge_color(&image, false), Rgba([0, 0, 0, 255]));
+        assert_eq!(dominant_color(&image), Rgba([0, 0, 0, 255]));
+    }
+}
@@ -0,0 +1,157 @@
+use anyhow::{ensure, Result};
+use clap::ValueEnum;
+use image::RgbaImage;
+use log::info;
+use serde::Serialize;
+use std::{collections::HashSet, path::PathBuf};
+use walkdir::WalkDir;
+
+use crate::sort;
+
+// How the two images of a before/after pair are shown.
+#[derive(ValueEnum, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum CompareStyle {
+    // Next to each other, before on the left.
+    SideBySide,
+    // In the same tile, split along the diagonal with before in the top left.
+    Diagonal,
+}
+
+// Pair the files directly in `before` with the files of the same name in
+// `after`, in the natural order of the names. Returns the paths interleaved,
+// before then after.
+pub fn pairs(before: &PathBuf, after: &PathBuf) -> Result<Vec<PathBuf>> {
+    let files = |dir: &PathBuf| -> Vec<PathBuf> {
+        WalkDir::new(dir)
+            .min_depth(1)
+            .max_depth(1)
+            .into_iter()
+            .flatten()
+            .filter(|d| d.file_type().is_file())
+            .map(|d| d.path().to_path_buf())
+            .collect()
+    };
+    let mut befores = files(before);
+    befores.sort_by(|a, b| sort::natural_cmp(a, b));
+    let afters: HashSet<_> = files(after)
+        .into_iter()
+        .filter_map(|path| path.file_name().map(|name| name.to_os_string()))
+        .collect();
+
+    let mut paths = Vec::new();
+    for path in befores {
+        match path.file_name().filter(|name| afters.contains(*name)) {
+            Some(name) => {
+                let partner = after.join(name);
+                paths.push(path);
+                paths.push(partner);
+            }
+            None => info!("{} has no match in {}.", path.display(), after.display()),
+        }
+    }
+    ensure!(
+        !paths.is_empty(),
+        "No file names in {} match a file name in {}",
+        before.display(),
+        after.display()
+    );
+    Ok(paths)
+}
+
+// Drop the pairs of `inputs` of which either image couldn't be decoded, given
+// the interleaved `pairs` they were decoded from.
+pub fn complete_pairs<T>(inputs: Vec<(PathBuf, T)>, pairs: &[PathBuf]) -> Vec<(PathBuf, T)> {
+    let decoded: HashSet<&PathBuf> = inputs.iter().map(|(path, _)| path).collect();
+    let complete: HashSet<PathBuf> = pairs
+        .chunks(2)
+        .filter(|pair| pair.iter().all(|path| decoded.contains(path)))
+        .flatten()
+        .cloned()
+        .collect();
+    inputs
+        .into_iter()
+        .filter(|(path, _)| complete.contains(path))
+        .collect()
+}
+
+// Hide the top left half of `image`, above the diagonal from its bottom left
+// to its top right corner, so the image below shows through.
+pub fn mask_diagonal(image: &mut RgbaImage) {
+    let (w, h) = (image.width() as u64, image.height() as u64);
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        if (x as u64 + 1) * h + (y as u64 + 1) * w <= w * h {
+            pixel[3] = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+    use std::fs;
+
+    #[test]
+    fn pairs_files_of_the_same_name() {
+        let dir = std::env::temp_dir().join(format!("collage-compare-{}", std::process::id()));
+        let (before, after) = (dir.join("before"), dir.join("after"));
+        fs::create_dir_all(before.join("nested")).unwrap();
+        fs::create_dir_all(&after).unwrap();
+        for name in [
+            "img10.jpg",
+            "img2.jpg",
+            "only-before.jpg",
+            "nested/img3.jpg",
+        ] {
+            fs::write(before.join(name), b"").unwrap();
+        }
+        for name in ["img2.jpg", "img10.jpg", "only-after.jpg"] {
+            fs::write(after.join(name), b"").unwrap();
+        }
+        let paths = pairs(&before, &after).unwrap();
+        assert_eq!(
+            paths,
+            [
+                before.join("img2.jpg"),
+                after.join("img2.jpg"),
+                before.join("img10.jpg"),
+                after.join("img10.jpg"),
+            ]
+        );
+        assert!(pairs(&before, &before.join("nested")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn drops_pairs_missing_an_image() {
+        let paths: Vec<PathBuf> = ["a1", "b1", "a2", "b2", "a3", "b3"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        let inputs: Vec<(PathBuf, usize)> = [0, 1, 2, 5]
+            .iter()
+            .map(|&i| (paths[i].clone(), i))
+            .collect();
+        let complete = complete_pairs(inputs, &paths);
+        assert_eq!(complete, [(paths[0].clone(), 0), (paths[1].clone(), 1)]);
+    }
+
+    #[test]
+    fn mask_diagonal_hides_the_top_left_half() {
+        let mut image = RgbaImage::from_pixel(4, 4, Rgba([1, 1, 1, 255]));
+        mask_diagonal(&mut image);
+        let hidden: Vec<Vec<bool>> = (0..4)
+            .map(|y| (0..4).map(|x| image.get_pixel(x, y)[3] == 0).collect())
+            .collect();
+        assert_eq!(
+            hidden,
+            [
+                [true, true, true, false],
+                [true, true, false, false],
+                [true, false, false, false],
+                [false, false, false, false],
+            ]
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use anyhow::{bail, Context, Result};
+use clap::{ArgAction, Command, CommandFactory};
+use directories::ProjectDirs;
+use std::{fs, path::PathBuf};
+use toml::{Table, Value};
+
+use crate::App;
+
+// The command line interface with every option also read from a `COLLAGE_*`
+// environment variable, e.g. `--log-format` from `COLLAGE_LOG_FORMAT`. The
+// options of a subcommand are read from variables named after it too, e.g.
+// `daemon --every` from `COLLAGE_DAEMON_EVERY`.
+pub fn command() -> Command {
+    with_env(App::command(), "COLLAGE")
+}
+
+fn with_env(command: Command, prefix: &str) -> Command {
+    let names: Vec<String> = command
+        .get_subcommands()
+        .map(|subcommand| subcommand.get_name().to_string())
+        .collect();
+    let command = command.mut_args(
+        |arg| match arg.get_long().map(|long| env_var(prefix, long)) {
+            Some(var) => arg.env(var),
+            None => arg,
+        },
+    );
+    names.iter().fold(command, |command, name| {
+        command.mut_subcommand(name, |subcommand| {
+            with_env(subcommand, &env_var(prefix, name))
+        })
+    })
+}
+
+fn env_var(prefix: &str, name: &str) -> String {
+    format!("{prefix}_{}", name.to_uppercase().replace('-', "_"))
+}
+
+// The config file used when none is given: `collage/config.toml` in the
+// platform's config directory, if it exists.
+pub fn default_config_path() -> Option<PathBuf> {
+    let path = ProjectDirs::from("", "", "collage")?
+        .config_dir()
+        .join("config.toml");
+    path.exists().then_some(path)
+}
+
+// Use the values of a TOML config file as the defaults of `command`. The keys
+// are the long names of the options, e.g. `spacing = 10` or
+// `log-format = "json"`, so the command line and environment variables still
+// take precedence over the file. Options given more than once on the command
+// line take an array, e.g. `image-z = ["hero.jpg=1", "logo.png=-1"]`.
+pub fn apply_config(mut command: Command, path: &PathBuf) -> Result<Command> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file {}", path.display()))?;
+    let table: Table = toml::from_str(&text)
+        .with_context(|| format!("Failed to parse config file {}", path.display()))?;
+
+    for (key, value) in table {
+        let long = key.replace('_', "-");
+        let Some(arg) = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(long.as_str()))
+        else {
+            bail!("Unknown option `{key}` in config file {}", path.display());
+        };
+        let id = arg.get_id().clone();
+        let multiple = matches!(arg.get_action(), ArgAction::Append)
+            || arg.get_num_args().is_some_and(|n| n.max_values() > 1);
+        // Defaults don't count as given for clap, so the image directory is
+        // no longer required once the file gives what stands in for it.
+        if ["compare", "album"].contains(&id.as_str()) {
+            command = command.mut_arg("image_dir", |arg| {
+                arg.required_unless_present(clap::builder::Resettable::Reset)
+            });
+        }
+        match value {
+            Value::Array(values) if multiple => {
+                let values = values
+                    .into_iter()
+                    .map(|v| config_value(&key, &v))
+                    .collect::<Result<Vec<_>>>()?;
+                command = command.mut_arg(id, |arg| arg.default_values(values));
+            }
+            Value::Array(_) => bail!("`{key}` in config file takes a single value, not an array"),
+            value => {
+                let value = config_value(&key, &value)?;
+                command = command.mut_arg(id, |arg| arg.default_value(value));
+            }
+        }
+    }
+    Ok(command)
+}
+
+fn config_value(key: &str, value: &Value) -> Result<String> {
+    value_to_arg(value)
+        .with_context(|| format!("Unsupported value for `{key}` in config file: {value}"))
+}
+
+// A single TOML value of an option, as on the command line. Tables, dates and
+// arrays have no such form.
+pub fn value_to_arg(value: &Value) -> Option<String> {
+    Some(match value {
+        Value::String(s) => s.clone(),
+        Value::Integer(i) => i.to_string(),
+        Value::Float(f) => f.to_string(),
+        Value::Boolean(b) => b.to_string(),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env_of(command: &Command, long: &str) -> Option<String> {
+        command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(long))
+            .and_then(|arg| arg.get_env())
+            .map(|var| var.to_string_lossy().into_owned())
+    }
+
+    #[test]
+    fn command_reads_every_option_from_the_environment() {
+        let command = command();
+        assert_eq!(
+            env_of(&command, "log-format").as_deref(),
+            Some("COLLAGE_LOG_FORMAT")
+        );
+        let daemon = command.find_subcommand("daemon").unwrap();
+        assert_eq!(
+            env_of(daemon, "every").as_deref(),
+            Some("COLLAGE_DAEMON_EVERY")
+        );
+        for subcommand in command.get_subcommands() {
+            for arg in subcommand.get_arguments() {
+                if let Some(long) = arg.get_long() {
+                    assert!(
+                        arg.get_env().is_some(),
+                        "{} --{long}",
+                        subcommand.get_name()
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn value_to_arg_of_scalars() {
+        assert_eq!(value_to_arg(&Value::from("a b")).as_deref(), Some("a b"));
+        assert_eq!(value_to_arg(&Value::from(10)).as_deref(), Some("10"));
+        assert_eq!(value_to_arg(&Value::from(0.5)).as_deref(), Some("0.5"));
+        assert_eq!(value_to_arg(&Value::from(true)).as_deref(), Some("true"));
+        assert_eq!(value_to_arg(&Value::Array(Vec::new())), None);
+        assert_eq!(value_to_arg(&Value::Table(Table::new())), None);
+    }
+}
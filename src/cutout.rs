@@ -0,0 +1,85 @@
+use anyhow::{ensure, Context, Result};
+use image::{imageops, imageops::FilterType, ImageFormat, RgbaImage};
+use std::{
+    io::{Cursor, Write},
+    path::Path,
+    process::{Command, Stdio},
+    thread,
+};
+
+// Cut the subject out of `image` by running an external segmentation
+// `program` with `args`, e.g. `rembg` with `i - -`. The program receives the
+// image as a PNG on stdin and writes it back as a PNG on stdout with the
+// background transparent. Only the alpha channel of its output is used, so
+// the colors of the image are kept as they are.
+pub fn cutout(image: &RgbaImage, program: &Path, args: &[String]) -> Result<RgbaImage> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to start cutout program {}", program.display()))?;
+
+    let mut png = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    let mut stdin = child
+        .stdin
+        .take()
+        .context("Failed to open cutout program stdin")?;
+    // The image is written from another thread, since a program that starts
+    // writing before it has read all of it would otherwise block forever.
+    let writer = thread::spawn(move || stdin.write_all(&png));
+
+    let output = child.wait_with_output()?;
+    // A program that exits without reading all of its input is only an error
+    // if it fails.
+    let _ = writer.join();
+    ensure!(
+        output.status.success(),
+        "Cutout program {} failed with {}",
+        program.display(),
+        output.status
+    );
+    let mask = image::load_from_memory(&output.stdout)
+        .context("The cutout program returned an invalid image")?
+        .into_rgba8();
+    let mask = if mask.dimensions() == image.dimensions() {
+        mask
+    } else {
+        imageops::resize(&mask, image.width(), image.height(), FilterType::Triangle)
+    };
+
+    let mut out = image.clone();
+    for (pixel, masked) in out.pixels_mut().zip(mask.pixels()) {
+        pixel[3] = (pixel[3] as u32 * masked[3] as u32 / 255) as u8;
+    }
+    Ok(out)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn image() -> RgbaImage {
+        RgbaImage::from_fn(8, 4, |x, y| Rgba([x as u8 * 30, y as u8 * 60, 90, 200]))
+    }
+
+    #[test]
+    fn cutout_keeps_each_argument_whole() {
+        // A mask that is the image itself leaves its alpha squared.
+        let args = ["-c".to_string(), "cat -".to_string()];
+        let out = cutout(&image(), Path::new("sh"), &args).unwrap();
+        for (got, want) in out.pixels().zip(image().pixels()) {
+            assert_eq!(got.0[..3], want.0[..3]);
+            assert_eq!(got[3], (200 * 200 / 255) as u8);
+        }
+    }
+
+    #[test]
+    fn cutout_fails_with_its_program() {
+        assert!(cutout(&image(), Path::new("false"), &[]).is_err());
+        assert!(cutout(&image(), Path::new("true"), &[]).is_err());
+        assert!(cutout(&image(), Path::new("/no/such/program"), &[]).is_err());
+    }
+}
@@ -0,0 +1,125 @@
+use image::{Rgba, RgbaImage};
+use std::path::PathBuf;
+
+use crate::{layout::Layout, text};
+
+const MARGIN: Rgba<u8> = Rgba([255, 0, 255, 255]);
+const GUTTER: Rgba<u8> = Rgba([0, 255, 255, 255]);
+const BOUNDARY: Rgba<u8> = Rgba([255, 0, 0, 255]);
+
+// Draw the structure of `layout` over the collage: the margins tinted magenta,
+// the gutters between the tiles cyan, the tile boundaries red and the index
+// and file name of each tile's image in its top left corner.
+pub fn draw(
+    image: &mut RgbaImage,
+    layout: &Layout,
+    paths: &[PathBuf],
+    left_margin: u32,
+    top_margin: u32,
+) {
+    let (width, height) = image.dimensions();
+    let mut covered = vec![false; width as usize * height as usize];
+    for tile in &layout.tiles {
+        for y in tile.y..(tile.y + tile.height).min(height) {
+            for x in tile.x..(tile.x + tile.width).min(width) {
+                covered[(y * width + x) as usize] = true;
+            }
+        }
+    }
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        if covered[(y * width + x) as usize] {
+            continue;
+        }
+        let margin = x < left_margin
+            || x >= width.saturating_sub(left_margin)
+            || y < top_margin
+            || y >= height.saturating_sub(top_margin);
+        tint(pixel, if margin { MARGIN } else { GUTTER });
+    }
+
+    for (i, tile) in layout.tiles.iter().enumerate() {
+        if tile.x >= width || tile.y >= height {
+            continue;
+        }
+        let right = (tile.x + tile.width).min(width) - 1;
+        let bottom = (tile.y + tile.height).min(height) - 1;
+        for x in tile.x..=right {
+            image.put_pixel(x, tile.y, BOUNDARY);
+            image.put_pixel(x, bottom, BOUNDARY);
+        }
+        for y in tile.y..=bottom {
+            image.put_pixel(tile.x, y, BOUNDARY);
+            image.put_pixel(right, y, BOUNDARY);
+        }
+
+        let name = paths
+            .get(i)
+            .and_then(|path| path.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let label = format!("{i} {name}");
+        let label: String = label
+            .chars()
+            .take((tile.width.saturating_sub(4) / text::GLYPH_SIZE) as usize)
+            .collect();
+        let box_width = text::width(&label, 1) + 2;
+        for y in tile.y + 1..(tile.y + 1 + text::GLYPH_SIZE + 2).min(bottom) {
+            for x in tile.x + 1..(tile.x + 1 + box_width).min(right) {
+                image.put_pixel(x, y, Rgba([0, 0, 0, 255]));
+            }
+        }
+        text::draw(
+            image,
+            &label,
+            tile.x + 2,
+            tile.y + 2,
+            1,
+            Rgba([255, 255, 255, 255]),
+        );
+    }
+}
+
+// Blend `color` halfway into `pixel`.
+fn tint(pixel: &mut Rgba<u8>, color: Rgba<u8>) {
+    for c in 0..3 {
+        pixel[c] = ((pixel[c] as u16 + color[c] as u16) / 2) as u8;
+    }
+    pixel[3] = 255;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::Tile;
+
+    #[test]
+    fn draws_margins_gutters_boundaries_and_labels() {
+        let gray = Rgba([100, 100, 100, 255]);
+        let mut image = RgbaImage::from_pixel(40, 20, gray);
+        let layout = Layout {
+            width: 40,
+            height: 20,
+            tiles: vec![Tile {
+                x: 2,
+                y: 2,
+                width: 30,
+                height: 16,
+            }],
+            labels: Vec::new(),
+        };
+        draw(&mut image, &layout, &[PathBuf::from("dir/a.png")], 2, 2);
+
+        assert_eq!(image.get_pixel(0, 0), &Rgba([177, 50, 177, 255]));
+        assert_eq!(image.get_pixel(35, 10), &Rgba([50, 177, 177, 255]));
+        for (x, y) in [(2, 2), (31, 10), (20, 17)] {
+            assert_eq!(image.get_pixel(x, y), &BOUNDARY);
+        }
+        assert_eq!(image.get_pixel(20, 14), &gray);
+        // The label "0 a.png" is cut to the 3 characters that fit, on black.
+        assert_eq!(image.get_pixel(3, 3), &Rgba([0, 0, 0, 255]));
+        let label = image::imageops::crop_imm(&image, 4, 4, 24, 8).to_image();
+        let mut expected = RgbaImage::from_pixel(24, 8, Rgba([0, 0, 0, 255]));
+        text::draw(&mut expected, "0 a", 0, 0, 1, Rgba([255, 255, 255, 255]));
+        assert_eq!(label, expected);
+    }
+}
@@ -0,0 +1,421 @@
+use anyhow::{anyhow, Context, Result};
+use image::{
+    codecs::{jpeg::JpegDecoder, png::PngDecoder, tiff::TiffDecoder, webp::WebPDecoder},
+    io::{Limits, Reader},
+    DynamicImage, ImageDecoder, ImageError, ImageFormat, ImageResult, RgbImage,
+};
+use jpeg_decoder::PixelFormat;
+use log::{debug, warn};
+use qcms::{DataType, Intent, Profile, Transform};
+use std::{
+    fs::{self, File},
+    io::{BufReader, Cursor},
+    path::Path,
+};
+
+// Open and decode an image, handling the inputs the `image` crate gets wrong
+// and converting images with an embedded color profile to sRGB. Images larger
+// than `limits` are refused before they are decoded.
+pub fn open(path: &Path, limits: &Limits) -> Result<DynamicImage> {
+    let format = ImageFormat::from_path(path)?;
+    let data = fs::read(path)?;
+    if format == ImageFormat::Jpeg {
+        if let Some(img) = decode_cmyk_jpeg(&data, limits)? {
+            return Ok(img);
+        }
+    }
+    let (img, icc) = decode_with_profile(&data, format, limits).map_err(over_limits)?;
+    Ok(match icc {
+        Some(icc) => to_srgb(img, &icc, path),
+        None => img,
+    })
+}
+
+// The dimensions of an image and the bytes it takes decoded, read from its
+// header alone. Images larger than `limits` are refused like by `open`.
+pub fn header(path: &Path, limits: &Limits) -> Result<(u32, u32, u64)> {
+    fn probe<'a>(decoder: impl ImageDecoder<'a>) -> ImageResult<(u32, u32, u64)> {
+        let (width, height) = decoder.dimensions();
+        Ok((width, height, decoder.total_bytes()))
+    }
+    let format = ImageFormat::from_path(path)?;
+    let reader = BufReader::new(File::open(path)?);
+    let (width, height, bytes) = match format {
+        ImageFormat::Jpeg => probe(JpegDecoder::new(reader)?)?,
+        ImageFormat::Png => probe(PngDecoder::new(reader)?)?,
+        ImageFormat::WebP => probe(WebPDecoder::new(reader)?)?,
+        ImageFormat::Tiff => probe(TiffDecoder::new(reader)?)?,
+        _ => {
+            let (width, height) = Reader::with_format(reader, format).into_dimensions()?;
+            // HDR images decode to 32 bit floats.
+            let bytes_per_pixel = match format {
+                ImageFormat::Hdr | ImageFormat::OpenExr => 16,
+                _ => 4,
+            };
+            (
+                width,
+                height,
+                width as u64 * height as u64 * bytes_per_pixel,
+            )
+        }
+    };
+    limits
+        .check_dimensions(width, height)
+        .and_then(|_| limits.clone().reserve(bytes))
+        .map_err(over_limits)?;
+    Ok((width, height, bytes))
+}
+
+// Decode an image along with its embedded ICC profile, for the formats that
+// can carry one.
+fn decode_with_profile(
+    data: &[u8],
+    format: ImageFormat,
+    limits: &Limits,
+) -> ImageResult<(DynamicImage, Option<Vec<u8>>)> {
+    fn decode<'a>(
+        mut decoder: impl ImageDecoder<'a>,
+        limits: &Limits,
+    ) -> ImageResult<(DynamicImage, Option<Vec<u8>>)> {
+        // The decoders only check the dimensions, so the size of the decoded
+        // image is checked here.
+        decoder.set_limits(limits.clone())?;
+        limits.clone().reserve(decoder.total_bytes())?;
+        let icc = decoder.icc_profile();
+        Ok((DynamicImage::from_decoder(decoder)?, icc))
+    }
+    match format {
+        ImageFormat::Jpeg => decode(JpegDecoder::new(Cursor::new(data))?, limits),
+        ImageFormat::Png => decode(PngDecoder::new(Cursor::new(data))?, limits),
+        ImageFormat::WebP => decode(WebPDecoder::new(Cursor::new(data))?, limits),
+        ImageFormat::Tiff => decode(TiffDecoder::new(Cursor::new(data))?, limits),
+        _ => {
+            let mut reader = Reader::with_format(Cursor::new(data), format);
+            reader.limits(limits.clone());
+            Ok((reader.decode()?, None))
+        }
+    }
+}
+
+// Say which options a decoding error over the limits is due to.
+fn over_limits(e: ImageError) -> anyhow::Error {
+    match e {
+        ImageError::Limits(_) => {
+            anyhow!("{e} (see --max-image-dimension and --max-decode-size)")
+        }
+        e => e.into(),
+    }
+}
+
+// Convert an image from its embedded RGB profile (e.g. Adobe RGB or Display
+// P3) to sRGB, the working space, so its colors match the other images.
+// Images whose profile can't be used are left as they are.
+fn to_srgb(img: DynamicImage, icc: &[u8], path: &Path) -> DynamicImage {
+    if !img.color().has_color() {
+        return img;
+    }
+    let Some(profile) = Profile::new_from_slice(icc, false) else {
+        warn!("Ignoring the invalid color profile of {}.", path.display());
+        return img;
+    };
+    if profile.is_sRGB() {
+        return img;
+    }
+    let srgb = Profile::new_sRGB();
+    let Some(transform) = Transform::new(&profile, &srgb, DataType::RGBA8, Intent::Perceptual)
+    else {
+        debug!("Ignoring the non RGB color profile of {}.", path.display());
+        return img;
+    };
+    let mut rgba = img.into_rgba8();
+    transform.apply(&mut rgba);
+    DynamicImage::ImageRgba8(rgba)
+}
+
+// Decode a CMYK or YCCK JPEG to RGB. Returns `None` for any other JPEG.
+//
+// `jpeg-decoder` always assumes Adobe style inverted CMYK, so CMYK JPEGs
+// without an Adobe segment, as written by some scanner and print software,
+// come out with inverted colors. Here we look at the Adobe segment ourselves.
+fn decode_cmyk_jpeg(data: &[u8], limits: &Limits) -> Result<Option<DynamicImage>> {
+    let mut decoder = jpeg_decoder::Decoder::new(data);
+    decoder.read_info().context("Failed to read JPEG header")?;
+    let info = decoder.info().context("Missing JPEG header")?;
+    if info.pixel_format != PixelFormat::CMYK32 {
+        return Ok(None);
+    }
+    let (width, height) = (info.width as u32, info.height as u32);
+    limits.check_dimensions(width, height)?;
+    limits.clone().reserve(width as u64 * height as u64 * 4)?;
+    let pixels = decoder.decode().context("Failed to decode CMYK JPEG")?;
+
+    // The decoded channels are the amount of ink for Adobe CMYK and YCCK
+    // files, and the amount of light (255 - ink) otherwise.
+    let adobe = has_adobe_segment(data);
+    let mut rgb = Vec::with_capacity(pixels.len() / 4 * 3);
+    for p in pixels.chunks_exact(4) {
+        let (c, m, y, k) = if adobe {
+            (255 - p[0], 255 - p[1], 255 - p[2], 255 - p[3])
+        } else {
+            (p[0], p[1], p[2], p[3])
+        };
+        let k = k as u16;
+        rgb.push((c as u16 * k / 255) as u8);
+        rgb.push((m as u16 * k / 255) as u8);
+        rgb.push((y as u16 * k / 255) as u8);
+    }
+    let img = RgbImage::from_raw(info.width as u32, info.height as u32, rgb)
+        .context("Invalid CMYK JPEG dimensions")?;
+    Ok(Some(DynamicImage::ImageRgb8(img)))
+}
+
+// Scan the JPEG markers up to the start of the scan for an Adobe APP14
+// segment.
+fn has_adobe_segment(data: &[u8]) -> bool {
+    let mut i = 2;
+    while i + 4 <= data.len() {
+        if data[i] != 0xFF {
+            break;
+        }
+        let marker = data[i + 1];
+        // Start of scan: the entropy coded data follows.
+        if marker == 0xDA {
+            break;
+        }
+        let len = u16::from_be_bytes([data[i + 2], data[i + 3]]) as usize;
+        let segment = &data[(i + 4).min(data.len())..(i + 2 + len).min(data.len())];
+        if marker == 0xEE && segment.starts_with(b"Adobe") {
+            return true;
+        }
+        i += 2 + len;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{codecs::jpeg::JpegEncoder, Rgb, RgbImage};
+
+    // Appends bits to entropy coded JPEG data, stuffing a zero after every
+    // 0xFF byte.
+    #[derive(Default)]
+    struct Bits {
+        bytes: Vec<u8>,
+        current: u32,
+        count: u32,
+    }
+
+    impl Bits {
+        fn push(&mut self, value: u32, count: u32) {
+            for i in (0..count).rev() {
+                self.current = self.current << 1 | (value >> i & 1);
+                self.count += 1;
+                if self.count == 8 {
+                    self.bytes.push(self.current as u8);
+                    if self.current == 0xff {
+                        self.bytes.push(0);
+                    }
+                    (self.current, self.count) = (0, 0);
+                }
+            }
+        }
+
+        fn finish(mut self) -> Vec<u8> {
+            while self.count != 0 {
+                self.push(1, 1);
+            }
+            self.bytes
+        }
+    }
+
+    fn segment(marker: u8, payload: &[u8]) -> Vec<u8> {
+        let mut segment = vec![0xff, marker];
+        segment.extend((payload.len() as u16 + 2).to_be_bytes());
+        segment.extend(payload);
+        segment
+    }
+
+    // An 8x8 JPEG of four components, each the flat `values`, with an Adobe
+    // segment of the color `transform` if given: 0 for CMYK and 2 for YCCK.
+    // Only the DC coefficients are coded, with a quantization of 1, so the
+    // values decode exactly.
+    fn four_component_jpeg(values: [u8; 4], transform: Option<u8>) -> Vec<u8> {
+        let mut jpeg = vec![0xff, 0xd8];
+        if let Some(transform) = transform {
+            let mut adobe = b"Adobe".to_vec();
+            adobe.extend([0, 100, 0, 0, 0, 0, transform]);
+            jpeg.extend(segment(0xee, &adobe));
+        }
+        jpeg.extend(segment(0xdb, &[[0].as_slice(), &[1; 64]].concat()));
+        let mut frame = vec![8, 0, 8, 0, 8, 4];
+        for id in 1..=4 {
+            frame.extend([id, 0x11, 0]);
+        }
+        jpeg.extend(segment(0xc0, &frame));
+        // The DC categories 0 to 11 are coded in 4 bits each, as themselves,
+        // and the only AC symbol, the end of block, in 1 bit.
+        let mut tables = vec![0x00, 0, 0, 0, 12, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        tables.extend(0..12);
+        tables.extend([0x10, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        jpeg.extend(segment(0xc4, &tables));
+        let mut scan = vec![4];
+        for id in 1..=4 {
+            scan.extend([id, 0x00]);
+        }
+        scan.extend([0, 63, 0]);
+        jpeg.extend(segment(0xda, &scan));
+
+        let mut bits = Bits::default();
+        for value in values {
+            let dc = 8 * (value as i32 - 128);
+            let category = 32 - dc.unsigned_abs().leading_zeros();
+            let coded = if dc < 0 { dc + (1 << category) - 1 } else { dc };
+            bits.push(category, 4);
+            bits.push(coded as u32, category);
+            bits.push(0, 1);
+        }
+        jpeg.extend(bits.finish());
+        jpeg.extend([0xff, 0xd9]);
+        jpeg
+    }
+
+    // The color of the decoded image, which is flat.
+    fn decoded_color(jpeg: &[u8]) -> [u8; 3] {
+        let img = decode_cmyk_jpeg(jpeg, &Limits::default())
+            .unwrap()
+            .expect("not decoded as CMYK")
+            .into_rgb8();
+        assert_eq!(img.dimensions(), (8, 8));
+        assert!(img.pixels().all(|p| p == img.get_pixel(0, 0)));
+        img.get_pixel(0, 0).0
+    }
+
+    fn assert_close(got: [u8; 3], want: [u8; 3]) {
+        for c in 0..3 {
+            assert!(got[c].abs_diff(want[c]) <= 1, "{got:?} != {want:?}");
+        }
+    }
+
+    #[test]
+    fn plain_cmyk_stores_the_ink() {
+        // 20% cyan and 40% black.
+        let jpeg = four_component_jpeg([51, 0, 0, 102], None);
+        assert!(!has_adobe_segment(&jpeg));
+        let light = |ink: u32, k: u32| ((255 - ink) * (255 - k) / 255) as u8;
+        assert_close(
+            decoded_color(&jpeg),
+            [light(51, 102), light(0, 102), light(0, 102)],
+        );
+    }
+
+    #[test]
+    fn adobe_cmyk_stores_the_ink_inverted() {
+        // The same ink as above, stored as Photoshop does.
+        let jpeg = four_component_jpeg([204, 255, 255, 153], Some(0));
+        assert!(has_adobe_segment(&jpeg));
+        let light = |ink: u32, k: u32| ((255 - ink) * (255 - k) / 255) as u8;
+        assert_close(
+            decoded_color(&jpeg),
+            [light(51, 102), light(0, 102), light(0, 102)],
+        );
+    }
+
+    #[test]
+    fn ycck_is_converted() {
+        // A neutral YCC of 100, the inverted CMY ink, and K stored inverted
+        // like the ink of Adobe CMYK.
+        let jpeg = four_component_jpeg([100, 128, 128, 200], Some(2));
+        let v = ((255 - 100) * 200 / 255) as u8;
+        assert_close(decoded_color(&jpeg), [v, v, v]);
+    }
+
+    #[test]
+    fn other_jpegs_are_left_to_the_image_crate() {
+        let img = RgbImage::from_pixel(8, 8, Rgb([10, 200, 30]));
+        let mut jpeg = Vec::new();
+        JpegEncoder::new(&mut jpeg)
+            .encode(img.as_raw(), 8, 8, image::ColorType::Rgb8)
+            .unwrap();
+        assert!(decode_cmyk_jpeg(&jpeg, &Limits::default())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn cmyk_over_the_limits_is_refused() {
+        let jpeg = four_component_jpeg([0, 0, 0, 0], None);
+        let mut limits = Limits::default();
+        limits.max_image_width = Some(4);
+        assert!(decode_cmyk_jpeg(&jpeg, &limits).is_err());
+    }
+
+    fn s15(value: f64) -> [u8; 4] {
+        ((value * 65536.0).round() as i32).to_be_bytes()
+    }
+
+    // A matrix/TRC ICC profile with the primaries of sRGB but linear curves,
+    // so that only the transfer function differs from sRGB.
+    fn linear_srgb_profile() -> Vec<u8> {
+        let xyz =
+            |[x, y, z]: [f64; 3]| [b"XYZ \0\0\0\0".as_slice(), &s15(x), &s15(y), &s15(z)].concat();
+        let curve = [
+            b"curv\0\0\0\0".as_slice(),
+            &1u32.to_be_bytes(),
+            &[1, 0, 0, 0],
+        ]
+        .concat();
+        let tags: [(&[u8; 4], Vec<u8>); 7] = [
+            (b"wtpt", xyz([0.9642, 1.0, 0.8249])),
+            (b"rXYZ", xyz([0.4361, 0.2225, 0.0139])),
+            (b"gXYZ", xyz([0.3851, 0.7169, 0.0971])),
+            (b"bXYZ", xyz([0.1431, 0.0606, 0.7141])),
+            (b"rTRC", curve.clone()),
+            (b"gTRC", curve.clone()),
+            (b"bTRC", curve),
+        ];
+        let mut table = (tags.len() as u32).to_be_bytes().to_vec();
+        let mut data = Vec::new();
+        let start = 128 + 4 + 12 * tags.len();
+        for (signature, tag) in &tags {
+            table.extend(*signature);
+            table.extend(((start + data.len()) as u32).to_be_bytes());
+            table.extend((tag.len() as u32).to_be_bytes());
+            data.extend(tag);
+        }
+        let size = start + data.len();
+        let mut header = vec![0; 128];
+        header[..4].copy_from_slice(&(size as u32).to_be_bytes());
+        header[8..12].copy_from_slice(&[2, 0x10, 0, 0]);
+        header[12..16].copy_from_slice(b"mntr");
+        header[16..20].copy_from_slice(b"RGB ");
+        header[20..24].copy_from_slice(b"XYZ ");
+        header[36..40].copy_from_slice(b"acsp");
+        header[68..80].copy_from_slice(&[s15(0.9642), s15(1.0), s15(0.8249)].concat());
+        [header, table, data].concat()
+    }
+
+    #[test]
+    fn to_srgb_applies_the_profile() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 2, Rgb([128, 128, 128])));
+        let converted = to_srgb(img, &linear_srgb_profile(), Path::new("linear.png")).into_rgba8();
+        // Linear 50% gray is 188 in sRGB.
+        let p = converted.get_pixel(0, 0);
+        for c in 0..3 {
+            assert!(p[c].abs_diff(188) <= 2, "{p:?}");
+        }
+        assert_eq!(p[3], 255);
+    }
+
+    #[test]
+    fn to_srgb_leaves_what_it_cant_use() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 2, Rgb([128, 64, 32])));
+        let same = to_srgb(img.clone(), b"not a profile", Path::new("x.png"));
+        assert_eq!(same, img);
+        let gray = DynamicImage::ImageLuma8(image::GrayImage::from_pixel(2, 2, image::Luma([128])));
+        assert_eq!(
+            to_srgb(gray.clone(), &linear_srgb_profile(), Path::new("x.png")),
+            gray
+        );
+    }
+}
@@ -0,0 +1,76 @@
+use image::{Rgba, RgbaImage};
+
+// How far around each pixel the filter looks.
+const RADIUS: i64 = 2;
+
+// Smooth the noise of `image` with a bilateral filter: each pixel becomes the
+// average of its neighbors, weighted by how close they are and how similar
+// their color is, so that flat areas are smoothed but edges are kept.
+// `strength` is how different (in levels of 0 to 255) two colors may be and
+// still be averaged.
+pub fn bilateral(image: &RgbaImage, strength: u32) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    let sigma = strength.max(1) as f32;
+    let spatial: Vec<f32> = (-RADIUS..=RADIUS)
+        .flat_map(|dy| (-RADIUS..=RADIUS).map(move |dx| (dx * dx + dy * dy) as f32))
+        .map(|d2| (-d2 / (2.0 * (RADIUS as f32).powi(2))).exp())
+        .collect();
+    // The weight of each mean absolute difference of the channels.
+    let range: Vec<f32> = (0..=255)
+        .map(|d| (-((d * d) as f32) / (2.0 * sigma * sigma)).exp())
+        .collect();
+
+    let mut out = RgbaImage::new(width, height);
+    for (x, y, pixel) in out.enumerate_pixels_mut() {
+        let center = image.get_pixel(x, y);
+        let mut sum = [0.0f32; 3];
+        let mut total = 0.0;
+        let mut weights = spatial.iter();
+        for dy in -RADIUS..=RADIUS {
+            for dx in -RADIUS..=RADIUS {
+                let weight = weights.next().unwrap();
+                let nx = (x as i64 + dx).clamp(0, width as i64 - 1) as u32;
+                let ny = (y as i64 + dy).clamp(0, height as i64 - 1) as u32;
+                let neighbor = image.get_pixel(nx, ny);
+                let difference = (0..3)
+                    .map(|c| center[c].abs_diff(neighbor[c]) as usize)
+                    .sum::<usize>()
+                    / 3;
+                let weight = weight * range[difference];
+                for c in 0..3 {
+                    sum[c] += neighbor[c] as f32 * weight;
+                }
+                total += weight;
+            }
+        }
+        let [r, g, b] = sum.map(|s| (s / total).round() as u8);
+        *pixel = Rgba([r, g, b, center[3]]);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_images_are_unchanged() {
+        let image = RgbaImage::from_pixel(6, 6, Rgba([10, 120, 240, 200]));
+        assert_eq!(bilateral(&image, 20), image);
+    }
+
+    #[test]
+    fn smooths_noise_but_keeps_edges() {
+        // Light noise on a dark half and a light half.
+        let image = RgbaImage::from_fn(12, 12, |x, y| {
+            let base = if x < 6 { 40 } else { 220 };
+            let noise = if (x + y) % 2 == 0 { 6 } else { 0 };
+            Rgba([base + noise, base + noise, base + noise, 255])
+        });
+        let smoothed = bilateral(&image, 20);
+        for (x, y, p) in smoothed.enumerate_pixels() {
+            let base = if x < 6 { 40 } else { 220 };
+            assert!(p[0].abs_diff(base + 3) <= 1, "({x}, {y}) is {p:?}");
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use log::{debug, warn};
+use notify_rust::Notification;
+use std::{
+    fs,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+// Show a native desktop notification: through D-Bus on Linux and the BSDs,
+// the Notification Center on macOS and a toast on Windows. A notification
+// that can't be shown is only a warning.
+pub fn notify(title: &str, body: &str) {
+    let shown = Notification::new()
+        .appname("collage")
+        .summary(title)
+        .body(body)
+        .show();
+    if let Err(e) = shown {
+        warn!("Failed to show a desktop notification: {e}");
+    }
+}
+
+// Set `path` as the desktop wallpaper: with AppleScript on macOS,
+// `SystemParametersInfo` on Windows, and `gsettings` for GNOME on Linux and
+// the BSDs, falling back to `feh` for other window managers. A wallpaper that
+// can't be set is only a warning.
+pub fn set_wallpaper(path: &Path) {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let path = path.display().to_string();
+    let done = if cfg!(target_os = "macos") {
+        // The path is passed as an argument of the script rather than spliced
+        // into it, so that no path can break it.
+        let script = "tell application \"System Events\" to tell every desktop to set picture \
+             to (item 1 of argv)";
+        run(
+            "osascript",
+            &["-e", "on run argv", "-e", script, "-e", "end run", &path],
+            &[],
+        )
+    } else if cfg!(windows) {
+        // PowerShell doesn't take arguments after a command, so the path is
+        // passed in the environment instead.
+        let script = "Add-Type -TypeDefinition 'using System.Runtime.InteropServices; \
+             public class Wallpaper { [DllImport(\"user32.dll\")] public static extern int \
+             SystemParametersInfo(int a, int b, string c, int d); }'; \
+             [Wallpaper]::SystemParametersInfo(20, 0, $env:WALLPAPER_PATH, 3)";
+        run(
+            "powershell",
+            &["-NoProfile", "-Command", script],
+            &[("WALLPAPER_PATH", path.as_str())],
+        )
+    } else {
+        let uri = format!("file://{path}");
+        let schema = "org.gnome.desktop.background";
+        if run("gsettings", &["set", schema, "picture-uri", &uri], &[]) {
+            // Newer versions of GNOME have a separate wallpaper for the dark
+            // style, which older ones don't know about.
+            let _ = run("gsettings", &["set", schema, "picture-uri-dark", &uri], &[]);
+            true
+        } else {
+            run("feh", &["--bg-fill", &path], &[])
+        }
+    };
+    if !done {
+        warn!("Failed to set {path} as the wallpaper.");
+    }
+}
+
+// Run `program` with `args` and `env` quietly, returning whether it
+// succeeded.
+fn run(program: &str, args: &[&str], env: &[(&str, &str)]) -> bool {
+    let status = Command::new(program)
+        .args(args)
+        .envs(env.iter().copied())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    match status {
+        Ok(status) if status.success() => true,
+        Ok(status) => {
+            debug!("{program} failed with {status}.");
+            false
+        }
+        Err(e) => {
+            debug!("Failed to run {program}: {e}");
+            false
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use image::{imageops, imageops::FilterType, Rgba, RgbaImage};
+
+// Prepare a collage for an e-ink panel of `width` x `height`: scale it to fit
+// the panel, centered on `background`, turn it gray and stretch its contrast,
+// since e-ink shows only a few dull levels of gray.
+pub fn prepare(image: &RgbaImage, (width, height): (u32, u32), background: Rgba<u8>) -> RgbaImage {
+    let (scale, left, top) = placement(image.dimensions(), (width, height));
+    let w = ((image.width() as f64 * scale).round() as u32).clamp(1, width);
+    let h = ((image.height() as f64 * scale).round() as u32).clamp(1, height);
+    let resized = imageops::resize(image, w, h, FilterType::CatmullRom);
+    let mut panel = RgbaImage::from_pixel(width, height, background);
+    imageops::overlay(&mut panel, &resized, left as i64, top as i64);
+
+    let gray: Vec<u8> = panel
+        .pixels()
+        .map(|p| (0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32).round() as u8)
+        .collect();
+    // Stretch the levels so that the darkest and brightest 1% of the pixels
+    // become black and white.
+    let mut counts = [0usize; 256];
+    gray.iter().for_each(|&g| counts[g as usize] += 1);
+    let percentile = |fraction: f64| {
+        let target = (gray.len() as f64 * fraction) as usize;
+        let mut seen = 0;
+        (0..256)
+            .find(|&v| {
+                seen += counts[v];
+                seen > target
+            })
+            .unwrap_or(255) as f32
+    };
+    let (low, high) = (percentile(0.01), percentile(0.99));
+    let range = (high - low).max(1.0);
+    for (pixel, g) in panel.pixels_mut().zip(gray) {
+        let v = ((g as f32 - low) / range * 255.0).clamp(0.0, 255.0) as u8;
+        *pixel = Rgba([v, v, v, 255]);
+    }
+    panel
+}
+
+// The `levels` evenly spaced grays, from black to white, an e-ink panel can
+// show.
+pub fn palette(levels: u32) -> Vec<Rgba<u8>> {
+    let levels = levels.clamp(2, 256);
+    (0..levels)
+        .map(|i| {
+            let v = (i * 255 / (levels - 1)) as u8;
+            Rgba([v, v, v, 255])
+        })
+        .collect()
+}
+
+// Where `prepare` puts a `width` x `height` collage on a panel of
+// `panel_width` x `panel_height`: the scale it resizes it by and the offset
+// of its top left corner.
+pub fn placement(
+    (width, height): (u32, u32),
+    (panel_width, panel_height): (u32, u32),
+) -> (f64, u32, u32) {
+    let scale = f64::min(
+        panel_width as f64 / width as f64,
+        panel_height as f64 / height as f64,
+    );
+    let w = ((width as f64 * scale).round() as u32).clamp(1, panel_width);
+    let h = ((height as f64 * scale).round() as u32).clamp(1, panel_height);
+    (scale, (panel_width - w) / 2, (panel_height - h) / 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn palette_spans_black_to_white() {
+        let grays: Vec<u8> = palette(4).iter().map(|p| p[0]).collect();
+        assert_eq!(grays, [0, 85, 170, 255]);
+        assert!(palette(16)
+            .iter()
+            .all(|p| p[0] == p[1] && p[1] == p[2] && p[3] == 255));
+    }
+
+    #[test]
+    fn placement_fits_and_centers() {
+        assert_eq!(placement((400, 200), (800, 600)), (2.0, 0, 100));
+        assert_eq!(placement((200, 400), (800, 600)), (1.5, 250, 0));
+        assert_eq!(placement((800, 600), (800, 600)), (1.0, 0, 0));
+    }
+
+    #[test]
+    fn prepare_fills_the_panel() {
+        let image = RgbaImage::from_pixel(40, 20, Rgba([200, 100, 50, 255]));
+        let panel = prepare(&image, (80, 60), Rgba([255, 255, 255, 255]));
+        assert_eq!(panel.dimensions(), (80, 60));
+        assert!(panel.pixels().all(|p| p[0] == p[1] && p[1] == p[2]));
+    }
+}
@@ -0,0 +1,35 @@
+// What a collage will take, worked out from the headers of its images before
+// any of them is decoded.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Estimate {
+    // The images whose headers could be read.
+    pub images: usize,
+    // The files that will be skipped.
+    pub skipped: usize,
+    // The memory all the decoded images take together.
+    pub decoded_bytes: u64,
+    // The most memory in use at once: the decoded images and the resized
+    // tiles, or the tiles and the canvas they are copied to, whichever is
+    // more.
+    pub peak_bytes: u64,
+    // The size of the collage, if its layout can be worked out without the
+    // images.
+    pub canvas: Option<(u32, u32)>,
+}
+
+impl Estimate {
+    // Print the estimate for `--dry-run`.
+    pub fn print(&self) {
+        let mb = |bytes: u64| bytes as f64 / 1_000_000.0;
+        println!(
+            "Images:         {} to use, {} to skip",
+            self.images, self.skipped
+        );
+        match self.canvas {
+            Some((width, height)) => println!("Dimensions:     {width}x{height}"),
+            None => println!("Dimensions:     unknown until the images are decoded"),
+        }
+        println!("Decoded images: {:.1}MB", mb(self.decoded_bytes));
+        println!("Peak memory:    {:.1}MB", mb(self.peak_bytes));
+    }
+}
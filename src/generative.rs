@@ -0,0 +1,225 @@
+use clap::ValueEnum;
+use image::{Rgba, RgbaImage};
+use rand::{seq::SliceRandom, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::Serialize;
+use std::f32::consts::TAU;
+
+use crate::texture::{hash, value_noise};
+
+// A procedural backdrop, colored from the photos and drawn from the seed.
+#[derive(ValueEnum, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum Style {
+    // Strokes following the currents of a smooth noise field.
+    FlowField,
+    // Large, soft discs of color melting into each other.
+    Blobs,
+    // A gradient across the canvas at a random angle, with noise.
+    Gradient,
+}
+
+// What the collage is drawn on, other than a plain color or a texture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Background {
+    Generative(Style),
+}
+
+impl std::str::FromStr for Background {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let styles = || {
+            Style::value_variants()
+                .iter()
+                .filter_map(|s| s.to_possible_value())
+                .map(|v| format!("`generative:{}`", v.get_name()))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let style = s
+            .strip_prefix("generative:")
+            .ok_or_else(|| format!("expected one of {}, got `{s}`", styles()))?;
+        Style::from_str(style, true)
+            .map(Background::Generative)
+            .map_err(|_| format!("expected one of {}, got `{s}`", styles()))
+    }
+}
+
+// A background is recorded as it is given on the command line.
+impl Serialize for Background {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let Background::Generative(style) = self;
+        let name = style
+            .to_possible_value()
+            .map_or_else(String::new, |v| v.get_name().to_string());
+        serializer.serialize_str(&format!("generative:{name}"))
+    }
+}
+
+// A `width` x `height` backdrop in `style`, in the colors of `palette`
+// (e.g. the dominant color of each photo), the same for the same `seed`.
+pub fn render(style: Style, width: u32, height: u32, palette: &[Rgba<u8>], seed: u64) -> RgbaImage {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    // The photos should stand out against their backdrop, so its colors are
+    // paler than theirs.
+    let mut colors: Vec<[f32; 3]> = palette
+        .iter()
+        .map(|c| [0, 1, 2].map(|i| c[i] as f32 * 0.65 + 255.0 * 0.35))
+        .collect();
+    if colors.is_empty() {
+        colors.push([235.0, 235.0, 235.0]);
+    }
+    colors.shuffle(&mut rng);
+    let salt = rng.gen::<u32>();
+    let (w, h) = (width as f32, height as f32);
+    let mut image = match style {
+        Style::Gradient => {
+            // The colors are spread along a line through the center of the
+            // canvas at a random angle, bent a little by the noise.
+            let stops = &colors[..colors.len().min(3)];
+            let angle = rng.gen::<f32>() * TAU;
+            let (dx, dy) = (angle.cos(), angle.sin());
+            let reach = (w * dx.abs() + h * dy.abs()).max(1.0);
+            let scale = (width.max(height) / 6).max(8);
+            RgbaImage::from_fn(width, height, |x, y| {
+                let along = ((x as f32 - w / 2.0) * dx + (y as f32 - h / 2.0) * dy) / reach + 0.5;
+                let bend = (value_noise(x, y, scale, salt) - 0.5) * 0.25;
+                pixel(mix(stops, along + bend))
+            })
+        }
+        Style::Blobs => {
+            // Each pixel is the average of the colors of the blobs, weighted
+            // by how near it is to their centers, over the average color.
+            let base = average(&colors);
+            let blobs: Vec<_> = (0..rng.gen_range(6..=12))
+                .map(|_| {
+                    let radius = w.min(h) * rng.gen_range(0.2..0.5);
+                    let center = (rng.gen::<f32>() * w, rng.gen::<f32>() * h);
+                    (
+                        center,
+                        1.0 / (2.0 * radius * radius),
+                        colors[rng.gen_range(0..colors.len())],
+                    )
+                })
+                .collect();
+            RgbaImage::from_fn(width, height, |x, y| {
+                let (mut sum, mut total) = (base.map(|c| c * 0.2), 0.2);
+                for &((cx, cy), falloff, color) in &blobs {
+                    let d = (x as f32 - cx).powi(2) + (y as f32 - cy).powi(2);
+                    let weight = (-d * falloff).exp();
+                    (0..3).for_each(|i| sum[i] += color[i] * weight);
+                    total += weight;
+                }
+                pixel(sum.map(|c| c / total))
+            })
+        }
+        Style::FlowField => {
+            // Particles are dropped all over a pale canvas and leave a thin
+            // stroke wherever the field carries them.
+            let base = average(&colors).map(|c| c * 0.4 + 255.0 * 0.6);
+            let mut image = RgbaImage::from_pixel(width, height, pixel(base));
+            let scale = (width.max(height) / 5).max(16);
+            let particles = (width as u64 * height as u64 / 500).max(1);
+            for _ in 0..particles {
+                let color = colors[rng.gen_range(0..colors.len())];
+                let (mut x, mut y) = (rng.gen::<f32>() * w, rng.gen::<f32>() * h);
+                for _ in 0..rng.gen_range(20..80) {
+                    if !(0.0..w).contains(&x) || !(0.0..h).contains(&y) {
+                        break;
+                    }
+                    let p = image.get_pixel_mut(x as u32, y as u32);
+                    for i in 0..3 {
+                        p[i] = (p[i] as f32 * 0.6 + color[i] * 0.4) as u8;
+                    }
+                    let angle = value_noise(x as u32, y as u32, scale, salt) * TAU * 2.0;
+                    x += angle.cos();
+                    y += angle.sin();
+                }
+            }
+            image
+        }
+    };
+    // A fine grain, so that the flat areas don't band.
+    for (x, y, p) in image.enumerate_pixels_mut() {
+        let grain = (hash(x, y, salt ^ 0x9e37_79b9) - 0.5) * 6.0;
+        for i in 0..3 {
+            p[i] = (p[i] as f32 + grain).clamp(0.0, 255.0) as u8;
+        }
+    }
+    image
+}
+
+// The color at `t`, from 0 to 1, of a gradient through `stops`.
+fn mix(stops: &[[f32; 3]], t: f32) -> [f32; 3] {
+    if stops.len() == 1 {
+        return stops[0];
+    }
+    let t = t.clamp(0.0, 1.0) * (stops.len() - 1) as f32;
+    let i = (t as usize).min(stops.len() - 2);
+    let f = t - i as f32;
+    [0, 1, 2].map(|c| stops[i][c] * (1.0 - f) + stops[i + 1][c] * f)
+}
+
+fn average(colors: &[[f32; 3]]) -> [f32; 3] {
+    [0, 1, 2].map(|c| colors.iter().map(|color| color[c]).sum::<f32>() / colors.len() as f32)
+}
+
+fn pixel(color: [f32; 3]) -> Rgba<u8> {
+    let [r, g, b] = color.map(|c| c.clamp(0.0, 255.0) as u8);
+    Rgba([r, g, b, 255])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn parses_and_records_backgrounds() {
+        let background = Background::from_str("generative:flow-field").unwrap();
+        assert_eq!(background, Background::Generative(Style::FlowField));
+        assert_eq!(
+            serde_json::to_string(&background).unwrap(),
+            "\"generative:flow-field\""
+        );
+        let error = Background::from_str("generative:waves").unwrap_err();
+        assert!(error.contains("`generative:blobs`"), "{error}");
+        assert!(Background::from_str("blobs").is_err());
+    }
+
+    #[test]
+    fn the_same_seed_draws_the_same_backdrop() {
+        let palette = [Rgba([200, 30, 30, 255]), Rgba([30, 30, 200, 255])];
+        for &style in Style::value_variants() {
+            let draw = |seed| render(style, 48, 32, &palette, seed);
+            assert_eq!(draw(1).dimensions(), (48, 32));
+            assert_eq!(draw(1), draw(1), "{style:?}");
+            assert_ne!(draw(1), draw(2), "{style:?}");
+            assert!(draw(1).pixels().all(|p| p[3] == 255));
+        }
+    }
+
+    #[test]
+    fn backdrops_are_paler_than_the_photos() {
+        let palette = [Rgba([0, 0, 0, 255])];
+        for &style in Style::value_variants() {
+            let image = render(style, 32, 32, &palette, 3);
+            // Black becomes 35% white, give or take the grain.
+            assert!(image.pixels().all(|p| p[0] >= 85), "{style:?}");
+        }
+        // Without a palette, the backdrop is light gray.
+        let image = render(Style::Gradient, 8, 8, &[], 3);
+        assert!(image.pixels().all(|p| p[0].abs_diff(235) <= 3));
+    }
+
+    #[test]
+    fn mixes_between_stops() {
+        let stops = [[0.0, 0.0, 0.0], [100.0, 0.0, 0.0], [100.0, 200.0, 0.0]];
+        assert_eq!(mix(&stops, 0.0), [0.0, 0.0, 0.0]);
+        assert_eq!(mix(&stops, 0.25), [50.0, 0.0, 0.0]);
+        assert_eq!(mix(&stops, 0.75), [100.0, 100.0, 0.0]);
+        assert_eq!(mix(&stops, 2.0), [100.0, 200.0, 0.0]);
+        assert_eq!(mix(&stops[..1], 0.5), [0.0, 0.0, 0.0]);
+    }
+}
@@ -0,0 +1,438 @@
+use anyhow::{bail, ensure, Context, Result};
+use image::RgbaImage;
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::mpsc,
+};
+
+use crate::color::linear_to_srgb;
+
+// An image to composite and where: its top left corner on the canvas and the
+// opacity it's drawn with.
+pub struct Layer<'a> {
+    pub image: &'a RgbaImage,
+    pub x: u32,
+    pub y: u32,
+    pub opacity: f32,
+}
+
+// Draws one layer as a quad over its tile, premultiplied so that the blend
+// state does the alpha blending of `color::overlay`. The texels are loaded
+// rather than sampled, so that every pixel lands exactly where it does on the
+// CPU.
+const SHADER: &str = r#"
+struct Layer {
+    canvas: vec2<f32>,
+    origin: vec2<f32>,
+    size: vec2<f32>,
+    opacity: f32,
+    linear: f32,
+}
+
+@group(0) @binding(0) var<uniform> layer: Layer;
+@group(0) @binding(1) var image: texture_2d<f32>;
+
+@vertex
+fn vs(@builtin(vertex_index) i: u32) -> @builtin(position) vec4<f32> {
+    let corner = vec2<f32>(f32(i & 1u), f32((i >> 1u) & 1u));
+    let p = (layer.origin + corner * layer.size) / layer.canvas;
+    return vec4<f32>(p.x * 2.0 - 1.0, 1.0 - p.y * 2.0, 0.0, 1.0);
+}
+
+fn decode(c: f32) -> f32 {
+    if c <= 0.04045 {
+        return c / 12.92;
+    }
+    return pow((c + 0.055) / 1.055, 2.4);
+}
+
+@fragment
+fn fs(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let texel = textureLoad(image, vec2<i32>(floor(position.xy - layer.origin)), 0);
+    let a = round(texel.a * 255.0 * layer.opacity) / 255.0;
+    var rgb = texel.rgb;
+    if layer.linear > 0.5 {
+        rgb = vec3<f32>(decode(rgb.r), decode(rgb.g), decode(rgb.b));
+    }
+    return vec4<f32>(rgb * a, a);
+}
+"#;
+
+// The canvas is kept premultiplied in half floats, which can be blended on
+// every adapter, and only rounded to 8 bits once all the layers are drawn.
+const CANVAS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const CANVAS_TEXEL: u32 = 8;
+
+// Blend `layers` over `canvas` in order on the GPU, in linear light if
+// `linear`, as `color::overlay` does on the CPU. Fails when there is no
+// adapter or the canvas is too large for it, leaving `canvas` as it was.
+// Translucent pixels are blended in half floats rather than 8 bits, so they
+// may come out a level apart from the CPU.
+pub fn composite(canvas: &mut RgbaImage, layers: &[Layer], linear: bool) -> Result<()> {
+    // Some drivers make wgpu panic rather than fail while probing them, which
+    // mustn't take the collage down with it.
+    panic::catch_unwind(AssertUnwindSafe(|| {
+        pollster::block_on(composite_async(canvas, layers, linear))
+    }))
+    .unwrap_or_else(|_| bail!("The graphics driver failed"))
+}
+
+async fn composite_async(canvas: &mut RgbaImage, layers: &[Layer<'_>], linear: bool) -> Result<()> {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            force_fallback_adapter: false,
+            compatible_surface: None,
+        })
+        .await
+        .context("No GPU adapter found")?;
+    let limits = adapter.limits();
+    let (width, height) = canvas.dimensions();
+    let padded_row = (width * CANVAS_TEXEL).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    ensure!(
+        width.max(height) <= limits.max_texture_dimension_2d
+            && padded_row as u64 * height as u64 <= limits.max_buffer_size,
+        "The collage is too large for the GPU ({})",
+        adapter.get_info().name
+    );
+    let (device, queue) = adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("collage"),
+                required_features: wgpu::Features::empty(),
+                required_limits: limits,
+            },
+            None,
+        )
+        .await
+        .context("Failed to open the GPU")?;
+    // Errors are caught rather than left to panic, so the CPU can take over.
+    device.push_error_scope(wgpu::ErrorFilter::OutOfMemory);
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+
+    let target = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("canvas"),
+        size: extent(width, height),
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: CANVAS_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+    let target_view = target.create_view(&Default::default());
+    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("layer"),
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+        ],
+    });
+    let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("composite"),
+        source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+    });
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("composite"),
+        bind_group_layouts: &[&bind_group_layout],
+        push_constant_ranges: &[],
+    });
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("composite"),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: &module,
+            entry_point: "vs",
+            buffers: &[],
+        },
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleStrip,
+            ..Default::default()
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        fragment: Some(wgpu::FragmentState {
+            module: &module,
+            entry_point: "fs",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: CANVAS_FORMAT,
+                blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        multiview: None,
+    });
+
+    // The canvas itself goes first, over a transparent target, and then every
+    // layer over it. Each layer is submitted on its own, so that its texture
+    // is freed once it's drawn rather than all of them held at once.
+    let background = Layer {
+        image: canvas,
+        x: 0,
+        y: 0,
+        opacity: 1.0,
+    };
+    for (i, layer) in [&background].into_iter().chain(layers).enumerate() {
+        let (w, h) = layer.image.dimensions();
+        if w == 0 || h == 0 {
+            continue;
+        }
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("layer"),
+            size: extent(w, h),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            texture.as_image_copy(),
+            layer.image.as_raw(),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * w),
+                rows_per_image: Some(h),
+            },
+            extent(w, h),
+        );
+        let uniform: Vec<u8> = [
+            width as f32,
+            height as f32,
+            layer.x as f32,
+            layer.y as f32,
+            w as f32,
+            h as f32,
+            layer.opacity,
+            if linear { 1.0 } else { 0.0 },
+        ]
+        .iter()
+        .flat_map(|v| v.to_ne_bytes())
+        .collect();
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("layer"),
+            size: uniform.len() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        queue.write_buffer(&buffer, 0, &uniform);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("layer"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(
+                        &texture.create_view(&Default::default()),
+                    ),
+                },
+            ],
+        });
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("layer"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &target_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: if i == 0 {
+                            wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT)
+                        } else {
+                            wgpu::LoadOp::Load
+                        },
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            pass.set_pipeline(&pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.draw(0..4, 0..1);
+        }
+        queue.submit([encoder.finish()]);
+    }
+
+    let readback = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("readback"),
+        size: padded_row as u64 * height as u64,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&Default::default());
+    encoder.copy_texture_to_buffer(
+        target.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &readback,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row),
+                rows_per_image: Some(height),
+            },
+        },
+        extent(width, height),
+    );
+    queue.submit([encoder.finish()]);
+    let (sender, receiver) = mpsc::channel();
+    readback
+        .slice(..)
+        .map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+    device.poll(wgpu::Maintain::Wait);
+    for _ in 0..2 {
+        if let Some(error) = device.pop_error_scope().await {
+            bail!("The GPU failed: {error}");
+        }
+    }
+    receiver
+        .recv()
+        .context("The GPU never returned the collage")?
+        .context("Failed to read the collage back from the GPU")?;
+
+    let data = readback.slice(..).get_mapped_range();
+    for (y, row) in data.chunks_exact(padded_row as usize).enumerate() {
+        for (x, texel) in row
+            .chunks_exact(CANVAS_TEXEL as usize)
+            .take(width as usize)
+            .enumerate()
+        {
+            let channel =
+                |c: usize| half_to_f32(u16::from_le_bytes([texel[2 * c], texel[2 * c + 1]]));
+            let a = channel(3);
+            // Nothing was drawn where a fully transparent canvas stayed so, and
+            // its colors are kept as they are.
+            if a <= 0.0 {
+                continue;
+            }
+            let encode = |c: f32| {
+                let c = (c / a).clamp(0.0, 1.0);
+                let c = if linear { linear_to_srgb(c) } else { c };
+                (c * 255.0).round() as u8
+            };
+            let pixel = canvas.get_pixel_mut(x as u32, y as u32);
+            for c in 0..3 {
+                pixel[c] = encode(channel(c));
+            }
+            pixel[3] = (a.clamp(0.0, 1.0) * 255.0).round() as u8;
+        }
+    }
+    Ok(())
+}
+
+fn extent(width: u32, height: u32) -> wgpu::Extent3d {
+    wgpu::Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+    }
+}
+
+// An IEEE half float, as the GPU stores the canvas, as an f32.
+fn half_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * (-24f32).exp2(),
+        31 if mantissa == 0.0 => f32::INFINITY,
+        31 => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * ((exponent - 15) as f32).exp2(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color;
+    use image::Rgba;
+
+    // The largest difference of any channel of any pixel.
+    fn max_diff(a: &RgbaImage, b: &RgbaImage) -> u8 {
+        a.pixels()
+            .zip(b.pixels())
+            .flat_map(|(p, q)| (0..4).map(move |c| p[c].abs_diff(q[c])))
+            .max()
+            .unwrap_or(0)
+    }
+
+    // Opaque and translucent layers, overlapping each other and the edge of
+    // a canvas that is itself partly transparent.
+    fn scene() -> (RgbaImage, Vec<(RgbaImage, u32, u32, f32)>) {
+        let canvas = RgbaImage::from_fn(64, 48, |x, y| {
+            Rgba([
+                (x * 4) as u8,
+                (y * 5) as u8,
+                90,
+                if x < 8 { 0 } else { 255 },
+            ])
+        });
+        let gradient = RgbaImage::from_fn(30, 20, |x, y| {
+            Rgba([200, (x * 8) as u8, (y * 12) as u8, (x * 8 + 10) as u8])
+        });
+        let opaque = RgbaImage::from_pixel(25, 25, Rgba([20, 180, 60, 255]));
+        let layers = vec![
+            (opaque.clone(), 0, 0, 1.0),
+            (gradient.clone(), 10, 5, 1.0),
+            (gradient, 30, 20, 0.6),
+            (opaque, 39, 23, 0.3),
+        ];
+        (canvas, layers)
+    }
+
+    #[test]
+    fn composite_agrees_with_the_cpu() {
+        for linear in [false, true] {
+            let (canvas, layers) = scene();
+            let mut cpu = canvas.clone();
+            for (image, x, y, opacity) in &layers {
+                let mut faded = image.clone();
+                color::fade(&mut faded, *opacity);
+                color::overlay(&mut cpu, &faded, *x, *y, linear);
+            }
+            let mut gpu = canvas;
+            let layers: Vec<_> = layers
+                .iter()
+                .map(|(image, x, y, opacity)| Layer {
+                    image,
+                    x: *x,
+                    y: *y,
+                    opacity: *opacity,
+                })
+                .collect();
+            if let Err(e) = composite(&mut gpu, &layers, linear) {
+                // Nothing to compare against without an adapter.
+                eprintln!("Skipping, no GPU: {e:#}");
+                return;
+            }
+            assert!(max_diff(&cpu, &gpu) <= 1, "linear: {linear}");
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use clap::ValueEnum;
+use image::{Rgba, RgbaImage};
+use serde::Serialize;
+
+// Whether `--histogram` draws one band for all of the images or one under
+// each of them.
+#[derive(ValueEnum, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum HistogramMode {
+    Combined,
+    PerImage,
+}
+
+// The number of pixels of each value of the red, green, blue and luminance
+// channels, ignoring fully transparent pixels.
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    channels: [[u64; 256]; 4],
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            channels: [[0; 256]; 4],
+        }
+    }
+}
+
+impl Histogram {
+    // Count the pixels of `image`.
+    pub fn add(&mut self, image: &RgbaImage) {
+        for Rgba([r, g, b, a]) in image.pixels() {
+            if *a == 0 {
+                continue;
+            }
+            let luma = (0.2126 * *r as f32 + 0.7152 * *g as f32 + 0.0722 * *b as f32).round();
+            for (channel, value) in self.channels.iter_mut().zip([*r, *g, *b, luma as u8]) {
+                channel[value as usize] += 1;
+            }
+        }
+    }
+
+    // Draw the histogram into a `width` x `height` band, the luminance in gray
+    // behind the red, green and blue channels, which add up where they
+    // overlap. Each channel is scaled to its own largest count, so a spike in
+    // one channel doesn't flatten the others.
+    pub fn render(&self, width: u32, height: u32, background: Rgba<u8>) -> RgbaImage {
+        let max = self
+            .channels
+            .map(|channel| channel.iter().copied().max().unwrap_or(0).max(1));
+        let mut band = RgbaImage::from_pixel(width, height, background);
+        for x in 0..width {
+            let value = (x as u64 * 256 / width as u64) as usize;
+            let bar = |channel: usize| {
+                (self.channels[channel][value] as f64 / max[channel] as f64 * height as f64).round()
+                    as u32
+            };
+            let [r, g, b, luma] = [bar(0), bar(1), bar(2), bar(3)];
+            for y in 0..height {
+                let level = height - y;
+                let rgb = [r, g, b].map(|bar| if bar >= level { 255 } else { 0 });
+                let color = if rgb != [0, 0, 0] {
+                    Rgba([rgb[0], rgb[1], rgb[2], 255])
+                } else if luma >= level {
+                    Rgba([128, 128, 128, 255])
+                } else {
+                    continue;
+                };
+                band.put_pixel(x, y, color);
+            }
+        }
+        band
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_a_bar_per_channel() {
+        let mut image = RgbaImage::from_pixel(3, 3, Rgba([255, 0, 0, 255]));
+        // Fully transparent pixels don't count.
+        image.put_pixel(0, 0, Rgba([0, 0, 0, 0]));
+        let mut histogram = Histogram::default();
+        histogram.add(&image);
+
+        let background = Rgba([1, 2, 3, 255]);
+        let band = histogram.render(256, 10, background);
+        let column = |x: u32| -> Vec<Rgba<u8>> { (0..10).map(|y| *band.get_pixel(x, y)).collect() };
+        // Red is all 255, green and blue are all 0, and the luminance of red
+        // is 54.
+        assert_eq!(column(255), [Rgba([255, 0, 0, 255]); 10]);
+        assert_eq!(column(0), [Rgba([0, 255, 255, 255]); 10]);
+        assert_eq!(column(54), [Rgba([128, 128, 128, 255]); 10]);
+        assert_eq!(column(100), [background; 10]);
+    }
+
+    #[test]
+    fn each_channel_is_scaled_to_its_own_peak() {
+        let mut histogram = Histogram::default();
+        // Three pixels of green 0 and one of green 255, all red 0.
+        for g in [0, 0, 0, 255] {
+            histogram.add(&RgbaImage::from_pixel(1, 1, Rgba([0, g, 0, 255])));
+        }
+        let band = histogram.render(256, 12, Rgba([0, 0, 0, 0]));
+        let height = |x: u32, c: usize| (0..12).filter(|&y| band.get_pixel(x, y)[c] == 255).count();
+        assert_eq!(height(0, 0), 12);
+        assert_eq!(height(0, 1), 12);
+        assert_eq!(height(255, 1), 4);
+    }
+}
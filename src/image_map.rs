@@ -0,0 +1,194 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::layout::Tile;
+
+// The format of the file `--image-map` writes next to the collage.
+#[derive(ValueEnum, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum MapFormat {
+    // A page with the collage and an HTML `<map>` of its tiles.
+    Html,
+    // The rectangle, file and link of each tile.
+    Json,
+}
+
+#[derive(Serialize)]
+struct Hotspot {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    file: String,
+    href: String,
+}
+
+#[derive(Serialize)]
+struct ImageMap {
+    image: String,
+    width: u32,
+    height: u32,
+    hotspots: Vec<Hotspot>,
+}
+
+// Write the image map of the collage at `collage` next to it, with the same
+// name, linking each tile to its source image. The link is `template` with
+// `{file}` replaced by the file name of the image and `{path}` by its path.
+// Returns the path of the map.
+pub fn write(
+    collage: &Path,
+    (width, height): (u32, u32),
+    tiles: &[Tile],
+    paths: &[PathBuf],
+    template: &str,
+    format: MapFormat,
+) -> Result<PathBuf> {
+    let hotspots = tiles
+        .iter()
+        .zip(paths)
+        .map(|(tile, path)| {
+            let file = path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned();
+            Hotspot {
+                x: tile.x,
+                y: tile.y,
+                width: tile.width,
+                height: tile.height,
+                href: template
+                    .replace("{file}", &file)
+                    .replace("{path}", &path.to_string_lossy()),
+                file,
+            }
+        })
+        .collect();
+    let map = ImageMap {
+        image: collage
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned(),
+        width,
+        height,
+        hotspots,
+    };
+    let (extension, text) = match format {
+        MapFormat::Html => ("html", html(&map)),
+        MapFormat::Json => ("json", serde_json::to_string_pretty(&map)?),
+    };
+    let path = collage.with_extension(extension);
+    fs::write(&path, text).with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+fn html(map: &ImageMap) -> String {
+    let mut areas = String::new();
+    for spot in &map.hotspots {
+        areas.push_str(&format!(
+            "  <area shape=\"rect\" coords=\"{},{},{},{}\" href=\"{}\" alt=\"{}\" title=\"{}\">\n",
+            spot.x,
+            spot.y,
+            spot.x + spot.width,
+            spot.y + spot.height,
+            escape(&spot.href),
+            escape(&spot.file),
+            escape(&spot.file),
+        ));
+    }
+    let image = escape(&map.image);
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{image}</title>\n</head>\n<body>\n\
+         <img src=\"{image}\" width=\"{}\" height=\"{}\" usemap=\"#collage\" alt=\"{image}\">\n\
+         <map name=\"collage\">\n{areas}</map>\n</body>\n</html>\n",
+        map.width, map.height,
+    )
+}
+
+// Escape the characters that are special in HTML text and attributes.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_map(format: MapFormat) -> String {
+        let dir =
+            std::env::temp_dir().join(format!("collage-map-{format:?}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let tiles = [
+            Tile {
+                x: 0,
+                y: 0,
+                width: 10,
+                height: 20,
+            },
+            Tile {
+                x: 10,
+                y: 0,
+                width: 30,
+                height: 20,
+            },
+        ];
+        let paths = [
+            PathBuf::from("photos/a.jpg"),
+            PathBuf::from("photos/b&\"c\".jpg"),
+        ];
+        let path = write(
+            &dir.join("collage_0.png"),
+            (40, 20),
+            &tiles,
+            &paths,
+            "https://example.com/{file}?from={path}",
+            format,
+        )
+        .unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        assert_eq!(path.file_stem().unwrap(), "collage_0");
+        fs::remove_dir_all(&dir).unwrap();
+        text
+    }
+
+    #[test]
+    fn writes_json_hotspots() {
+        let json: serde_json::Value = serde_json::from_str(&write_map(MapFormat::Json)).unwrap();
+        assert_eq!(json["image"], "collage_0.png");
+        assert_eq!(
+            (json["width"].as_u64(), json["height"].as_u64()),
+            (Some(40), Some(20))
+        );
+        let spot = &json["hotspots"][1];
+        assert_eq!(
+            (&spot["x"], &spot["width"], &spot["file"]),
+            (&10.into(), &30.into(), &"b&\"c\".jpg".into())
+        );
+        assert_eq!(
+            json["hotspots"][0]["href"],
+            "https://example.com/a.jpg?from=photos/a.jpg"
+        );
+    }
+
+    #[test]
+    fn writes_an_escaped_html_map() {
+        let html = write_map(MapFormat::Html);
+        assert!(html
+            .contains("<img src=\"collage_0.png\" width=\"40\" height=\"20\" usemap=\"#collage\""));
+        assert!(html.contains(
+            "<area shape=\"rect\" coords=\"0,0,10,20\" href=\"https://example.com/a.jpg?from=photos/a.jpg\""
+        ));
+        assert!(html.contains("coords=\"10,0,40,20\""));
+        assert!(html.contains("alt=\"b&amp;&quot;c&quot;.jpg\""));
+        assert_eq!(escape("<a & b>"), "&lt;a &amp; b&gt;");
+    }
+}
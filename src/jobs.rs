@@ -0,0 +1,130 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::{ffi::OsString, fs, path::Path};
+use toml::{Table, Value};
+
+use crate::config::value_to_arg;
+
+// A jobs file describes several collages to be created in one run, e.g.
+//
+// ```toml
+// parallel = true
+//
+// [[job]]
+// source = "photos/2023"
+// name = "2023"
+// orientation = "landscape"
+// height = 400
+// ```
+//
+// Every key of a job other than `source` is the long name of a command line
+// option, so a job accepts exactly the same settings as the command line,
+// and the config file gives the defaults of every job. With `parallel`, up
+// to four jobs run at once.
+#[derive(Deserialize, Debug, Clone)]
+pub struct JobFile {
+    #[serde(default)]
+    pub parallel: bool,
+    #[serde(default, rename = "job")]
+    pub jobs: Vec<Table>,
+}
+
+impl JobFile {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read jobs file {}", path.display()))?;
+        toml::from_str(&text)
+            .with_context(|| format!("Failed to parse jobs file {}", path.display()))
+    }
+}
+
+// Convert a job table to the command line arguments it stands for. Relative
+// sources are resolved against `base`, the directory of the jobs file.
+pub fn job_args(job: &Table, base: &Path) -> Result<Vec<OsString>> {
+    let mut args = vec![OsString::from("collage")];
+    for (key, value) in job {
+        if key == "source" {
+            let Value::String(source) = value else {
+                bail!("The job source must be a string");
+            };
+            args.push(base.join(source).into_os_string());
+            continue;
+        }
+        let flag = OsString::from(format!("--{}", key.replace('_', "-")));
+        match value {
+            Value::Boolean(true) => args.push(flag),
+            Value::Boolean(false) => {}
+            Value::Array(values) => {
+                for v in values {
+                    args.push(flag.clone());
+                    args.push(job_value(v)?.into());
+                }
+            }
+            v => {
+                args.push(flag);
+                args.push(job_value(v)?.into());
+            }
+        }
+    }
+    Ok(args)
+}
+
+fn job_value(value: &Value) -> Result<String> {
+    value_to_arg(value).with_context(|| format!("Unsupported value in jobs file: {value}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(text: &str) -> Table {
+        let mut file: JobFile = toml::from_str(text).unwrap();
+        file.jobs.remove(0)
+    }
+
+    #[test]
+    fn parses_a_jobs_file() {
+        let file: JobFile =
+            toml::from_str("[[job]]\nsource = \"a\"\n[[job]]\nsource = \"b\"").unwrap();
+        assert!(!file.parallel);
+        assert_eq!(file.jobs.len(), 2);
+        let file: JobFile = toml::from_str("parallel = true").unwrap();
+        assert!(file.parallel);
+        assert!(file.jobs.is_empty());
+    }
+
+    #[test]
+    fn job_args_are_command_line_arguments() {
+        let job = job("[[job]]\n\
+             source = \"2023\"\n\
+             height = 400\n\
+             gap_color = \"white\"\n\
+             linear = true\n\
+             quiet = false\n\
+             caption = [\"a\", \"b\"]\n");
+        let args = job_args(&job, Path::new("photos")).unwrap();
+        let source = Path::new("photos").join("2023");
+        // The keys of a table are sorted.
+        let expected = [
+            "collage",
+            "--caption",
+            "a",
+            "--caption",
+            "b",
+            "--gap-color",
+            "white",
+            "--height",
+            "400",
+            "--linear",
+            source.to_str().unwrap(),
+        ];
+        assert_eq!(args, expected.map(OsString::from));
+    }
+
+    #[test]
+    fn job_args_reject_what_isnt_an_argument() {
+        let base = Path::new(".");
+        assert!(job_args(&job("[[job]]\nsource = 1"), base).is_err());
+        assert!(job_args(&job("[[job]]\nmargin = { top = 1 }"), base).is_err());
+    }
+}
@@ -0,0 +1,1135 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use crate::text;
+
+#[allow(dead_code)]
+#[derive(ValueEnum, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Orientation {
+    Portrait,
+    Landscape,
+}
+
+// The spacing between images: a number of pixels, or `auto` to scale it with
+// the size of the images and how many there are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Spacing {
+    Pixels(u32),
+    Auto,
+}
+
+impl Spacing {
+    // The spacing in pixels for `count` images of `cell` pixels. Auto spacing
+    // starts at a fifth of the cell for a single image and shrinks with the
+    // square root of the count, so a few images get generous whitespace and
+    // many are packed densely, but never less than 2 pixels apart.
+    pub fn pixels(self, count: usize, cell: u32) -> u32 {
+        match self {
+            Spacing::Pixels(pixels) => pixels,
+            Spacing::Auto => {
+                let fraction = 0.2 / (count.max(1) as f64).sqrt();
+                ((cell as f64 * fraction).round() as u32).max(2)
+            }
+        }
+    }
+}
+
+// Spacing is recorded as it is given on the command line.
+impl Serialize for Spacing {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Spacing::Pixels(pixels) => serializer.serialize_u32(*pixels),
+            Spacing::Auto => serializer.serialize_str("auto"),
+        }
+    }
+}
+
+impl std::str::FromStr for Spacing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(Spacing::Auto);
+        }
+        s.parse()
+            .map(Spacing::Pixels)
+            .map_err(|_| format!("expected a number of pixels or `auto`, got `{s}`"))
+    }
+}
+
+// The settings a layout engine may use to place the images.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LayoutOptions {
+    pub orientation: Orientation,
+    pub image_width: u32,
+    pub image_height: u32,
+    pub top_margin: u32,
+    pub left_margin: u32,
+    pub spacing: u32,
+    // How far each image of a row or column overlaps the one before it.
+    pub overlap: u32,
+}
+
+// The rectangle of the output image an image is drawn into. If its size
+// differs from the size of the image, the image is resized to fit.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Tile {
+    // Round the edges of the tile to multiples of `n` pixels, keeping it at
+    // least `n` pixels wide and tall.
+    pub fn snap(&mut self, n: u32) {
+        let round = |v: u32| ((v as u64 + n as u64 / 2) / n as u64 * n as u64) as u32;
+        let (right, bottom) = (round(self.x + self.width), round(self.y + self.height));
+        self.x = round(self.x);
+        self.y = round(self.y);
+        self.width = right.saturating_sub(self.x).max(n);
+        self.height = bottom.saturating_sub(self.y).max(n);
+    }
+}
+
+// Text drawn on the output image with its top left corner at `x`, `y`, each
+// pixel of the font becoming a `scale` x `scale` square.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub x: u32,
+    pub y: u32,
+    pub scale: u32,
+    pub text: String,
+}
+
+// The size of the output image and one tile per image, in the same order as
+// the images, along with any labels drawn on top.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    pub width: u32,
+    pub height: u32,
+    pub tiles: Vec<Tile>,
+    #[serde(default)]
+    pub labels: Vec<Label>,
+}
+
+impl Layout {
+    // Snap the tiles and labels to a grid of `n` pixels, growing the output
+    // image to a multiple of `n` so that every tile still fits.
+    pub fn snap(&mut self, n: u32) -> Result<()> {
+        let up = |v: u32| fits((v as u64).div_ceil(n as u64) * n as u64);
+        for tile in &mut self.tiles {
+            tile.snap(n);
+        }
+        for label in &mut self.labels {
+            label.x = label.x / n * n;
+            label.y = label.y / n * n;
+        }
+        let right = self.tiles.iter().map(|t| t.x + t.width).max().unwrap_or(0);
+        let bottom = self.tiles.iter().map(|t| t.y + t.height).max().unwrap_or(0);
+        self.width = up(self.width.max(right))?;
+        self.height = up(self.height.max(bottom))?;
+        Ok(())
+    }
+}
+
+// The error for output dimensions that overflow.
+fn overflow() -> anyhow::Error {
+    anyhow!(
+        "The output image is too large: its dimensions overflow. Use a smaller \
+         --width/--height, less spacing or fewer images"
+    )
+}
+
+// A coordinate computed in u64, if it fits the output image's u32.
+fn fits(v: u64) -> Result<u32> {
+    u32::try_from(v).map_err(|_| overflow())
+}
+
+// A layout engine computes where each image goes given the (already resized)
+// image dimensions.
+pub trait LayoutEngine {
+    fn layout(&self, sizes: &[(u32, u32)], options: &LayoutOptions) -> Result<Layout>;
+}
+
+// The built in layout: a single column (portrait) or row (landscape) of images.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StackLayout;
+
+impl LayoutEngine for StackLayout {
+    fn layout(&self, sizes: &[(u32, u32)], options: &LayoutOptions) -> Result<Layout> {
+        // Everything is computed with checked arithmetic since many images or
+        // a huge spacing can overflow the output dimensions.
+        let n = sizes.len() as u32;
+        let along = |&(w, h): &(u32, u32)| match options.orientation {
+            Orientation::Portrait => h,
+            Orientation::Landscape => w,
+        };
+        ensure!(
+            sizes.iter().all(|size| along(size) > options.overlap),
+            "--overlap {} must be smaller than the images",
+            options.overlap
+        );
+        // Overlapping images pull the gaps between them negative.
+        let gaps = options
+            .spacing
+            .checked_mul(n.saturating_sub(1))
+            .zip(options.overlap.checked_mul(n.saturating_sub(1)));
+        let (width, height) = match options.orientation {
+            Orientation::Portrait => {
+                let w = options
+                    .left_margin
+                    .checked_mul(2)
+                    .and_then(|m| m.checked_add(options.image_width));
+                let h = sizes
+                    .iter()
+                    .try_fold(0u32, |a, b| a.checked_add(b.1))
+                    .zip(gaps)
+                    .and_then(|(hs, (gaps, overlaps))| hs.checked_add(gaps)?.checked_sub(overlaps))
+                    .zip(options.top_margin.checked_mul(2))
+                    .and_then(|(h, m)| h.checked_add(m));
+                (w.ok_or_else(overflow)?, h.ok_or_else(overflow)?)
+            }
+            Orientation::Landscape => {
+                let h = options
+                    .top_margin
+                    .checked_mul(2)
+                    .and_then(|m| m.checked_add(options.image_height));
+                let w = sizes
+                    .iter()
+                    .try_fold(0u32, |a, b| a.checked_add(b.0))
+                    .zip(gaps)
+                    .and_then(|(ws, (gaps, overlaps))| ws.checked_add(gaps)?.checked_sub(overlaps))
+                    .zip(options.left_margin.checked_mul(2))
+                    .and_then(|(w, m)| w.checked_add(m));
+                (w.ok_or_else(overflow)?, h.ok_or_else(overflow)?)
+            }
+        };
+
+        let mut tiles = Vec::with_capacity(sizes.len());
+        let mut x = options.left_margin;
+        let mut y = options.top_margin;
+        for &(w, h) in sizes {
+            tiles.push(Tile {
+                x,
+                y,
+                width: w,
+                height: h,
+            });
+            // Past the last image the position may leave the collage, so it
+            // saturates rather than overflowing.
+            match options.orientation {
+                Orientation::Portrait => {
+                    y = y
+                        .saturating_add(h - options.overlap)
+                        .saturating_add(options.spacing)
+                }
+                Orientation::Landscape => {
+                    x = x
+                        .saturating_add(w - options.overlap)
+                        .saturating_add(options.spacing)
+                }
+            }
+        }
+        Ok(Layout {
+            width,
+            height,
+            tiles,
+            labels: Vec::new(),
+        })
+    }
+}
+
+// A row of a `RowsLayout`: its label and how many of the images it holds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Row {
+    pub label: String,
+    pub count: usize,
+}
+
+// Split `n` images into rows of between `min` and `max` images, as few rows
+// as possible with as even a number of images as possible, so that no row is
+// left with an awkward one or two. Fewer than `min` images make a single row.
+pub fn balanced_rows(n: usize, min: usize, max: usize) -> Result<Vec<usize>> {
+    if n <= max {
+        return Ok(vec![n]);
+    }
+    let rows = n.div_ceil(max);
+    ensure!(
+        n / rows >= min,
+        "{n} images can't be split into rows of {min} to {max} images"
+    );
+    // The first `n % rows` rows get the extra images.
+    Ok((0..rows)
+        .map(|i| n / rows + usize::from(i < n % rows))
+        .collect())
+}
+
+// Labeled rows of images, e.g. one per month. The images fill the rows in
+// order, and the labels are drawn in a column to the left of the rows. A row
+// without images is still drawn, as an empty row of the image height.
+#[derive(Debug, Clone)]
+pub struct RowsLayout {
+    pub rows: Vec<Row>,
+}
+
+impl LayoutEngine for RowsLayout {
+    fn layout(&self, sizes: &[(u32, u32)], options: &LayoutOptions) -> Result<Layout> {
+        ensure!(
+            self.rows.iter().map(|row| row.count).sum::<usize>() == sizes.len(),
+            "The rows hold a different number of images than were given"
+        );
+        let spacing = options.spacing as u64;
+        // The labels are an eighth of the image height, but at least the size
+        // of the font.
+        let scale = (options.image_height / (8 * text::GLYPH_SIZE)).max(1);
+        let label_width = self
+            .rows
+            .iter()
+            .map(|row| text::width(&row.label, scale) as u64)
+            .max()
+            .unwrap_or(0);
+        let label_column = match label_width {
+            0 => 0,
+            w => w + spacing.max((text::GLYPH_SIZE * scale) as u64),
+        };
+
+        // Everything is computed in u64 and checked at the end, since many
+        // images or a huge spacing can overflow the output dimensions.
+        let mut tiles = Vec::with_capacity(sizes.len());
+        let mut labels = Vec::with_capacity(self.rows.len());
+        let mut rest = sizes;
+        let mut y = options.top_margin as u64;
+        let mut width = 0;
+        for (i, row) in self.rows.iter().enumerate() {
+            if i > 0 {
+                y += spacing;
+            }
+            let (images, next) = rest.split_at(row.count);
+            rest = next;
+            labels.push((options.left_margin as u64, y, row.label.clone()));
+            let mut x = options.left_margin as u64 + label_column;
+            for (j, &(w, h)) in images.iter().enumerate() {
+                if j > 0 {
+                    x += spacing;
+                }
+                tiles.push((x, y, w, h));
+                x += w as u64;
+            }
+            width = width.max(x);
+            y += images
+                .iter()
+                .map(|&(_, h)| h)
+                .max()
+                .unwrap_or(options.image_height) as u64;
+        }
+        let width = width + options.left_margin as u64;
+        let height = y + options.top_margin as u64;
+
+        Ok(Layout {
+            width: fits(width)?,
+            height: fits(height)?,
+            tiles: tiles
+                .into_iter()
+                .map(|(x, y, width, height)| {
+                    Ok(Tile {
+                        x: fits(x)?,
+                        y: fits(y)?,
+                        width,
+                        height,
+                    })
+                })
+                .collect::<Result<_>>()?,
+            labels: labels
+                .into_iter()
+                .map(|(x, y, text)| {
+                    Ok(Label {
+                        x: fits(x)?,
+                        y: fits(y)?,
+                        scale,
+                        text,
+                    })
+                })
+                .collect::<Result<_>>()?,
+        })
+    }
+}
+
+// A layout engine implemented by an external program. The program receives
+// a JSON object `{"images": [{"width", "height"}, ...], "options": {...}}` on
+// stdin and must write a JSON layout `{"width", "height", "tiles": [{"x",
+// "y", "width", "height"}, ...]}` to stdout.
+#[derive(Debug, Clone)]
+pub struct ExternalLayout {
+    pub command: String,
+}
+
+#[derive(Serialize)]
+struct ImageSize {
+    width: u32,
+    height: u32,
+}
+
+#[derive(Serialize)]
+struct ExternalRequest<'a> {
+    images: Vec<ImageSize>,
+    options: &'a LayoutOptions,
+}
+
+impl LayoutEngine for ExternalLayout {
+    fn layout(&self, sizes: &[(u32, u32)], options: &LayoutOptions) -> Result<Layout> {
+        let mut words = self.command.split_whitespace();
+        let Some(program) = words.next() else {
+            bail!("The layout engine command is empty");
+        };
+        let mut child = Command::new(program)
+            .args(words)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to start layout engine `{}`", self.command))?;
+
+        let request = ExternalRequest {
+            images: sizes
+                .iter()
+                .map(|&(width, height)| ImageSize { width, height })
+                .collect(),
+            options,
+        };
+        let mut stdin = child
+            .stdin
+            .take()
+            .context("Failed to open layout engine stdin")?;
+        serde_json::to_writer(&mut stdin, &request)?;
+        stdin.flush()?;
+        drop(stdin);
+
+        let output = child.wait_with_output()?;
+        ensure!(
+            output.status.success(),
+            "Layout engine `{}` failed with {}",
+            self.command,
+            output.status
+        );
+        let layout: Layout = serde_json::from_slice(&output.stdout)
+            .context("The layout engine returned an invalid layout")?;
+        ensure!(
+            layout.tiles.len() == sizes.len(),
+            "The layout engine returned {} tiles for {} images",
+            layout.tiles.len(),
+            sizes.len()
+        );
+        for tile in &layout.tiles {
+            ensure!(
+                tile.width > 0
+                    && tile.height > 0
+                    && tile.x as u64 + tile.width as u64 <= layout.width as u64
+                    && tile.y as u64 + tile.height as u64 <= layout.height as u64,
+                "The layout engine returned a tile outside of the output image: {tile:?}"
+            );
+        }
+        Ok(layout)
+    }
+}
+
+// What a grid does with a last row that is short of images.
+#[derive(ValueEnum, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum LastRow {
+    // Aligned to the left, like the other rows.
+    Left,
+    // Centered under the other rows.
+    Center,
+    // Stretched to the width of the other rows.
+    Justify,
+    // Left out, along with its images.
+    Hide,
+}
+
+// A grid of `columns` columns, filled row by row, each row as tall as its
+// tallest image and as wide as its images, which are the image width unless
+// they keep their aspect ratio. The grid doesn't leave out the images of a
+// short last row itself: with `LastRow::Hide` they must not be given to it.
+#[derive(Debug, Clone, Copy)]
+pub struct GridLayout {
+    pub columns: usize,
+    pub last_row: LastRow,
+}
+
+impl LayoutEngine for GridLayout {
+    fn layout(&self, sizes: &[(u32, u32)], options: &LayoutOptions) -> Result<Layout> {
+        let columns = self.columns.clamp(1, sizes.len().max(1)) as u64;
+        let spacing = options.spacing as u64;
+        let left = options.left_margin as u64;
+        let width_of = |row: &[(u32, u32)]| {
+            let gaps = (row.len() as u64).saturating_sub(1) * spacing;
+            row.iter().map(|&(w, _)| w as u64).sum::<u64>() + gaps
+        };
+        // The first row is always full, so the widest row is a full one.
+        let row_width = sizes
+            .chunks(columns as usize)
+            .map(width_of)
+            .max()
+            .unwrap_or(0);
+
+        let mut tiles = Vec::with_capacity(sizes.len());
+        let mut y = options.top_margin as u64;
+        let rows = sizes.chunks(columns as usize);
+        let count = rows.len();
+        for (i, row) in rows.enumerate() {
+            if i > 0 {
+                y += spacing;
+            }
+            let missing = columns - row.len() as u64;
+            let last = i + 1 == count && missing > 0;
+            let (mut x, width) = match self.last_row {
+                LastRow::Center if last => (left + (row_width - width_of(row)) / 2, None),
+                LastRow::Justify if last => {
+                    let gaps = (row.len() as u64 - 1) * spacing;
+                    (left, Some((row_width - gaps) / row.len() as u64))
+                }
+                _ => (left, None),
+            };
+            for &(w, h) in row {
+                let w = width.map_or(w as u64, |width| width);
+                tiles.push((x, y, fits(w)?, h));
+                x += w + spacing;
+            }
+            y += row.iter().map(|&(_, h)| h).max().unwrap_or(0) as u64;
+        }
+
+        Ok(Layout {
+            width: fits(row_width + 2 * left)?,
+            height: fits(y + options.top_margin as u64)?,
+            tiles: tiles
+                .into_iter()
+                .map(|(x, y, width, height)| {
+                    Ok(Tile {
+                        x: fits(x)?,
+                        y: fits(y)?,
+                        width,
+                        height,
+                    })
+                })
+                .collect::<Result<_>>()?,
+            labels: Vec::new(),
+        })
+    }
+}
+
+// Cells of the image size filling a `width` x `height` canvas row by row, the
+// last row and column cut off by the edges of the canvas. It takes exactly
+// `cells` images, so the images are repeated to fill it.
+#[derive(Debug, Clone, Copy)]
+pub struct FillLayout {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FillLayout {
+    // The number of columns and rows it takes to fill the canvas.
+    fn grid(&self, options: &LayoutOptions) -> (u64, u64) {
+        let count = |canvas: u32, margin: u32, cell: u32| {
+            let step = cell as u64 + options.spacing as u64;
+            (canvas.saturating_sub(margin) as u64).div_ceil(step).max(1)
+        };
+        (
+            count(self.width, options.left_margin, options.image_width),
+            count(self.height, options.top_margin, options.image_height),
+        )
+    }
+
+    // The number of images it takes to fill the canvas.
+    pub fn cells(&self, options: &LayoutOptions) -> usize {
+        let (columns, rows) = self.grid(options);
+        (columns * rows) as usize
+    }
+}
+
+impl LayoutEngine for FillLayout {
+    fn layout(&self, sizes: &[(u32, u32)], options: &LayoutOptions) -> Result<Layout> {
+        ensure!(
+            sizes.len() == self.cells(options),
+            "Filling the canvas takes {} images, but {} were given",
+            self.cells(options),
+            sizes.len()
+        );
+        let (columns, _) = self.grid(options);
+        let step_x = options.image_width as u64 + options.spacing as u64;
+        let step_y = options.image_height as u64 + options.spacing as u64;
+        let tiles = (0..sizes.len() as u64)
+            .map(|i| {
+                Ok(Tile {
+                    x: fits(options.left_margin as u64 + i % columns * step_x)?,
+                    y: fits(options.top_margin as u64 + i / columns * step_y)?,
+                    width: options.image_width,
+                    height: options.image_height,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Layout {
+            width: self.width,
+            height: self.height,
+            tiles,
+            labels: Vec::new(),
+        })
+    }
+}
+
+// Sections of images, e.g. of a mood board, each a header above a grid of its
+// images with `columns` images per row. The images fill the sections in order,
+// and a section without images is just its header.
+#[derive(Debug, Clone)]
+pub struct SectionsLayout {
+    pub sections: Vec<Row>,
+    pub columns: usize,
+}
+
+impl LayoutEngine for SectionsLayout {
+    fn layout(&self, sizes: &[(u32, u32)], options: &LayoutOptions) -> Result<Layout> {
+        ensure!(
+            self.sections
+                .iter()
+                .map(|section| section.count)
+                .sum::<usize>()
+                == sizes.len(),
+            "The sections hold a different number of images than were given"
+        );
+        let spacing = options.spacing as u64;
+        let scale = (options.image_height / (4 * text::GLYPH_SIZE)).max(1);
+        let header = (text::GLYPH_SIZE * scale) as u64;
+        let left = options.left_margin as u64;
+
+        let mut tiles = Vec::with_capacity(sizes.len());
+        let mut labels = Vec::with_capacity(self.sections.len());
+        let mut rest = sizes;
+        let mut y = options.top_margin as u64;
+        let mut width = left;
+        for (i, section) in self.sections.iter().enumerate() {
+            // Sections are set apart by twice the spacing between images.
+            if i > 0 {
+                y += 2 * spacing.max(header / 2);
+            }
+            labels.push((left, y, section.label.clone()));
+            width = width.max(left + text::width(&section.label, scale) as u64);
+            y += header + spacing.max(header / 2);
+
+            let (images, next) = rest.split_at(section.count);
+            rest = next;
+            for (j, row) in images.chunks(self.columns.max(1)).enumerate() {
+                if j > 0 {
+                    y += spacing;
+                }
+                let mut x = left;
+                for (k, &(w, h)) in row.iter().enumerate() {
+                    if k > 0 {
+                        x += spacing;
+                    }
+                    tiles.push((x, y, w, h));
+                    x += w as u64;
+                }
+                width = width.max(x);
+                y += row.iter().map(|&(_, h)| h).max().unwrap_or(0) as u64;
+            }
+        }
+        let width = width + options.left_margin as u64;
+        let height = y + options.top_margin as u64;
+
+        Ok(Layout {
+            width: fits(width)?,
+            height: fits(height)?,
+            tiles: tiles
+                .into_iter()
+                .map(|(x, y, width, height)| {
+                    Ok(Tile {
+                        x: fits(x)?,
+                        y: fits(y)?,
+                        width,
+                        height,
+                    })
+                })
+                .collect::<Result<_>>()?,
+            labels: labels
+                .into_iter()
+                .map(|(x, y, text)| {
+                    Ok(Label {
+                        x: fits(x)?,
+                        y: fits(y)?,
+                        scale,
+                        text,
+                    })
+                })
+                .collect::<Result<_>>()?,
+        })
+    }
+}
+
+// Before/after pairs of images, a pair per row. Side by side the before images
+// form the left column and the after images the right one; split along the
+// diagonal both images of a pair share a tile the size of the before image.
+// With `labels` the columns are headed "Before" and "After".
+#[derive(Debug, Clone, Copy)]
+pub struct CompareLayout {
+    pub diagonal: bool,
+    pub labels: bool,
+}
+
+impl LayoutEngine for CompareLayout {
+    fn layout(&self, sizes: &[(u32, u32)], options: &LayoutOptions) -> Result<Layout> {
+        ensure!(
+            sizes.len().is_multiple_of(2),
+            "Before/after images must come in pairs"
+        );
+        let spacing = options.spacing as u64;
+        let before_width = sizes.iter().step_by(2).map(|&(w, _)| w as u64).max();
+        let after_width = sizes
+            .iter()
+            .skip(1)
+            .step_by(2)
+            .map(|&(w, _)| w as u64)
+            .max();
+        let (before_width, after_width) = (before_width.unwrap_or(0), after_width.unwrap_or(0));
+        let (after_x, right) = if self.diagonal {
+            (0, before_width)
+        } else {
+            (before_width + spacing, before_width + spacing + after_width)
+        };
+        let left = options.left_margin as u64;
+
+        let mut y = options.top_margin as u64;
+        let mut labels = Vec::new();
+        if self.labels {
+            let scale = (options.image_height / (8 * text::GLYPH_SIZE)).max(1);
+            let after = "After";
+            let after_label_x = if self.diagonal {
+                (left + right).saturating_sub(text::width(after, scale) as u64)
+            } else {
+                left + after_x
+            };
+            labels.push((left, y, scale, "Before"));
+            labels.push((after_label_x, y, scale, after));
+            y += (text::GLYPH_SIZE * scale) as u64 + spacing.max((text::GLYPH_SIZE * scale) as u64);
+        }
+
+        let mut tiles = Vec::with_capacity(sizes.len());
+        for (i, pair) in sizes.chunks(2).enumerate() {
+            if i > 0 {
+                y += spacing;
+            }
+            let (before, after) = (pair[0], pair[1]);
+            tiles.push((left, y, before.0, before.1));
+            if self.diagonal {
+                tiles.push((left, y, before.0, before.1));
+                y += before.1 as u64;
+            } else {
+                tiles.push((left + after_x, y, after.0, after.1));
+                y += before.1.max(after.1) as u64;
+            }
+        }
+        let width = left + right + options.left_margin as u64;
+        let height = y + options.top_margin as u64;
+
+        Ok(Layout {
+            width: fits(width)?,
+            height: fits(height)?,
+            tiles: tiles
+                .into_iter()
+                .map(|(x, y, width, height)| {
+                    Ok(Tile {
+                        x: fits(x)?,
+                        y: fits(y)?,
+                        width,
+                        height,
+                    })
+                })
+                .collect::<Result<_>>()?,
+            labels: labels
+                .into_iter()
+                .map(|(x, y, scale, text)| {
+                    Ok(Label {
+                        x: fits(x)?,
+                        y: fits(y)?,
+                        scale,
+                        text: text.to_string(),
+                    })
+                })
+                .collect::<Result<_>>()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(orientation: Orientation) -> LayoutOptions {
+        LayoutOptions {
+            orientation,
+            image_width: 100,
+            image_height: 50,
+            top_margin: 10,
+            left_margin: 5,
+            spacing: 4,
+            overlap: 0,
+        }
+    }
+
+    fn tile(x: u32, y: u32, width: u32, height: u32) -> Tile {
+        Tile {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn stack_portrait() {
+        let layout = StackLayout
+            .layout(&[(100, 50), (100, 30)], &options(Orientation::Portrait))
+            .unwrap();
+        assert_eq!((layout.width, layout.height), (110, 104));
+        assert_eq!(
+            layout.tiles,
+            vec![tile(5, 10, 100, 50), tile(5, 64, 100, 30)]
+        );
+    }
+
+    #[test]
+    fn stack_landscape() {
+        let layout = StackLayout
+            .layout(&[(60, 50), (40, 50)], &options(Orientation::Landscape))
+            .unwrap();
+        assert_eq!((layout.width, layout.height), (114, 70));
+        assert_eq!(
+            layout.tiles,
+            vec![tile(5, 10, 60, 50), tile(69, 10, 40, 50)]
+        );
+    }
+
+    #[test]
+    fn stack_overlap() {
+        let options = LayoutOptions {
+            spacing: 0,
+            overlap: 10,
+            ..options(Orientation::Portrait)
+        };
+        let layout = StackLayout
+            .layout(&[(100, 50), (100, 50)], &options)
+            .unwrap();
+        assert_eq!(layout.height, 110);
+        assert_eq!(layout.tiles[1], tile(5, 50, 100, 50));
+    }
+
+    #[test]
+    fn stack_overlap_must_be_smaller_than_every_image() {
+        let options = LayoutOptions {
+            overlap: 50,
+            ..options(Orientation::Portrait)
+        };
+        assert!(StackLayout.layout(&[(100, 50)], &options).is_err());
+        assert!(StackLayout
+            .layout(&[(100, 80), (100, 50)], &options)
+            .is_err());
+    }
+
+    #[test]
+    fn stack_overflow() {
+        let options = options(Orientation::Portrait);
+        assert!(StackLayout
+            .layout(&[(100, u32::MAX - 10)], &options)
+            .is_err());
+        assert!(StackLayout
+            .layout(&[(100, u32::MAX / 2), (100, u32::MAX / 2)], &options)
+            .is_err());
+    }
+
+    fn grid(columns: usize, last_row: LastRow) -> GridLayout {
+        GridLayout { columns, last_row }
+    }
+
+    #[test]
+    fn grid_layout() {
+        let layout = grid(2, LastRow::Left)
+            .layout(&[(100, 50); 3], &options(Orientation::Portrait))
+            .unwrap();
+        assert_eq!((layout.width, layout.height), (214, 124));
+        assert_eq!(
+            layout.tiles,
+            [
+                tile(5, 10, 100, 50),
+                tile(109, 10, 100, 50),
+                tile(5, 64, 100, 50)
+            ]
+        );
+    }
+
+    #[test]
+    fn grid_last_row() {
+        let options = options(Orientation::Portrait);
+        let last = |last_row| {
+            grid(2, last_row)
+                .layout(&[(100, 50); 3], &options)
+                .unwrap()
+                .tiles[2]
+        };
+        assert_eq!(last(LastRow::Center), tile(57, 64, 100, 50));
+        assert_eq!(last(LastRow::Justify), tile(5, 64, 204, 50));
+    }
+
+    #[test]
+    fn grid_of_images_of_their_own_width() {
+        let layout = grid(2, LastRow::Left)
+            .layout(
+                &[(60, 50), (120, 50), (80, 50), (90, 50)],
+                &options(Orientation::Landscape),
+            )
+            .unwrap();
+        assert_eq!(layout.width, 194);
+        assert_eq!(
+            layout.tiles,
+            [
+                tile(5, 10, 60, 50),
+                tile(69, 10, 120, 50),
+                tile(5, 64, 80, 50),
+                tile(89, 64, 90, 50)
+            ]
+        );
+    }
+
+    #[test]
+    fn grid_has_no_more_columns_than_images() {
+        let layout = grid(5, LastRow::Center)
+            .layout(&[(100, 50); 2], &options(Orientation::Portrait))
+            .unwrap();
+        assert_eq!(layout.width, 214);
+        assert_eq!(layout.tiles[0].x, 5);
+    }
+
+    #[test]
+    fn fill_layout_covers_the_canvas() {
+        let engine = FillLayout {
+            width: 250,
+            height: 120,
+        };
+        let options = options(Orientation::Portrait);
+        assert_eq!(engine.cells(&options), 9);
+        let layout = engine.layout(&[(100, 50); 9], &options).unwrap();
+        assert_eq!((layout.width, layout.height), (250, 120));
+        assert_eq!(layout.tiles[4], tile(109, 64, 100, 50));
+        // The last column and row run past the edges and are cut off.
+        assert_eq!(layout.tiles[8], tile(213, 118, 100, 50));
+    }
+
+    #[test]
+    fn fill_layout_takes_exactly_its_cells() {
+        let engine = FillLayout {
+            width: 250,
+            height: 120,
+        };
+        assert!(engine
+            .layout(&[(100, 50); 8], &options(Orientation::Portrait))
+            .is_err());
+    }
+
+    #[test]
+    fn balanced_rows_are_even() {
+        assert_eq!(balanced_rows(5, 2, 6).unwrap(), [5]);
+        assert_eq!(balanced_rows(10, 2, 4).unwrap(), [4, 3, 3]);
+        assert_eq!(balanced_rows(12, 3, 4).unwrap(), [4, 4, 4]);
+        // Not 4 + 1: the last row isn't left with a single image.
+        assert_eq!(balanced_rows(5, 2, 4).unwrap(), [3, 2]);
+    }
+
+    #[test]
+    fn balanced_rows_of_few_images() {
+        assert_eq!(balanced_rows(1, 3, 5).unwrap(), [1]);
+    }
+
+    #[test]
+    fn balanced_rows_that_cant_be() {
+        assert!(balanced_rows(6, 4, 5).is_err());
+    }
+
+    fn row(label: &str, count: usize) -> Row {
+        Row {
+            label: label.to_string(),
+            count,
+        }
+    }
+
+    #[test]
+    fn rows_layout() {
+        let engine = RowsLayout {
+            rows: vec![row("", 2), row("", 0), row("", 1)],
+        };
+        let layout = engine
+            .layout(
+                &[(100, 50), (60, 40), (80, 50)],
+                &options(Orientation::Landscape),
+            )
+            .unwrap();
+        assert_eq!((layout.width, layout.height), (174, 178));
+        // The empty row still takes the image height.
+        assert_eq!(
+            layout.tiles,
+            [
+                tile(5, 10, 100, 50),
+                tile(109, 10, 60, 40),
+                tile(5, 118, 80, 50)
+            ]
+        );
+        assert_eq!(
+            layout
+                .labels
+                .iter()
+                .map(|label| (label.x, label.y))
+                .collect::<Vec<_>>(),
+            [(5, 10), (5, 64), (5, 118)]
+        );
+    }
+
+    #[test]
+    fn rows_layout_leaves_a_column_for_the_labels() {
+        let engine = RowsLayout {
+            rows: vec![row("Jan", 1), row("February", 1)],
+        };
+        let layout = engine
+            .layout(&[(100, 50), (100, 50)], &options(Orientation::Landscape))
+            .unwrap();
+        let column = text::width("February", 1) + text::GLYPH_SIZE;
+        assert_eq!(layout.tiles[0].x, 5 + column);
+        assert_eq!(layout.width, 5 + column + 100 + 5);
+    }
+
+    #[test]
+    fn rows_layout_needs_every_image() {
+        let engine = RowsLayout {
+            rows: vec![row("", 2)],
+        };
+        assert!(engine
+            .layout(&[(100, 50)], &options(Orientation::Landscape))
+            .is_err());
+    }
+
+    #[test]
+    fn compare_side_by_side() {
+        let engine = CompareLayout {
+            diagonal: false,
+            labels: false,
+        };
+        let layout = engine
+            .layout(
+                &[(100, 50), (80, 60), (90, 40), (100, 40)],
+                &options(Orientation::Portrait),
+            )
+            .unwrap();
+        assert_eq!((layout.width, layout.height), (214, 124));
+        assert_eq!(
+            layout.tiles,
+            [
+                tile(5, 10, 100, 50),
+                tile(109, 10, 80, 60),
+                tile(5, 74, 90, 40),
+                tile(109, 74, 100, 40),
+            ]
+        );
+    }
+
+    #[test]
+    fn compare_diagonal_shares_the_tile() {
+        let engine = CompareLayout {
+            diagonal: true,
+            labels: false,
+        };
+        let layout = engine
+            .layout(&[(100, 50), (80, 60)], &options(Orientation::Portrait))
+            .unwrap();
+        assert_eq!((layout.width, layout.height), (110, 70));
+        assert_eq!(layout.tiles, [tile(5, 10, 100, 50), tile(5, 10, 100, 50)]);
+    }
+
+    #[test]
+    fn compare_labels_head_the_columns() {
+        let engine = CompareLayout {
+            diagonal: false,
+            labels: true,
+        };
+        let layout = engine
+            .layout(&[(100, 50), (100, 50)], &options(Orientation::Portrait))
+            .unwrap();
+        let texts: Vec<_> = layout
+            .labels
+            .iter()
+            .map(|label| (label.text.as_str(), label.x, label.y))
+            .collect();
+        assert_eq!(texts, [("Before", 5, 10), ("After", 109, 10)]);
+        assert_eq!(layout.tiles[0].y, 10 + 2 * text::GLYPH_SIZE);
+    }
+
+    #[test]
+    fn compare_needs_pairs() {
+        let engine = CompareLayout {
+            diagonal: false,
+            labels: false,
+        };
+        assert!(engine
+            .layout(&[(100, 50)], &options(Orientation::Portrait))
+            .is_err());
+    }
+
+    #[test]
+    fn sections_layout() {
+        let engine = SectionsLayout {
+            sections: vec![row("A", 3), row("B", 1)],
+            columns: 2,
+        };
+        let layout = engine
+            .layout(&[(100, 50); 4], &options(Orientation::Portrait))
+            .unwrap();
+        assert_eq!((layout.width, layout.height), (214, 206));
+        assert_eq!(
+            layout.tiles,
+            [
+                tile(5, 22, 100, 50),
+                tile(109, 22, 100, 50),
+                tile(5, 76, 100, 50),
+                tile(5, 146, 100, 50),
+            ]
+        );
+        let headers: Vec<_> = layout
+            .labels
+            .iter()
+            .map(|label| (label.text.as_str(), label.x, label.y))
+            .collect();
+        assert_eq!(headers, [("A", 5, 10), ("B", 5, 134)]);
+    }
+
+    #[test]
+    fn sections_layout_needs_every_image() {
+        let engine = SectionsLayout {
+            sections: vec![row("A", 1)],
+            columns: 2,
+        };
+        assert!(engine
+            .layout(&[(100, 50); 2], &options(Orientation::Portrait))
+            .is_err());
+    }
+
+    #[test]
+    fn external_layout_needs_a_command() {
+        let engine = ExternalLayout {
+            command: " ".to_string(),
+        };
+        assert!(engine
+            .layout(&[(100, 50)], &options(Orientation::Portrait))
+            .is_err());
+    }
+}
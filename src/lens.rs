@@ -0,0 +1,120 @@
+use image::{Rgba, RgbaImage};
+
+use crate::color;
+
+// The strength of the lens correction for a photo taken at `focal_length`
+// (35mm equivalent): none from 35mm up, rising to full at 13mm and wider,
+// where the vignetting and barrel distortion of phone and action cameras are
+// the worst.
+pub fn strength_for(focal_length: f64) -> f32 {
+    ((35.0 - focal_length) / 22.0).clamp(0.0, 1.0) as f32
+}
+
+// Brighten the corners and straighten the barrel distortion of `image`, both
+// growing with the square of the distance from the center. `strength` is from
+// 0 (none) to 1, where the corners are brightened by 60% in linear light and
+// pulled in by 8%.
+pub fn correct(image: &RgbaImage, strength: f32) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    let (cx, cy) = (width as f32 / 2.0, height as f32 / 2.0);
+    let radius2 = (cx * cx + cy * cy).max(1.0);
+    let distortion = -0.08 * strength;
+    let vignette = 0.6 * strength;
+    let decode: Vec<f32> = (0..=255u8)
+        .map(|v| color::srgb_to_linear(v as f32 / 255.0))
+        .collect();
+
+    let mut out = RgbaImage::new(width, height);
+    for (x, y, pixel) in out.enumerate_pixels_mut() {
+        let (dx, dy) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+        let r2 = (dx * dx + dy * dy) / radius2;
+        let scale = 1.0 + distortion * r2;
+        let source = sample(image, cx + dx * scale - 0.5, cy + dy * scale - 0.5);
+        let gain = 1.0 + vignette * r2;
+        let mut corrected = [0; 4];
+        for c in 0..3 {
+            let linear = decode[source[c].round() as usize] * gain;
+            corrected[c] = (color::linear_to_srgb(linear.min(1.0)) * 255.0).round() as u8;
+        }
+        corrected[3] = source[3].round() as u8;
+        *pixel = Rgba(corrected);
+    }
+    out
+}
+
+// The color of `image` at (x, y), interpolated between the nearest pixels.
+fn sample(image: &RgbaImage, x: f32, y: f32) -> [f32; 4] {
+    let (width, height) = (image.width() as f32, image.height() as f32);
+    let x = x.clamp(0.0, width - 1.0);
+    let y = y.clamp(0.0, height - 1.0);
+    let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+    let (x1, y1) = (
+        (x0 + 1).min(image.width() - 1),
+        (y0 + 1).min(image.height() - 1),
+    );
+    let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+    let mut out = [0.0; 4];
+    for (c, o) in out.iter_mut().enumerate() {
+        let top =
+            image.get_pixel(x0, y0)[c] as f32 * (1.0 - fx) + image.get_pixel(x1, y0)[c] as f32 * fx;
+        let bottom =
+            image.get_pixel(x0, y1)[c] as f32 * (1.0 - fx) + image.get_pixel(x1, y1)[c] as f32 * fx;
+        *o = top * (1.0 - fy) + bottom * fy;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strength_grows_as_the_lens_widens() {
+        assert_eq!(strength_for(50.0), 0.0);
+        assert_eq!(strength_for(35.0), 0.0);
+        assert_eq!(strength_for(24.0), 0.5);
+        assert_eq!(strength_for(13.0), 1.0);
+        assert_eq!(strength_for(8.0), 1.0);
+    }
+
+    #[test]
+    fn no_strength_is_no_change() {
+        let image = RgbaImage::from_fn(9, 7, |x, y| Rgba([x as u8 * 20, y as u8 * 30, 5, 255]));
+        assert_eq!(correct(&image, 0.0), image);
+    }
+
+    #[test]
+    fn brightens_the_corners_more_than_the_center() {
+        let image = RgbaImage::from_pixel(20, 20, Rgba([100, 100, 100, 255]));
+        let corrected = correct(&image, 1.0);
+        let center = corrected.get_pixel(10, 10)[0];
+        let corner = corrected.get_pixel(0, 0)[0];
+        assert!(center.abs_diff(100) <= 1, "{center}");
+        // The middle of the corner pixel is 0.9 of the way to the corner, so
+        // brightened by 54% in linear light.
+        let expected = color::linear_to_srgb(color::srgb_to_linear(100.0 / 255.0) * 1.54) * 255.0;
+        assert!((corner as f32 - expected).abs() <= 1.0, "{corner}");
+    }
+
+    #[test]
+    fn pulls_the_edges_in() {
+        // A white border one pixel wide is pushed out of the image.
+        let image = RgbaImage::from_fn(50, 50, |x, y| {
+            let v = if x == 0 || y == 0 || x == 49 || y == 49 {
+                255
+            } else {
+                0
+            };
+            Rgba([v, v, v, 255])
+        });
+        let corrected = correct(&image, 1.0);
+        assert!(corrected.get_pixel(0, 0)[0] < 128);
+    }
+
+    #[test]
+    fn samples_between_pixels() {
+        let image = RgbaImage::from_fn(2, 1, |x, _| Rgba([x as u8 * 200, 0, 0, 255]));
+        assert_eq!(sample(&image, 0.25, 0.0), [50.0, 0.0, 0.0, 255.0]);
+        assert_eq!(sample(&image, -3.0, 9.0), [0.0, 0.0, 0.0, 255.0]);
+    }
+}
@@ -0,0 +1,113 @@
+use anyhow::{bail, ensure, Context, Result};
+use directories::UserDirs;
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process::{self, Command, Stdio},
+};
+
+// The images of an album of the system photo library, in a directory.
+#[derive(Debug)]
+pub struct Album {
+    pub dir: PathBuf,
+    // Whether `dir` was exported for the collage, and is removed when the
+    // album is dropped.
+    pub exported: bool,
+}
+
+impl Drop for Album {
+    fn drop(&mut self) {
+        if self.exported {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+}
+
+// Open the album `name`. On macOS it is exported from Photos to a temporary
+// directory, as JPEGs with `osxphotos` if it is installed or else with
+// AppleScript. Elsewhere it is the folder of that name in the Pictures
+// directory.
+pub fn open(name: &str) -> Result<Album> {
+    if !cfg!(target_os = "macos") {
+        return Ok(Album {
+            dir: folder(name)?,
+            exported: false,
+        });
+    }
+    let album = Album {
+        dir: std::env::temp_dir().join(format!("collage-album-{}", process::id())),
+        exported: true,
+    };
+    fs::create_dir_all(&album.dir)
+        .with_context(|| format!("Failed to create {}", album.dir.display()))?;
+    let dir = album.dir.display().to_string();
+    let osxphotos = Command::new("osxphotos")
+        .args(["export", &dir, "--album", name, "--convert-to-jpeg"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .status();
+    let status = match osxphotos {
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+            let script = format!(
+                "tell application \"Photos\" to export (get media items of (first album whose \
+                 name is {})) to (POSIX file {} as alias)",
+                quote(name),
+                quote(&dir)
+            );
+            Command::new("osascript")
+                .args(["-e", &script])
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .status()
+                .context("Failed to run osascript")?
+        }
+        status => status.context("Failed to run osxphotos")?,
+    };
+    ensure!(
+        status.success(),
+        "Failed to export the album `{name}` from Photos ({status})"
+    );
+    Ok(album)
+}
+
+// The folder of the album `name` in the Pictures directory, matched
+// regardless of case if there isn't one with exactly that name.
+pub fn folder(name: &str) -> Result<PathBuf> {
+    let pictures = UserDirs::new()
+        .and_then(|dirs| dirs.picture_dir().map(Path::to_path_buf))
+        .context("There is no Pictures directory to find albums in")?;
+    let exact = pictures.join(name);
+    if exact.is_dir() {
+        return Ok(exact);
+    }
+    let mut albums: Vec<PathBuf> = fs::read_dir(&pictures)
+        .with_context(|| format!("Failed to read {}", pictures.display()))?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    albums.sort();
+    let album_name = |path: &PathBuf| {
+        path.file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string()
+    };
+    if let Some(album) = albums
+        .iter()
+        .find(|album| album_name(album).eq_ignore_ascii_case(name))
+    {
+        return Ok(album.clone());
+    }
+    let names: Vec<String> = albums.iter().map(album_name).collect();
+    if names.is_empty() {
+        bail!("There is no album `{name}` in {}", pictures.display());
+    }
+    bail!(
+        "There is no album `{name}` in {}, the albums are: {}",
+        pictures.display(),
+        names.join(", ")
+    )
+}
@@ -1,27 +1,189 @@
-use anyhow::{ensure, Context, Result};
-use clap::{Parser, ValueEnum};
+use anyhow::{bail, ensure, Context, Result};
+use clap::{FromArgMatches, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
 use directories::UserDirs;
-use image::{imageops::FilterType, DynamicImage, GenericImage, Rgba, RgbaImage};
-use log::{info, warn};
+use image::{
+    imageops, imageops::FilterType, io::Limits, DynamicImage, ImageFormat, Rgba, RgbaImage,
+};
+use log::{debug, info, warn, LevelFilter};
+use rand::{seq::SliceRandom, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::{
-    fs::metadata,
-    io::{self, Write},
-    path::PathBuf,
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    ffi::{OsStr, OsString},
+    fs::{self, File},
+    hash::{Hash, Hasher},
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 use walkdir::WalkDir;
 
-#[allow(dead_code)]
-#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-enum Orientation {
-    Portrait,
-    Landscape,
+mod bench;
+mod bursts;
+#[cfg(feature = "c2pa")]
+mod c2pa;
+mod captions;
+mod checkpoint;
+mod color;
+mod compare;
+mod config;
+mod cutout;
+mod debug_overlay;
+mod decode;
+mod denoise;
+mod desktop;
+mod eink;
+mod estimate;
+mod generative;
+#[cfg(feature = "gpu")]
+mod gpu;
+mod histogram;
+mod image_map;
+mod jobs;
+mod layout;
+mod lens;
+mod library;
+mod manifest;
+mod metadata;
+mod moodboard;
+#[cfg(feature = "notify")]
+mod notify;
+mod output;
+mod placeholder;
+mod qr;
+mod quantize;
+mod ratings;
+mod review;
+mod sidecar;
+mod sort;
+mod split;
+mod text;
+mod texture;
+mod tonemap;
+mod trim;
+#[cfg(feature = "upload")]
+mod upload;
+mod zorder;
+
+use captions::Captions;
+use checkpoint::{Checkpoint, State};
+use color::{Letterbox, ResizeFilter, WhiteBalance};
+use compare::CompareStyle;
+use estimate::Estimate;
+use generative::Background;
+use histogram::{Histogram, HistogramMode};
+use image_map::MapFormat;
+use layout::{
+    CompareLayout, ExternalLayout, FillLayout, GridLayout, Label, LastRow, Layout, LayoutEngine,
+    LayoutOptions, Orientation, Row, RowsLayout, SectionsLayout, Spacing, StackLayout, Tile,
+};
+use manifest::Manifest;
+use moodboard::MoodBoard;
+use qr::QrPosition;
+use quantize::Dither;
+use sidecar::Sidecar;
+use texture::Texture;
+use tonemap::ToneMap;
+use zorder::ZOrder;
+
+#[derive(ValueEnum, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+enum LogFormat {
+    Text,
+    Json,
 }
 
-#[derive(Parser, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Subcommand, Debug, Clone, PartialEq, Eq)]
+enum Command {
+    /// Print a shell completion script to stdout.
+    Completions {
+        /// The shell to generate the completion script for.
+        shell: Shell,
+    },
+    /// Create the collages described in a jobs file.
+    Run {
+        /// The TOML file describing the jobs.
+        jobs: PathBuf,
+    },
+    /// Cut an image into a grid of separate tiles, e.g. for a multi-post
+    /// Instagram grid. The tiles are saved as `<name>_<num>.png`, numbered
+    /// left to right and top to bottom.
+    Split {
+        /// The image to split.
+        image: PathBuf,
+        /// The number of columns in the grid.
+        #[arg(short, long, default_value_t = 3, value_parser = clap::value_parser!(u32).range(1..))]
+        columns: u32,
+        /// The number of rows in the grid.
+        #[arg(short, long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+        rows: u32,
+        /// Pixels each tile extends into its neighbors, as bleed.
+        #[arg(long, default_value_t = 0)]
+        overlap: u32,
+        /// The directory to save the tiles to.
+        /// If not specified, the default is the downloads directory.
+        #[arg(short = 'O', long)]
+        output: Option<PathBuf>,
+    },
+    /// Add the images that are new in the image directory of a collage made
+    /// with `--manifest` to it, in the space left in its layout or in new
+    /// space, without making it again.
+    Append {
+        /// The collage to add the new images to.
+        collage: PathBuf,
+    },
+    /// Render a directory with each resize filter, on one thread and on all of
+    /// them, and report how long each took and how close it came to the
+    /// sharpest filter in linear light.
+    Bench {
+        /// The directory with the images to render.
+        dir: PathBuf,
+        /// Options for every render, after `--`, e.g. `-- --width 400`.
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+    /// Keep making the collage, on a schedule, whenever its images change, or
+    /// both, e.g. for a photo frame or the desktop. The options are read again
+    /// for every collage, so changes to the config file take effect without a
+    /// restart. The collage needs an `--output` file, which each one replaces,
+    /// and with `--upload` (in builds with the `upload` feature) each one is
+    /// uploaded too.
+    Daemon {
+        /// How often to make the collage, e.g. `30m`, `1h` or `1d`.
+        #[arg(long, value_parser = parse_duration, required_unless_present = "watch")]
+        every: Option<Duration>,
+        /// Make the collage whenever a file in its image directory is added,
+        /// removed or changed, which is checked every 5 seconds.
+        #[arg(long, default_value_t = false)]
+        watch: bool,
+        /// The config file with the options of the collage. If not specified,
+        /// the default config file is used.
+        #[arg(long)]
+        config: Option<PathBuf>,
+        /// Set each collage as the desktop wallpaper.
+        #[arg(long, default_value_t = false)]
+        wallpaper: bool,
+        /// Options of the collage, after `--`, e.g.
+        /// `-- ~/Pictures --output frame.png`.
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+}
+
+#[derive(Parser, Serialize, Debug, Clone, PartialEq)]
 #[command(name = "Collage")]
 #[command(author = "Jeffrey M. Rosenbluth")]
 #[command(version = "0.1")]
 #[command(about = "Create a collage from a directory of images", long_about = None)]
+#[command(args_conflicts_with_subcommands = true)]
 /// Create a collage from a directory of images.
 ///
 /// Collage can either be a column (portrait) or a row (landscape) of images.
@@ -29,17 +191,29 @@ enum Orientation {
 /// All images are resized to the same size specified by the user. Size will
 /// default to the size of the first image.
 struct App {
+    #[command(subcommand)]
+    #[serde(skip)]
+    command: Option<Command>,
+
     /// The directory wiht the images to be used in the collage.
-    image_dir: PathBuf,
+    #[arg(required_unless_present_any = ["compare", "album"])]
+    image_dir: Option<PathBuf>,
+
+    /// An album of the system photo library to use instead of an image
+    /// directory: exported from Photos on macOS (with `osxphotos` if it is
+    /// installed), or the folder with that name in the Pictures directory
+    /// elsewhere.
+    #[arg(long, conflicts_with_all = ["image_dir", "compare", "batch"])]
+    album: Option<String>,
 
     /// The width of the images in the collage. If not specified, the width of
     /// the first image will be used.
-    #[arg(long = "width", short = 'W')]
+    #[arg(long = "width", short = 'W', value_parser = clap::value_parser!(u32).range(1..))]
     image_width: Option<u32>,
 
     /// The height of the images in the collage. If not specified, the height of
     /// the first image will be used.
-    #[arg(long = "height", short = 'H')]
+    #[arg(long = "height", short = 'H', value_parser = clap::value_parser!(u32).range(1..))]
     image_height: Option<u32>,
 
     /// The orientation of the collage. If not specified, the default is
@@ -55,186 +229,3523 @@ struct App {
     #[arg(long = "left", short = 'l', default_value_t = 0)]
     left_margin: u32,
 
-    /// The spacing between images. If not specified, the default is 20.
-    #[arg(long = "spacing", short = 's', default_value_t = 20)]
-    spacing: u32,
+    /// The spacing between images in pixels, or `auto` to scale the spacing
+    /// with the size and number of the images: generous for a few, dense for
+    /// many. Auto spacing also sets margins that are left at 0. If not
+    /// specified, the default is 20.
+    #[arg(long = "spacing", short = 's', default_value = "20")]
+    spacing: Spacing,
 
     /// The background color of the collage. If not specified, the default is
     /// white.
-    #[arg(long = "color", short = 'c', default_value = "#ffffff")]
+    #[arg(
+        long = "color",
+        short = 'c',
+        value_name = "COLOR",
+        default_value = "#ffffff"
+    )]
     background_color: String,
 
+    /// A texture, in its own colors, to draw the collage on instead of the
+    /// plain --color, for a scrapbook look. If not specified, there is no
+    /// texture.
+    #[arg(long)]
+    texture: Option<Texture>,
+
+    /// A procedural backdrop to draw the collage on instead of the plain
+    /// --color, colored from the photos and drawn from the seed:
+    /// `generative:flow-field` for strokes along a noise field,
+    /// `generative:blobs` for soft discs of color or `generative:gradient`
+    /// for a noisy gradient. If not specified, there is no backdrop.
+    #[arg(long, value_name = "generative:STYLE", conflicts_with_all = ["texture", "manifest"])]
+    background: Option<Background>,
+
     /// If true, then the aspect ratio of the images will be preserved. If not
     /// specified, the default is false.
     #[arg(long = "preserve", short = 'p', default_value_t = false)]
     preserve_aspect_ratio: bool,
+
+    /// Scale each image to fit within the width and height in both dimensions,
+    /// preserving its aspect ratio, and pad the rest of the cell with the
+    /// background color.
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with = "preserve_aspect_ratio"
+    )]
+    contain: bool,
+
+    /// Scale the images by whole factors with nearest neighbor sampling,
+    /// centered in their tiles, so pixel art and sprites stay crisp instead of
+    /// blurring.
+    #[arg(
+        long = "pixel-art",
+        default_value_t = false,
+        conflicts_with_all = ["denoise", "lens_correct"]
+    )]
+    pixel_art: bool,
+
+    /// What the padding of `--contain` is filled with: the `background` of the
+    /// collage, the average color of the image's `edge`s next to it (also
+    /// `auto`), or the image's `dominant` color. If not specified, the default
+    /// is `background`.
+    #[arg(long, default_value = "background", requires = "contain")]
+    letterbox: Letterbox,
+
+    /// Smooth the noise of each image before it is downscaled, so that high
+    /// ISO photos don't stand out next to clean ones. The strength, from 1 to
+    /// 100, is how different two colors may be and still be smoothed
+    /// together; around 20 suits most noisy photos.
+    #[arg(long, value_name = "STRENGTH", value_parser = clap::value_parser!(u32).range(1..=100))]
+    denoise: Option<u32>,
+
+    /// Brighten the dark corners and straighten the barrel distortion of
+    /// wide-angle photos, as strongly as their EXIF 35mm focal length calls
+    /// for. Photos taken at 35mm or longer, or without the metadata, are left
+    /// as they are.
+    #[arg(long = "lens-correct", default_value_t = false)]
+    lens_correct: bool,
+
+    /// The strength of `--lens-correct` from 1 to 100 for every photo, instead
+    /// of going by their focal length.
+    #[arg(
+        long = "lens-strength",
+        requires = "lens_correct",
+        value_parser = clap::value_parser!(u32).range(1..=100)
+    )]
+    lens_strength: Option<u32>,
+
+    /// Correct the white balance of each image, so that photos shot under
+    /// mixed lighting don't clash: `gray-world` (the default without a value)
+    /// takes the average color to be gray, `percentile` takes the brightest
+    /// colors to be white.
+    #[arg(long = "auto-wb", num_args = 0..=1, default_missing_value = "gray-world")]
+    auto_wb: Option<WhiteBalance>,
+
+    /// Crop uniform borders, such as scanner edges, letterboxing or
+    /// screenshot chrome, off each image before it is laid out.
+    #[arg(long, default_value_t = false)]
+    trim: bool,
+
+    /// Turn each image whose shape doesn't match the tiles by 90 degrees, so
+    /// that it fills its tile: landscape images in a portrait column and
+    /// portrait images in a landscape row. With both --width and --height,
+    /// the shape of the tiles is that of the given size.
+    #[arg(long = "auto-rotate-tiles", default_value_t = false)]
+    auto_rotate_tiles: bool,
+
+    /// Make the pixels of each image that match this backdrop color, e.g.
+    /// `#00ff00`, transparent, for product shots and stickers.
+    #[arg(long = "chroma-key", value_name = "COLOR")]
+    chroma_key: Option<String>,
+
+    /// Cut the subject out of each image, leaving its surroundings
+    /// transparent, with an external segmentation program such as `rembg`.
+    /// The program receives each image as a PNG on stdin and writes it back
+    /// as a PNG with a transparent background on stdout.
+    #[arg(long, value_name = "PROGRAM")]
+    cutout: Option<PathBuf>,
+
+    /// An argument to pass to the --cutout program, e.g. `--cutout rembg
+    /// --cutout-arg i --cutout-arg - --cutout-arg -`. May be given more than
+    /// once.
+    #[arg(
+        long = "cutout-arg",
+        value_name = "ARG",
+        requires = "cutout",
+        allow_hyphen_values = true
+    )]
+    cutout_arg: Vec<String>,
+
+    /// How far the color of a pixel may be from the `--chroma-key` color, as a
+    /// distance in RGB, for it to be made transparent. Pixels up to twice as
+    /// far are made partially transparent. If not specified, the default is
+    /// 60.
+    #[arg(
+        long = "chroma-tolerance",
+        default_value_t = 60,
+        requires = "chroma_key"
+    )]
+    chroma_tolerance: u32,
+
+    /// The format of the log messages. With `json` each log line is a JSON
+    /// object and a final result record is printed to stdout.
+    #[arg(long = "log-format", default_value = "text")]
+    log_format: LogFormat,
+
+    /// Create one collage per immediate subdirectory of the image directory,
+    /// each named after its folder.
+    #[arg(long, default_value_t = false)]
+    batch: bool,
+
+    /// The name of the output file, which is saved as `<name>_<num>.png` in
+    /// the output directory.
+    #[arg(long, default_value = "collage")]
+    #[serde(serialize_with = "serialize_lossy")]
+    name: OsString,
+
+    /// Keep only one photo of each burst: photos taken by the same camera at
+    /// most this many seconds apart, going by their EXIF dates. The largest
+    /// file of a burst, usually the sharpest, is kept.
+    #[arg(
+        long = "dedup-bursts",
+        value_name = "SECONDS",
+        conflicts_with = "compare"
+    )]
+    dedup_bursts: Option<u32>,
+
+    /// Shuffle the order of the images.
+    #[arg(long, default_value_t = false)]
+    shuffle: bool,
+
+    /// The seed for every random choice, so that a collage can be reproduced
+    /// exactly. If not specified, a random seed is used and printed.
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// An external layout engine command. It receives the image sizes and
+    /// options as JSON on stdin and writes the layout as JSON to stdout.
+    #[arg(long = "layout-engine")]
+    layout_engine: Option<String>,
+
+    /// Overlap each image with the one before it by this many pixels instead
+    /// of spacing them apart. If not specified, the default is 0.
+    #[arg(
+        long,
+        default_value_t = 0,
+        conflicts_with_all = ["spacing", "layout_engine", "year_in_review", "mood_board", "compare"]
+    )]
+    overlap: u32,
+
+    /// Cross-fade the images where they overlap, for a seamless strip instead
+    /// of hard seams. The images are stacked in the `input` --z-order.
+    #[arg(
+        long = "overlap-blend",
+        default_value_t = false,
+        requires = "overlap",
+        conflicts_with = "image_z"
+    )]
+    overlap_blend: bool,
+
+    /// The opacity of the images, from 0 (invisible) to 1 (opaque), so that
+    /// overlapping images and the background show through. If not specified,
+    /// the default is 1.
+    #[arg(long, default_value = "1", value_parser = parse_opacity)]
+    opacity: f32,
+
+    /// The opacity of a single image, as `FILE=OPACITY` with the file name of
+    /// the image, e.g. `sky.jpg=0.5`. Takes precedence over `--opacity` and
+    /// may be given more than once.
+    #[arg(long = "image-opacity", value_name = "FILE=OPACITY", value_parser = parse_image_opacity)]
+    image_opacity: Vec<(String, f32)>,
+
+    /// How many times a single image appears, as `FILE=COUNT` with the file
+    /// name of the image, e.g. `logo.png=5`, to repeat a key photo or logo
+    /// throughout the collage. A count of 0 leaves the image out. May be given
+    /// more than once.
+    #[arg(
+        long = "image-count",
+        value_name = "FILE=COUNT",
+        value_parser = parse_image_count,
+        conflicts_with = "compare"
+    )]
+    image_count: Vec<(String, u32)>,
+
+    /// The order overlapping images are stacked in, from the bottom to the
+    /// top: in the `input` order, `random`, `largest-first` so that the
+    /// smallest are on top, or by `date` taken with the newest on top. If
+    /// not specified, the default is `input`.
+    #[arg(long = "z-order", default_value = "input")]
+    z_order: ZOrder,
+
+    /// The stacking level of a single image, as `FILE=Z` with the file name
+    /// of the image, e.g. `hero.jpg=1` to put it above all the others or
+    /// `-1` below them. Images without one are at level 0, stacked by
+    /// --z-order. May be given more than once.
+    #[arg(
+        long = "image-z",
+        value_name = "FILE=Z",
+        value_parser = parse_image_z,
+        allow_hyphen_values = true
+    )]
+    image_z: Vec<(String, i32)>,
+
+    /// Lay the images out in a grid with this many columns instead of a
+    /// single column or row.
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u32).range(1..),
+        conflicts_with_all = ["layout_engine", "overlap"]
+    )]
+    columns: Option<u32>,
+
+    /// What to do with a last row of the grid that is short of images: align
+    /// it to the `left`, `center` it, `justify` it to the full width, or
+    /// `hide` it. If not specified, the default is `left`.
+    #[arg(long = "last-row", default_value = "left", requires = "columns")]
+    last_row: LastRow,
+
+    /// Lay the images out in rows of at least this many images, as few and as
+    /// evenly filled rows as possible.
+    #[arg(
+        long = "min-per-row",
+        value_parser = clap::value_parser!(u32).range(1..),
+        conflicts_with_all = ["layout_engine", "overlap", "columns"]
+    )]
+    min_per_row: Option<u32>,
+
+    /// Lay the images out in rows of at most this many images, as few and as
+    /// evenly filled rows as possible.
+    #[arg(
+        long = "max-per-row",
+        value_parser = clap::value_parser!(u32).range(1..),
+        conflicts_with_all = ["layout_engine", "overlap", "columns"]
+    )]
+    max_per_row: Option<u32>,
+
+    /// Round the positions and sizes of the tiles to multiples of this many
+    /// pixels, for pixel perfect alignment to a baseline grid.
+    #[arg(long = "grid-snap", value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    grid_snap: Option<u32>,
+
+    /// Repeat the images as many times as it takes to fill a canvas of this
+    /// size, e.g. `1920x1080`, as a grid of cells of the image size cut off at
+    /// the edges.
+    #[arg(
+        long,
+        value_name = "WIDTHxHEIGHT",
+        value_parser = parse_dimensions,
+        conflicts_with_all = [
+            "preserve_aspect_ratio",
+            "layout_engine",
+            "overlap",
+            "exif_info",
+            "captions_from",
+            "batch",
+            "columns",
+            "min_per_row",
+            "max_per_row",
+        ]
+    )]
+    fill: Option<(u32, u32)>,
+
+    /// Mirror every other repeat of the images of `--fill`.
+    #[arg(long, default_value_t = false, requires = "fill")]
+    mirror: bool,
+
+    /// Create a year in review: a row per month, labeled with its name, of
+    /// the images taken in the most recent year, going by their EXIF dates.
+    #[arg(
+        long = "year-in-review",
+        default_value_t = false,
+        conflicts_with_all = [
+            "shuffle",
+            "layout_engine",
+            "fill",
+            "columns",
+            "min_per_row",
+            "max_per_row"
+        ]
+    )]
+    year_in_review: bool,
+
+    /// The most images per month in a year in review; the first ones taken
+    /// are used. If not specified, the default is 4.
+    #[arg(
+        long = "per-month",
+        default_value_t = 4,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    per_month: u32,
+
+    /// Create a mood board from a TOML spec of named sections, each a header
+    /// above its images. The images of a section are files or directories
+    /// relative to the image directory.
+    #[arg(
+        long = "mood-board",
+        value_name = "SPEC",
+        conflicts_with_all = [
+            "batch",
+            "shuffle",
+            "layout_engine",
+            "year_in_review",
+            "fill",
+            "columns",
+            "min_per_row",
+            "max_per_row"
+        ]
+    )]
+    mood_board: Option<PathBuf>,
+
+    /// Compare two directories of images: the files of the same name are
+    /// paired, and each pair is shown in a row, before then after.
+    #[arg(
+        long,
+        num_args = 2,
+        value_names = ["BEFORE", "AFTER"],
+        conflicts_with_all = [
+            "image_dir",
+            "batch",
+            "shuffle",
+            "layout_engine",
+            "year_in_review",
+            "mood_board",
+            "fill",
+            "columns",
+            "min_per_row",
+            "max_per_row"
+        ]
+    )]
+    compare: Option<Vec<PathBuf>>,
+
+    /// How the pairs of `--compare` are shown. If not specified, the default
+    /// is `side-by-side`.
+    #[arg(long = "compare-style", default_value = "side-by-side")]
+    compare_style: CompareStyle,
+
+    /// Head the columns of `--compare` with "Before" and "After".
+    #[arg(long = "compare-labels", default_value_t = false)]
+    compare_labels: bool,
+
+    /// Show the star rating of each image, from its EXIF or XMP metadata, as
+    /// a badge in the bottom left corner of its tile.
+    #[arg(long = "show-ratings", default_value_t = false)]
+    show_ratings: bool,
+
+    /// Add a footer below the collage so it documents itself. The text is a
+    /// template in which `{date}`, `{count}`, `{folder}` and `{name}` are
+    /// replaced by the date it was made, the number of images, the name of
+    /// the image directory and the name of the collage. Without a template,
+    /// the footer is `{date} | {count} images | {folder}`.
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        num_args = 0..=1,
+        default_missing_value = "{date} | {count} images | {folder}"
+    )]
+    footer: Option<String>,
+
+    /// Add a QR code of this URL to the collage, e.g. linking to the full
+    /// online album.
+    #[arg(long, value_name = "URL")]
+    qr: Option<String>,
+
+    /// Where the QR code of `--qr` goes: an extra tile after the images, or a
+    /// badge in a corner. If not specified, the default is `tile`.
+    #[arg(long = "qr-position", default_value = "tile")]
+    qr_position: QrPosition,
+
+    /// Add a strip under each image with its aperture, shutter speed, ISO and
+    /// focal length, from its EXIF metadata.
+    #[arg(long = "exif-info", default_value_t = false)]
+    exif_info: bool,
+
+    /// Add a strip under each image with its caption from a CSV file of file
+    /// names and captions, e.g. `beach.jpg,"Low tide, early morning"`. With
+    /// `--exif-info` the shooting info follows the caption.
+    #[arg(long = "captions-from", value_name = "CSV")]
+    captions_from: Option<PathBuf>,
+
+    /// Add a luminance and RGB histogram: one band across the bottom of the
+    /// collage for all of the images (`combined`, the default without a
+    /// value), or one under each image (`per-image`).
+    #[arg(long, num_args = 0..=1, default_missing_value = "combined")]
+    histogram: Option<HistogramMode>,
+
+    /// A TOML config file whose keys are the long option names. Options are
+    /// taken from the command line first, then `COLLAGE_*` environment
+    /// variables, then the config file and finally the defaults. If not
+    /// specified, `collage/config.toml` in the user's config directory is used
+    /// if it exists.
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Answer yes to every confirmation prompt.
+    #[arg(
+        long,
+        short = 'y',
+        default_value_t = false,
+        conflicts_with = "no_input"
+    )]
+    yes: bool,
+
+    /// Only read the image headers and print the number of images, the
+    /// dimensions of the collage and the memory it needs, without making it.
+    #[arg(long = "dry-run", default_value_t = false, conflicts_with = "nested")]
+    dry_run: bool,
+
+    /// Never prompt; cancel instead of asking for confirmation.
+    #[arg(long = "no-input", default_value_t = false)]
+    no_input: bool,
+
+    /// Ask for confirmation if the collage would take more memory than this,
+    /// e.g. `500MB` or `8GB`, as estimated from the image headers. If not
+    /// specified, the default is 2GB.
+    #[arg(long = "confirm-over", default_value = "2GB", value_parser = parse_size)]
+    confirm_over: u64,
+
+    /// The maximum number of pixels of the output image. If not specified,
+    /// the default is 1,000,000,000.
+    #[arg(
+        long = "max-output-pixels",
+        default_value_t = 1_000_000_000,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    max_output_pixels: u64,
+
+    /// The maximum width and height, in pixels, of an image to decode. Larger
+    /// images are skipped (or fail the run with `--strict`) before they are
+    /// decoded. If not specified, there is no limit.
+    #[arg(long = "max-image-dimension", value_parser = clap::value_parser!(u32).range(1..))]
+    max_image_dimension: Option<u32>,
+
+    /// The most memory decoding a single image may take, e.g. `500MB` or
+    /// `2GB`, so that a corrupt or malicious file can't exhaust the memory.
+    /// Larger images are skipped like those over `--max-image-dimension`. If
+    /// not specified, the default is 1GB.
+    #[arg(long = "max-decode-size", default_value = "1GB", value_parser = parse_size)]
+    max_decode_size: u64,
+
+    /// Fail if any image in the image directory cannot be decoded instead of
+    /// skipping it.
+    #[arg(long, default_value_t = false)]
+    strict: bool,
+
+    /// Put a placeholder, marked with the file name, in the place of each
+    /// image that cannot be decoded instead of leaving it out, so that the
+    /// other images keep their places.
+    #[arg(long, default_value_t = false, conflicts_with = "strict")]
+    placeholders: bool,
+
+    /// Resize and composite the images in linear light instead of in gamma
+    /// encoded sRGB. Slower, but fine detail doesn't darken.
+    #[arg(long, default_value_t = false)]
+    linear: bool,
+
+    /// The filter images are resized with: `nearest`, `triangle`,
+    /// `catmull-rom`, `gaussian` or `lanczos3`, from the fastest to the
+    /// sharpest. If not specified, the default is `catmull-rom`.
+    #[arg(long, default_value = "catmull-rom")]
+    filter: ResizeFilter,
+
+    /// The number of threads that resize the images. If not specified, the
+    /// default is the number of CPUs.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    threads: Option<u32>,
+
+    /// Alpha composite the images onto the canvas on the GPU, which is
+    /// faster for very large collages. Only the blending is done there:
+    /// rotating, masking, shadows and the other effects of each image are
+    /// still done on the CPU. Falls back to the CPU when there is no GPU or
+    /// the collage doesn't fit on it.
+    #[cfg(feature = "gpu")]
+    #[arg(long, default_value_t = false)]
+    gpu: bool,
+
+    /// How HDR images (EXR and Radiance HDR) are tone mapped: with the filmic
+    /// `aces` curve, with `reinhard`, or `clamp`ed. Except when clamped, each
+    /// is first exposed for its average brightness. If not specified, the
+    /// default is `aces`.
+    #[arg(long = "tone-map", default_value = "aces")]
+    tone_map: ToneMap,
+
+    /// Reduce the finished collage to this many colors, from 2 to 256, for
+    /// much smaller PNGs (saved with a palette) or a poster look.
+    #[arg(long, value_parser = clap::value_parser!(u16).range(2..=256))]
+    colors: Option<u16>,
+
+    /// Prepare the collage for an e-ink panel of this resolution, e.g.
+    /// `800x480`: fitted to the panel, in high contrast gray.
+    #[arg(
+        long,
+        value_name = "WIDTHxHEIGHT",
+        value_parser = parse_dimensions,
+        conflicts_with = "colors"
+    )]
+    eink: Option<(u32, u32)>,
+
+    /// The number of grays of the `--eink` panel, 2 for black and white. If not
+    /// specified, the default is 16.
+    #[arg(
+        long = "eink-levels",
+        default_value_t = 16,
+        requires = "eink",
+        value_parser = clap::value_parser!(u32).range(2..=256)
+    )]
+    eink_levels: u32,
+
+    /// How to dither a collage saved as a GIF, which holds at most 256
+    /// colors, reduced with `--colors` or prepared for `--eink`, so that gradients don't break up
+    /// into bands: `none`, an `ordered` pattern, or `floyd-steinberg` error
+    /// diffusion. If not specified, the default is `none`.
+    #[arg(long, default_value = "none")]
+    dither: Dither,
+
+    /// Where to save the collage: a file, or a directory in which it is saved
+    /// as `<name>_<num>.png`. In batch mode this is always a directory. If not
+    /// specified, the downloads directory is used (or the current directory if
+    /// there is none).
+    #[arg(long, short = 'O')]
+    output: Option<PathBuf>,
+
+    /// Upload the collage after saving it: to `s3://bucket/key`, using the
+    /// `AWS_*` environment variables for credentials and region, or with a
+    /// PUT to an `https://` URL, e.g. a pre-signed one. A target ending in `/`
+    /// gets the file name appended.
+    #[cfg(feature = "upload")]
+    #[arg(long, value_name = "TARGET")]
+    upload: Option<String>,
+
+    /// Embed C2PA Content Credentials in the collage, signed with this
+    /// certificate chain (PEM, the signing certificate first) and --c2pa-key:
+    /// that collage made it, its settings and the SHA-256 of every input, so
+    /// that a published collage can be verified. Only PNG and JPEG collages
+    /// can hold them.
+    #[cfg(feature = "c2pa")]
+    #[arg(long = "c2pa-cert", value_name = "PEM", requires = "c2pa_key")]
+    c2pa_cert: Option<PathBuf>,
+
+    /// The private key of --c2pa-cert, a P-256 key in PKCS#8 PEM.
+    #[cfg(feature = "c2pa")]
+    #[arg(long = "c2pa-key", value_name = "PEM", requires = "c2pa_cert")]
+    c2pa_key: Option<PathBuf>,
+
+    /// POST the outcome of each run to this URL as JSON: the output path,
+    /// dimensions, duration and a `status` of `ok`, `cancelled` or `error`.
+    #[cfg(feature = "notify")]
+    #[arg(long = "notify-url", value_name = "URL")]
+    notify_url: Option<String>,
+
+    /// Show a desktop notification when each collage is done, or has failed.
+    #[arg(long, default_value_t = false)]
+    notify: bool,
+
+    /// Make a collage of collages: every subdirectory of the image directory
+    /// is made into a collage of its own with the same settings, which then
+    /// goes in the place of the subdirectory.
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all = ["year_in_review", "mood_board", "compare"]
+    )]
+    nested: bool,
+
+    /// Name the collage after a hash of the images and the settings, as
+    /// `<name>_<hash>.png` in the output directory. If that file already
+    /// exists nothing changed, so it is kept instead of rendering again.
+    #[arg(long = "name-by-hash", default_value_t = false)]
+    name_by_hash: bool,
+
+    /// Resume an interrupted run from its checkpoint, reusing the layout and
+    /// the tiles that were already resized. Runs with this flag, or with at
+    /// least 1000 files, are checkpointed as they go.
+    #[arg(long, default_value_t = false)]
+    resume: bool,
+
+    /// Draw the layout over the collage to help understand it: the margins in
+    /// magenta, the gutters in cyan, the tile boundaries in red and the index
+    /// and file name of each image.
+    #[arg(long = "debug-overlay", default_value_t = false)]
+    debug_overlay: bool,
+
+    /// Also write an image map next to the collage, with the same name, that
+    /// links each tile to its image: an `html` page with the collage as a
+    /// clickable `<map>` (the default without a value), or the rectangles as
+    /// `json`.
+    #[arg(long = "image-map", num_args = 0..=1, default_missing_value = "html")]
+    image_map: Option<MapFormat>,
+
+    /// Also write a manifest of the layout next to the collage, as
+    /// `<name>.manifest.json`, so that `collage append` can add new images
+    /// to it later without making it again.
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all = [
+            "compare", "nested", "year_in_review", "mood_board", "fill", "footer", "qr",
+            "eink", "colors", "debug_overlay",
+        ]
+    )]
+    manifest: bool,
+
+    /// Also write a sidecar next to the collage, as `<name>.sidecar.json`,
+    /// recording the command line, every setting, the seed, the version of
+    /// collage and the SHA-256 of every input and of the collage, so that it
+    /// can be audited and made again later.
+    #[arg(long, default_value_t = false)]
+    sidecar: bool,
+
+    // The command line the options were parsed from, for the manifest.
+    #[arg(skip)]
+    #[serde(skip)]
+    args: Vec<OsString>,
+
+    /// The link of each tile of `--image-map`, in which `{file}` is replaced by
+    /// the file name of its image and `{path}` by its path, e.g.
+    /// `https://example.com/album/{file}`. If not specified, the default is
+    /// `{path}`.
+    #[arg(
+        long = "link-template",
+        default_value = "{path}",
+        requires = "image_map"
+    )]
+    link_template: String,
+
+    /// Only print the path of the collage and errors.
+    #[arg(long, short, default_value_t = false, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Print more about what is happening: `-v` for progress, `-vv` for
+    /// details.
+    #[arg(long, short, action = clap::ArgAction::Count)]
+    verbose: u8,
+}
+
+impl App {
+    // Check the options before any images are decoded, so that a bad
+    // combination fails right away with a specific message.
+    fn validate(&self) -> Result<()> {
+        let image_dirs = match &self.compare {
+            Some(dirs) => dirs.iter().collect(),
+            None => vec![self
+                .image_dir
+                .as_ref()
+                .context("No image directory given")?],
+        };
+        for image_dir in image_dirs {
+            ensure!(
+                image_dir.is_dir(),
+                "The image directory {} does not exist",
+                image_dir.display()
+            );
+        }
+        hex_to_color(&self.background_color)
+            .with_context(|| format!("Invalid --color {}", self.background_color))?;
+        if let Some(key) = &self.chroma_key {
+            hex_to_color(key).with_context(|| format!("Invalid --chroma-key {key}"))?;
+        }
+
+        // The spacing runs between the cells, so it shouldn't dwarf them.
+        let cell = match self.orientation {
+            Orientation::Portrait => self.image_height.or(self.image_width),
+            Orientation::Landscape => self.image_width.or(self.image_height),
+        };
+        if let (Some(cell), Spacing::Pixels(spacing)) = (cell, self.spacing) {
+            ensure!(
+                spacing <= cell,
+                "--spacing {spacing} is larger than the images ({cell} pixels)"
+            );
+        }
+
+        // Each image fades in over the one before it, so that one has to be
+        // beneath it.
+        ensure!(
+            !self.overlap_blend || self.z_order == ZOrder::Input,
+            "--overlap-blend can't be used with --z-order {}",
+            self.z_order.to_possible_value().unwrap().get_name()
+        );
+
+        if let Some(url) = &self.qr {
+            qr::render(url, 0)?;
+            ensure!(
+                self.qr_position != QrPosition::Tile
+                    || !(self.year_in_review
+                        || self.mood_board.is_some()
+                        || self.compare.is_some()
+                        || self.fill.is_some()),
+                "The QR code can't be a tile of a year in review, a mood board, a \
+                 comparison or a filled canvas; use a corner --qr-position instead"
+            );
+        }
+
+        ensure!(
+            !(self.manifest && self.histogram == Some(HistogramMode::Combined)),
+            "--manifest doesn't work with a combined histogram, which changes with every image"
+        );
+
+        ensure!(
+            !((self.exif_info
+                || self.captions_from.is_some()
+                || self.histogram == Some(HistogramMode::PerImage))
+                && self.compare.is_some()
+                && self.compare_style == CompareStyle::Diagonal),
+            "--exif-info, captions and per-image histograms can't go under the images of a \
+             diagonal comparison, which share a tile"
+        );
+        if let (Some(min), Some(max)) = (self.min_per_row, self.max_per_row) {
+            ensure!(
+                min <= max,
+                "--min-per-row {min} is more than --max-per-row {max}"
+            );
+        }
+        ensure!(
+            !(self.fill.is_some() && self.histogram == Some(HistogramMode::PerImage)),
+            "Per-image histograms can't go under the cells of --fill"
+        );
+
+        if let Some(output) = &self.output {
+            ensure!(
+                !self.name_by_hash || self.batch || output.is_dir(),
+                "--name-by-hash names the collage itself, so --output must be a directory"
+            );
+            if !self.batch && !output.is_dir() {
+                ImageFormat::from_path(output)
+                    .with_context(|| format!("Unknown image format for {}", output.display()))?;
+            }
+        }
+        #[cfg(feature = "c2pa")]
+        if let (Some(cert), Some(key)) = (&self.c2pa_cert, &self.c2pa_key) {
+            c2pa::Signer::load(cert, key)?;
+            if let Some(output) = self.output.as_ref().filter(|o| !self.batch && !o.is_dir()) {
+                ensure!(
+                    matches!(
+                        ImageFormat::from_path(output),
+                        Ok(ImageFormat::Png | ImageFormat::Jpeg)
+                    ),
+                    "Content credentials can only be embedded in a PNG or JPEG, not {}",
+                    output.display()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    // Everything that affects the tiles of a collage, to tell the checkpoints
+    // of different runs apart: every setting of how it looks, and the
+    // captions read from a file. The seed is left out, since a resumed run
+    // goes on with the one it was checkpointed with.
+    fn checkpoint_key(&self, image_dir: &Path, name: &OsStr) -> Result<String> {
+        let image_dir = image_dir
+            .canonicalize()
+            .unwrap_or_else(|_| image_dir.to_path_buf());
+        let captions = match &self.captions_from {
+            Some(path) => Some(
+                fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read captions file {}", path.display()))?,
+            ),
+            None => None,
+        };
+        Ok(serde_json::json!({
+            "image-dir": image_dir.to_string_lossy(),
+            "name": name.to_string_lossy(),
+            "settings": self.look_settings()?,
+            "captions": captions,
+        })
+        .to_string())
+    }
+
+    // A hash of the contents of the files at `paths`, in order, and of every
+    // setting that affects the collage. The seed only counts when something
+    // random depends on it.
+    fn output_hash(&self, paths: &[PathBuf], seed: u64) -> Result<String> {
+        let mut settings = self.look_settings()?;
+        if self.is_stochastic() {
+            settings.insert("seed".to_string(), seed.into());
+        }
+
+        let mut hasher = Sha256::new();
+        hasher.update(serde_json::Value::Object(settings).to_string());
+        for path in paths {
+            let mut file =
+                File::open(path).with_context(|| format!("Failed to read {}", path.display()))?;
+            io::copy(&mut file, &mut hasher)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+        }
+        Ok(hasher
+            .finalize()
+            .iter()
+            .take(8)
+            .map(|b| format!("{b:02x}"))
+            .collect())
+    }
+
+    // Every setting that is set, keyed by its option as written on the
+    // command line and in a config file, e.g. `"z-order": "random"`.
+    fn settings(&self) -> Result<serde_json::Map<String, serde_json::Value>> {
+        let serde_json::Value::Object(fields) = serde_json::to_value(self)? else {
+            bail!("Failed to record the settings");
+        };
+        let command = config::command();
+        Ok(fields
+            .into_iter()
+            .filter(|(_, value)| !value.is_null())
+            .map(|(id, value)| {
+                let long = command
+                    .get_arguments()
+                    .find(|arg| arg.get_id() == id.as_str())
+                    .and_then(|arg| arg.get_long());
+                (long.map_or(id.clone(), str::to_string), value)
+            })
+            .collect())
+    }
+
+    // The settings of how the collage looks: all of them but `RUN_SETTINGS`.
+    fn look_settings(&self) -> Result<serde_json::Map<String, serde_json::Value>> {
+        let mut settings = self.settings()?;
+        settings.retain(|long, _| !RUN_SETTINGS.contains(&long.as_str()));
+        Ok(settings)
+    }
+
+    // The settings that go into the recipe published with a collage: those of
+    // how it looks, but for those that name files, URLs or commands or hold
+    // text that is drawn on it, such as footers and QR codes.
+    #[cfg(feature = "c2pa")]
+    fn recipe_settings(&self) -> Result<serde_json::Map<String, serde_json::Value>> {
+        let command = config::command();
+        let mut settings = self.look_settings()?;
+        settings.retain(|long, _| {
+            command
+                .get_arguments()
+                .find(|arg| arg.get_long() == Some(long))
+                .is_some_and(|arg| !is_private(arg))
+        });
+        Ok(settings)
+    }
+
+    // Whether `--auto-rotate-tiles` turns a `width` x `height` image: when it
+    // is landscape and the tiles are portrait, or the other way around.
+    fn rotates(&self, width: u32, height: u32) -> bool {
+        if !self.auto_rotate_tiles || width == height {
+            return false;
+        }
+        let portrait_tiles = match (self.image_width, self.image_height) {
+            (Some(w), Some(h)) if w != h => h > w,
+            _ => self.orientation == Orientation::Portrait,
+        };
+        (height > width) != portrait_tiles
+    }
+
+    // The limits on decoding a single image.
+    fn decode_limits(&self) -> Limits {
+        let mut limits = Limits::default();
+        limits.max_image_width = self.max_image_dimension;
+        limits.max_image_height = self.max_image_dimension;
+        limits.max_alloc = Some(self.max_decode_size);
+        limits
+    }
+
+    // Whether the images of the collage may include the file at `path`.
+    fn reads(&self, path: &Path) -> bool {
+        self.compare
+            .iter()
+            .flatten()
+            .chain(&self.image_dir)
+            .any(|dir| path.starts_with(dir))
+    }
+
+    // The number of threads to resize the images with.
+    fn threads(&self) -> usize {
+        match self.threads {
+            Some(threads) => threads as usize,
+            None => thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+
+    // Whether any of the selected options makes random choices.
+    fn is_stochastic(&self) -> bool {
+        self.shuffle || self.z_order == ZOrder::Random || self.background.is_some()
+    }
+
+    // The layout engine selected by the options.
+    fn layout_engine(&self) -> Box<dyn LayoutEngine> {
+        match &self.layout_engine {
+            Some(command) => Box::new(ExternalLayout {
+                command: command.clone(),
+            }),
+            None => Box::new(StackLayout),
+        }
+    }
+
+    // The opacity of the image at `path`: its own from `--image-opacity` or
+    // else the global `--opacity`.
+    fn opacity_of(&self, path: &Path) -> f32 {
+        let name = path.file_name().unwrap_or_default();
+        self.image_opacity
+            .iter()
+            .rev()
+            .find(|(file, _)| OsStr::new(file) == name)
+            .map_or(self.opacity, |(_, opacity)| *opacity)
+    }
+
+    // How many times the image at `path` appears: its count from
+    // `--image-count` or else once.
+    fn count_of(&self, path: &Path) -> usize {
+        let name = path.file_name().unwrap_or_default();
+        self.image_count
+            .iter()
+            .rev()
+            .find(|(file, _)| OsStr::new(file) == name)
+            .map_or(1, |(_, count)| *count as usize)
+    }
+
+    // The stacking level of the image at `path`: its level from `--image-z`
+    // or else 0.
+    fn z_of(&self, path: &Path) -> i32 {
+        let name = path.file_name().unwrap_or_default();
+        self.image_z
+            .iter()
+            .rev()
+            .find(|(file, _)| OsStr::new(file) == name)
+            .map_or(0, |(_, z)| *z)
+    }
+
+    // The spacing between images in pixels, once `auto` has been resolved.
+    fn spacing(&self) -> u32 {
+        match self.spacing {
+            Spacing::Pixels(pixels) => pixels,
+            Spacing::Auto => unreachable!("--spacing auto is resolved before use"),
+        }
+    }
+
+    // Resolve `--spacing auto` for `count` images, sizing the cells from the
+    // first image when no width or height was given.
+    fn resolve_spacing(&self, paths: &[PathBuf]) -> App {
+        let mut app = self.clone();
+        if self.spacing != Spacing::Auto {
+            return app;
+        }
+        let first = paths
+            .iter()
+            .find_map(|path| image::image_dimensions(path).ok())
+            .unwrap_or_default();
+        let (width, height) = (
+            self.image_width.unwrap_or(first.0),
+            self.image_height.unwrap_or(first.1),
+        );
+        let cell = match self.orientation {
+            Orientation::Portrait => height,
+            Orientation::Landscape => width,
+        };
+        let spacing = self.spacing.pixels(paths.len(), cell);
+        debug!(
+            "Auto spacing of {spacing} pixels for {} images.",
+            paths.len()
+        );
+        app.spacing = Spacing::Pixels(spacing);
+        if app.top_margin == 0 {
+            app.top_margin = spacing;
+        }
+        if app.left_margin == 0 {
+            app.left_margin = spacing;
+        }
+        app
+    }
+
+    fn layout_options(&self, image_width: u32, image_height: u32) -> LayoutOptions {
+        LayoutOptions {
+            orientation: self.orientation,
+            image_width,
+            image_height,
+            top_margin: self.top_margin,
+            left_margin: self.left_margin,
+            // Overlapping images have no spacing between them.
+            spacing: if self.overlap > 0 { 0 } else { self.spacing() },
+            overlap: self.overlap,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 struct Model {
-    images: Vec<DynamicImage>,
-    image_width: u32,
-    image_height: u32,
+    images: Vec<RgbaImage>,
+    layout: Layout,
+}
+
+// The largest size a placeholder takes from the header of its file.
+const PLACEHOLDER_MAX: u32 = 4096;
+
+// The file an image came from, its size and the decoded image, which is
+// `None` for a file that failed to decode and gets a placeholder.
+type Input = (PathBuf, ((u32, u32), Option<Arc<DynamicImage>>));
+
+// Decoded images shared between the jobs of a run, keyed by path.
+type ImageCache = Mutex<HashMap<PathBuf, Arc<DynamicImage>>>;
+
+// A file that was skipped because it could not be decoded.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Skipped {
+    #[serde(serialize_with = "serialize_lossy")]
+    path: PathBuf,
+    error: String,
+}
+
+// JSON can only hold UTF-8, so non-UTF-8 paths are written lossily.
+fn serialize_lossy<S: serde::Serializer, P: AsRef<OsStr> + ?Sized>(
+    path: &P,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&path.as_ref().to_string_lossy())
+}
+
+// The machine-readable record of a finished run.
+#[derive(Serialize, Debug, Clone)]
+struct RunResult {
+    #[serde(serialize_with = "serialize_lossy")]
+    output: PathBuf,
+    width: u32,
+    height: u32,
+    images: usize,
+    skipped: Vec<Skipped>,
+    seed: u64,
+    file_size: u64,
+    duration_ms: u128,
+    stages: Stages,
+    // Whether `--name-by-hash` found the collage already made.
+    up_to_date: bool,
+}
+
+// How long each stage of a run took, in milliseconds.
+#[derive(Serialize, Debug, Clone, Copy, Default)]
+struct Stages {
+    decode_ms: u128,
+    layout_ms: u128,
+    resize_ms: u128,
+    composite_ms: u128,
+    save_ms: u128,
+}
+
+impl RunResult {
+    // Print a short human readable summary of the run.
+    fn print_summary(&self) {
+        let secs = |ms: u128| ms as f64 / 1000.0;
+        if self.up_to_date {
+            println!("The collage {} is up to date.", self.output.display());
+            return;
+        }
+        println!("Saved the collage to {}.", self.output.display());
+        println!(
+            "  Images:     {} used, {} skipped",
+            self.images,
+            self.skipped.len()
+        );
+        println!("  Dimensions: {}x{}", self.width, self.height);
+        println!("  File size:  {:.1}KB", self.file_size as f64 / 1000.0);
+        println!(
+            "  Time:       {:.2}s (decode {:.2}s, layout {:.2}s, resize {:.2}s, \
+             composite {:.2}s, save {:.2}s)",
+            secs(self.duration_ms),
+            secs(self.stages.decode_ms),
+            secs(self.stages.layout_ms),
+            secs(self.stages.resize_ms),
+            secs(self.stages.composite_ms),
+            secs(self.stages.save_ms),
+        );
+    }
 }
 
-// Resize an image to the specified width and height. If preserve_aspect_ratio
-// is true, then the image will be resized so that if `Portrait` orientation
-// then the width will be set to width and the heigth to width / aspect ratio.
-// If it's `Landscape` then the width will be set to height * aspect ratio.
-fn prepare_image(image: &DynamicImage, width: u32, height: u32, app: &App) -> DynamicImage {
-    // If we're not preserving the aspect ratio, just resize to the exact width and height.
+// Runs with at least this many files are checkpointed even without
+// `--resume`.
+const CHECKPOINT_MIN_IMAGES: usize = 1000;
+
+// Images resized below this many pixels in either dimension are barely
+// visible in the collage.
+const MIN_USEFUL_SIZE: u32 = 8;
+
+// The size an image is resized to given the specified width and height. If
+// preserve_aspect_ratio is true, then the image will be resized so that if
+// `Portrait` orientation then the width will be set to width and the heigth to
+// width / aspect ratio. If it's `Landscape` then the width will be set to
+// height * aspect ratio.
+fn image_size((iw, ih): (u32, u32), width: u32, height: u32, app: &App) -> (u32, u32) {
+    // If we're not preserving the aspect ratio, just use the exact width and height.
     if !app.preserve_aspect_ratio {
-        return image.resize_exact(width, height, FilterType::CatmullRom);
+        return (width, height);
     };
 
-    let aspect_ratio = image.width() as f32 / image.height() as f32;
+    let aspect_ratio = iw as f32 / ih as f32;
 
     let (w, h) = match app.orientation {
         Orientation::Landscape => ((height as f32 * aspect_ratio) as u32, height),
         Orientation::Portrait => (width, (width as f32 / aspect_ratio) as u32),
     };
 
-    image.resize_exact(w, h, FilterType::CatmullRom)
+    // Extreme aspect ratios can truncate to 0, which the resize can't handle.
+    (w.max(1), h.max(1))
 }
 
-// Convert a hex code to a color.
-pub fn hex_to_color(hex: &str) -> Result<Rgba<u8>> {
-    let hex_code = hex.strip_prefix('#').map_or(hex, |stripped| stripped);
-    ensure!(hex_code.len() == 6, "Invalid hex code length");
+// The directory to save collages in: the `--output` directory if given, else
+// the downloads dir, falling back to the current directory on systems
+// without one.
+fn output_dir(dir: Option<&Path>) -> Result<PathBuf> {
+    if let Some(dir) = dir {
+        return Ok(dir.to_path_buf());
+    }
+    if let Some(dir) = UserDirs::new().and_then(|dirs| dirs.download_dir().map(Path::to_path_buf)) {
+        return Ok(dir);
+    }
+    let dir = std::env::current_dir().context("Failed to get the current directory")?;
+    warn!(
+        "No downloads directory found, saving to {} instead.",
+        dir.display()
+    );
+    Ok(dir)
+}
 
-    let red = u8::from_str_radix(&hex_code[..2], 16).context("Invalid hex code for red channel")?;
-    let green =
-        u8::from_str_radix(&hex_code[2..4], 16).context("Invalid hex code for green channel")?;
-    let blue =
-        u8::from_str_radix(&hex_code[4..6], 16).context("Invalid hex code for blue channel")?;
+// The scale of the caption and `--exif-info` text and the height of the strip
+// it's drawn in, with half a line of padding above and below.
+fn caption_strip(image_height: u32) -> (u32, u32) {
+    let scale = (image_height / (8 * text::GLYPH_SIZE)).max(1);
+    (scale, 2 * text::GLYPH_SIZE * scale)
+}
 
-    Ok(Rgba([red, green, blue, 255]))
+// The height of a histogram band under images of `size`, with an eighth of it
+// as the gap above the band.
+fn histogram_strip(size: u32) -> u32 {
+    (size / 4).max(8)
 }
 
-fn main() -> Result<()> {
-    env_logger::init();
-    let app = App::parse();
+// Take the histogram strips of `strip` pixels back out of the bottom of the
+// tiles, returning where each image's histogram band goes. Without a strip
+// there are no bands.
+fn take_histogram_strips(layout: &mut Layout, strip: u32) -> Vec<Tile> {
+    if strip == 0 {
+        return Vec::new();
+    }
+    let gap = strip / 8;
+    layout
+        .tiles
+        .iter_mut()
+        .map(|tile| {
+            tile.height = tile.height.saturating_sub(strip).max(1);
+            Tile {
+                x: tile.x,
+                y: tile.y + tile.height + gap,
+                width: tile.width,
+                height: strip - gap,
+            }
+        })
+        .collect()
+}
 
-    if let (Some(w), Some(h)) = (app.image_height, app.image_height) {
-        if w * h > 100_000_000 {
-            warn!("The product of the width and height is greater than 100,000Mb.");
-        }
+// Take the caption strips back out of the bottom of the tiles and label each
+// with the caption of its image, the shooting info or both. Text too wide for
+// its tile is drawn smaller, and cut short if it doesn't fit even at the
+// smallest size.
+fn add_caption_strips<T>(
+    layout: &mut Layout,
+    inputs: &[(PathBuf, T)],
+    scale: u32,
+    strip: u32,
+    captions: Option<&Captions>,
+    exif_info: bool,
+) {
+    for (tile, (path, _)) in layout.tiles.iter_mut().zip(inputs) {
+        tile.height = tile.height.saturating_sub(strip).max(1);
+        let caption = captions.and_then(|captions| captions.get(path));
+        let shooting_info = exif_info.then(|| metadata::shooting_info(path)).flatten();
+        let info = match (caption, shooting_info) {
+            (Some(caption), Some(info)) => format!("{caption}  {info}"),
+            (Some(caption), None) => caption.to_string(),
+            (None, Some(info)) => info,
+            (None, None) => continue,
+        };
+        let scale = scale.min(tile.width / text::width(&info, 1).max(1)).max(1);
+        let glyph = text::GLYPH_SIZE * scale;
+        layout.labels.push(Label {
+            x: tile.x,
+            y: tile.y + tile.height + (strip - glyph) / 2,
+            scale,
+            text: info.chars().take((tile.width / glyph) as usize).collect(),
+        });
     }
+}
 
-    info!("Opening images.");
-    // We need to read the images before we can create the model.
-    let mut images: Vec<DynamicImage> = Vec::new();
-    let mut paths: Vec<PathBuf> = WalkDir::new(&app.image_dir)
-        .into_iter()
-        .flatten()
-        .map(|d| d.path().to_path_buf())
-        .collect();
-    paths.sort_by(|a, b| a.to_string_lossy().cmp(&b.to_string_lossy()));
+// Draw the QR code of `url` centered in its tile or, without one, as a badge
+// a fifth the size of the collage in the corner of `--qr-position`.
+fn add_qr(out: &mut RgbaImage, url: &str, app: &App, tile: Option<Tile>) -> Result<()> {
+    let (width, height) = out.dimensions();
+    let tile = tile.unwrap_or_else(|| {
+        let size = (width.min(height) / 5).max(1);
+        let inset = app
+            .spacing()
+            .min(width.min(height).saturating_sub(size) / 2);
+        let (left, right) = (inset, width.saturating_sub(size + inset));
+        let (top, bottom) = (inset, height.saturating_sub(size + inset));
+        let (x, y) = match app.qr_position {
+            QrPosition::TopLeft => (left, top),
+            QrPosition::TopRight => (right, top),
+            QrPosition::BottomLeft => (left, bottom),
+            QrPosition::Tile | QrPosition::BottomRight => (right, bottom),
+        };
+        Tile {
+            x,
+            y,
+            width: size,
+            height: size,
+        }
+    });
+    let code = qr::render(url, tile.width.min(tile.height))?;
+    let x = tile.x as i64 + (tile.width as i64 - code.width() as i64) / 2;
+    let y = tile.y as i64 + (tile.height as i64 - code.height() as i64) / 2;
+    imageops::replace(out, &code, x, y);
+    Ok(())
+}
 
-    info!("Calculating the total size of the images.");
-    let mut raw_megabytes = 0;
+// Decode the images at `paths`, skipping (or with `--strict` failing on)
+// those that can't be decoded.
+fn decode_images(
+    app: &App,
+    paths: &[PathBuf],
+    cache: Option<&ImageCache>,
+) -> Result<(Vec<Input>, Vec<Skipped>)> {
+    let mut images = Vec::new();
+    let mut skipped: Vec<Skipped> = Vec::new();
     for path in paths {
-        if let Ok(metadata) = metadata(&path) {
-            raw_megabytes += metadata.len() / 1_000_000;
-        }
-        if let Ok(img) = image::open(&path) {
-            images.push(img);
+        match open_input(app, path, cache) {
+            Ok(img) => images.push((path.clone(), ((img.width(), img.height()), Some(img)))),
+            // Files that aren't images at all are never an error.
+            Err(e) if app.strict && ImageFormat::from_path(path).is_ok() => {
+                return Err(e.context(format!("Failed to decode {}", path.display())));
+            }
+            Err(e) => {
+                if ImageFormat::from_path(path).is_ok() {
+                    warn!("Skipping {}: {e:#}", path.display());
+                } else {
+                    debug!("Skipping {}: {e:#}", path.display());
+                }
+                skipped.push(Skipped {
+                    path: path.clone(),
+                    error: format!("{e:#}"),
+                });
+                // A placeholder takes the shape of the image, if its header
+                // can be read. Only the size is kept, scaled down to at most
+                // PLACEHOLDER_MAX since the header may be corrupt, and the
+                // placeholder is drawn at the size of its tile.
+                if app.placeholders && ImageFormat::from_path(path).is_ok() {
+                    let (width, height) = image::image_dimensions(path).unwrap_or((800, 600));
+                    let scale = (PLACEHOLDER_MAX as f64 / width.max(height).max(1) as f64).min(1.0);
+                    let size = (
+                        ((width as f64 * scale) as u32).max(1),
+                        ((height as f64 * scale) as u32).max(1),
+                    );
+                    images.push((path.clone(), (size, None)));
+                }
+            }
         }
     }
+    Ok((images, skipped))
+}
 
-    // If the total size of the images is greater than 100Mb, then ask the user
-    // if they want to proceed.
-    if raw_megabytes > 100 {
-        print!("The image files total {raw_megabytes}Mb. Do you want to proceed? [Y/n]: ");
-        io::stdout().flush().unwrap();
+// Prepare the image of tile `i` of `state`: decode it (unless `decoded`
+// already is), correct it, resize it to its tile and checkpoint it.
+fn prepare_tile(
+    app: &App,
+    state: &State,
+    i: usize,
+    decoded: Option<Arc<DynamicImage>>,
+    repeat: usize,
+    checkpoint: Option<&Checkpoint>,
+    cache: Option<&ImageCache>,
+) -> Result<RgbaImage> {
+    let tile = &state.layout.tiles[i];
+    if let Some(done) = resumed_tile(checkpoint, app, i, tile) {
+        return Ok(done);
+    }
+    if app.placeholders && state.skipped.iter().any(|s| s.path == state.paths[i]) {
+        let name = state.paths[i].file_name().unwrap_or_default();
+        return Ok(placeholder::render(
+            tile.width,
+            tile.height,
+            &name.to_string_lossy(),
+        ));
+    }
+    let image = match decoded {
+        Some(image) => image,
+        None => open_input(app, &state.paths[i], cache)
+            .with_context(|| format!("Failed to decode {}", state.paths[i].display()))?,
+    };
+    let image = match tonemap::tone_map(&image, app.tone_map) {
+        Some(mapped) => Arc::new(DynamicImage::ImageRgba8(mapped)),
+        None => image,
+    };
+    let image = match app.denoise {
+        Some(strength) => Arc::new(DynamicImage::ImageRgba8(denoise::bilateral(
+            &image.to_rgba8(),
+            strength,
+        ))),
+        None => image,
+    };
+    let strength = match app.lens_strength {
+        _ if !app.lens_correct => 0.0,
+        Some(strength) => strength as f32 / 100.0,
+        None => metadata::focal_length_35mm(&state.paths[i]).map_or(0.0, lens::strength_for),
+    };
+    let image = if strength > 0.0 {
+        Arc::new(DynamicImage::ImageRgba8(lens::correct(
+            &image.to_rgba8(),
+            strength,
+        )))
+    } else {
+        image
+    };
+    // The white balance is measured on the photo alone, before the padding
+    // of `--contain` or `--letterbox` can skew it.
+    let image = match app.auto_wb {
+        Some(mode) => {
+            let mut balanced = image.to_rgba8();
+            color::white_balance(&mut balanced, mode);
+            Arc::new(DynamicImage::ImageRgba8(balanced))
+        }
+        None => image,
+    };
+    let mut prepared = if app.contain {
+        contain(&image, tile.width, tile.height, app)
+    } else {
+        resize(&image, tile.width, tile.height, app)
+    };
+    if let Some(key) = &app.chroma_key {
+        color::chroma_key(&mut prepared, hex_to_color(key)?, app.chroma_tolerance);
+    }
+    if let Some(program) = &app.cutout {
+        prepared = cutout::cutout(&prepared, program, &app.cutout_arg)
+            .with_context(|| format!("Failed to cut out {}", state.paths[i].display()))?;
+    }
+    // Every other repeat of the images of a filled canvas is mirrored.
+    if app.mirror && (i / repeat) % 2 == 1 {
+        imageops::flip_horizontal_in_place(&mut prepared);
+    }
+    // Each image fades in over the one before it.
+    if app.overlap_blend && i > 0 {
+        let vertical = app.orientation == Orientation::Portrait;
+        color::fade_in(&mut prepared, app.overlap, vertical);
+    }
+    // The after image of a diagonal comparison only covers the bottom
+    // right half of the tile it shares with the before image.
+    if app.compare.is_some() && app.compare_style == CompareStyle::Diagonal && i % 2 == 1 {
+        compare::mask_diagonal(&mut prepared);
+    }
+    if let Some(checkpoint) = checkpoint {
+        if let Err(e) = checkpoint.save_tile(i, &prepared) {
+            warn!("Failed to checkpoint tile {i}: {e:#}");
+        }
+    }
+    Ok(prepared)
+}
 
-        let mut input = String::new();
-        io::stdin().read_line(&mut input).unwrap();
+// Repeat every input as many times as its `--image-count`.
+fn repeat_counted<T: Clone>(app: &App, inputs: Vec<(PathBuf, T)>) -> Vec<(PathBuf, T)> {
+    inputs
+        .into_iter()
+        .flat_map(|input| {
+            let count = app.count_of(&input.0);
+            std::iter::repeat_n(input, count)
+        })
+        .collect()
+}
 
-        match input.trim().to_lowercase().as_str() {
-            "y" | "yes" => {
-                println!("Proceeding...");
+// Estimate the collage of `paths` from the image headers alone, laid out
+// just like `collage` lays out the decoded images, with a copy of its `rng`.
+fn estimate(
+    app: &App,
+    image_dir: &Path,
+    name: &OsStr,
+    paths: &[PathBuf],
+    rng: &ChaCha8Rng,
+    groups: &Groups,
+    captions: Option<&Captions>,
+) -> Estimate {
+    let limits = app.decode_limits();
+    let mut inputs = Vec::new();
+    let mut skipped = 0;
+    for path in paths {
+        match decode::header(path, &limits) {
+            Ok((width, height, bytes)) if app.rotates(width, height) => {
+                inputs.push((path.clone(), (height, width, bytes)))
             }
-            _ => {
-                println!("Operation cancelled.");
-                return Ok(());
+            Ok(header) => inputs.push((path.clone(), header)),
+            Err(_) => {
+                skipped += 1;
+                if app.placeholders && ImageFormat::from_path(path).is_ok() {
+                    inputs.push((path.clone(), (800, 600, 800 * 600)));
+                }
             }
         }
     }
+    if app.compare.is_some() {
+        inputs = compare::complete_pairs(inputs, paths);
+    }
 
-    info!("Setting the global image dimensions.");
-    // If the user didn't specify the width or height, then we use the width
-    // and height of the first image.
-    let image_width = app.image_width.unwrap_or(images[0].width());
-    let image_height = app.image_height.unwrap_or(images[0].height());
-
-    info!("Resizing images if necessary.");
-    // Resize all the images to the same width (for portrait) or height (for
-    // landscape).
-    images = images
-        .into_iter()
-        .map(|image| prepare_image(&image, image_width, image_height, &app))
-        .collect();
-
-    // Create the model.
-    let model = Model {
-        images,
-        image_width,
-        image_height,
+    // Each image is decoded once, however often it is repeated, and is
+    // briefly converted to RGBA while it is resized.
+    let decoded_bytes = inputs.iter().map(|(_, (_, _, bytes))| bytes).sum();
+    let largest = inputs
+        .iter()
+        .map(|(_, (w, h, _))| *w as u64 * *h as u64 * 4)
+        .max()
+        .unwrap_or(0);
+    let mut estimate = Estimate {
+        images: inputs.len(),
+        skipped,
+        decoded_bytes,
+        peak_bytes: decoded_bytes + largest,
+        canvas: None,
     };
-
-    let n = model.images.len() as u32;
-
-    info!("Calculating the size of the output image.");
-    // Calculate the width and height of the output image.
-    let (width, height) = match app.orientation {
-        Orientation::Portrait => {
-            let w = model.image_width + 2 * app.left_margin;
-            let hs = model.images.iter().fold(0, |a, b| a + b.height());
-            let h = hs + app.spacing * (n - 1) + 2 * app.top_margin;
-            (w, h)
-        }
-        Orientation::Landscape => {
-            let h = model.image_height + 2 * app.top_margin;
-            let ws = model.images.iter().fold(0, |a, b| a + b.width());
-            let w = ws + app.spacing * (n - 1) + 2 * app.left_margin;
-            (w, h)
-        }
+    inputs = repeat_counted(app, inputs);
+    // An external layout engine is only run for the collage itself.
+    if inputs.is_empty() || app.layout_engine.is_some() {
+        return estimate;
+    }
+    let Ok(plan) = plan(
+        app,
+        inputs,
+        |&(w, h, _)| (w, h),
+        &mut rng.clone(),
+        groups,
+        captions,
+    ) else {
+        return estimate;
     };
-
-    info!(
-        "Creating the blank output image with color {}.",
-        app.background_color
+    let rgba = |(w, h): (u32, u32)| w as u64 * h as u64 * 4;
+    let tiles: u64 = plan
+        .layout
+        .tiles
+        .iter()
+        .map(|tile| rgba((tile.width, tile.height)))
+        .sum();
+    let (_, _, height) = bottom_bands(
+        app,
+        image_dir,
+        name,
+        plan.inputs.len(),
+        (plan.layout.width, plan.layout.height),
     );
-    let mut out_image = RgbaImage::from_pixel(width, height, hex_to_color(&app.background_color)?);
+    let canvas = app.eink.unwrap_or((plan.layout.width, height));
+    estimate.peak_bytes = tiles + estimate.peak_bytes.max(rgba((plan.layout.width, height)));
+    estimate.canvas = Some(canvas);
+    estimate
+}
 
-    info!("Copying the {n} images to the output image.");
-    // Copy the images to the output image.
-    let mut x = app.left_margin;
-    let mut y = app.top_margin;
-    for image in &model.images {
-        let (dx, dy) = match app.orientation {
-            Orientation::Portrait => (0, image.height() + app.spacing),
-            Orientation::Landscape => (image.width() + app.spacing, 0),
+// The images picked by a year in review or a mood board, each with the month
+// or section it goes in.
+struct Groups {
+    of: HashMap<PathBuf, usize>,
+    mood_board: Option<MoodBoard>,
+}
+
+// The tiles of a collage, with the inputs in the order of the tiles.
+struct Plan<T> {
+    inputs: Vec<(PathBuf, T)>,
+    layout: Layout,
+    qr_tile: Option<Tile>,
+    histogram_tiles: Vec<Tile>,
+}
+
+// Lay out `inputs`, shuffling, repeating and leaving them out as the options
+// ask. An input is a decoded image or, to estimate the collage before any
+// image is decoded, only what its header says, so `dims` gives its size.
+fn plan<T: Clone>(
+    app: &App,
+    mut inputs: Vec<(PathBuf, T)>,
+    dims: impl Fn(&T) -> (u32, u32),
+    rng: &mut ChaCha8Rng,
+    groups: &Groups,
+    captions: Option<&Captions>,
+) -> Result<Plan<T>> {
+    if app.shuffle {
+        info!("Shuffling the images.");
+        inputs.shuffle(rng);
+    }
+
+    info!("Setting the global image dimensions.");
+    // If the user didn't specify the width or height, then we use the
+    // width and height of the first image.
+    let (first_width, first_height) = dims(&inputs[0].1);
+    let image_width = app.image_width.unwrap_or(first_width);
+    let image_height = app.image_height.unwrap_or(first_height);
+
+    // Filling a canvas repeats the images for as many cells as it has.
+    let fill = app.fill.map(|(width, height)| FillLayout { width, height });
+    if let Some(fill) = &fill {
+        let cells = fill.cells(&app.layout_options(image_width, image_height));
+        inputs = inputs.iter().cycle().take(cells).cloned().collect();
+    }
+
+    // A short last row of a grid may be left out.
+    if let Some(columns) = app.columns.map(|c| c as usize) {
+        let short = inputs.len() % columns;
+        if app.last_row == LastRow::Hide && inputs.len() > columns && short > 0 {
+            info!("Leaving out the {short} images of the short last row.");
+            inputs.truncate(inputs.len() - short);
+        }
+    }
+
+    info!("Calculating the layout of the output image.");
+    // All images get the same width (for portrait) or height (for
+    // landscape). The layout is computed from the target sizes so
+    // that an oversized output is rejected before any resizing.
+    let mut sizes: Vec<(u32, u32)> = inputs
+        .iter()
+        .map(|(_, input)| image_size(dims(input), image_width, image_height, app))
+        .collect();
+    // The strips under the images, for the captions, the EXIF info
+    // and the histograms, are part of the tiles until the layout is
+    // done.
+    let (caption_scale, mut caption) = caption_strip(image_height);
+    if !app.exif_info && captions.is_none() {
+        caption = 0;
+    }
+    let band = match app.histogram {
+        Some(HistogramMode::PerImage) => histogram_strip(image_height),
+        _ => 0,
+    };
+    let strip = caption + band;
+    for size in &mut sizes {
+        size.1 += strip;
+    }
+    // The QR code tile goes after the images.
+    let qr_tile = app.qr.is_some() && app.qr_position == QrPosition::Tile;
+    if qr_tile {
+        sizes.push((image_width, image_height));
+    }
+    let engine: Box<dyn LayoutEngine> = if app.compare.is_some() {
+        Box::new(CompareLayout {
+            diagonal: app.compare_style == CompareStyle::Diagonal,
+            labels: app.compare_labels,
+        })
+    } else if app.year_in_review {
+        Box::new(RowsLayout {
+            rows: review::rows(inputs.iter().map(|(path, _)| groups.of[path])),
+        })
+    } else if let Some(columns) = app.columns {
+        Box::new(GridLayout {
+            columns: columns as usize,
+            last_row: app.last_row,
+        })
+    } else if app.min_per_row.is_some() || app.max_per_row.is_some() {
+        let min = app.min_per_row.unwrap_or(1) as usize;
+        let max = app.max_per_row.map_or(sizes.len(), |max| max as usize);
+        let rows = layout::balanced_rows(sizes.len(), min, max)?;
+        Box::new(RowsLayout {
+            rows: rows
+                .into_iter()
+                .map(|count| Row {
+                    label: String::new(),
+                    count,
+                })
+                .collect(),
+        })
+    } else if let Some(fill) = fill {
+        Box::new(fill)
+    } else if let Some(board) = &groups.mood_board {
+        Box::new(SectionsLayout {
+            sections: board.rows(inputs.iter().map(|(path, _)| groups.of[path])),
+            columns: board.columns,
+        })
+    } else {
+        app.layout_engine()
+    };
+    let options = app.layout_options(image_width, image_height + strip);
+    let mut layout = engine.layout(&sizes, &options)?;
+    let mut qr_tile = if qr_tile { layout.tiles.pop() } else { None };
+    let mut histogram_tiles = take_histogram_strips(&mut layout, band);
+    if caption > 0 {
+        add_caption_strips(
+            &mut layout,
+            &inputs,
+            caption_scale,
+            caption,
+            captions,
+            app.exif_info,
+        );
+    }
+    if let Some(n) = app.grid_snap {
+        layout.snap(n)?;
+        qr_tile
+            .iter_mut()
+            .chain(&mut histogram_tiles)
+            .for_each(|tile| tile.snap(n));
+    }
+    Ok(Plan {
+        inputs,
+        layout,
+        qr_tile,
+        histogram_tiles,
+    })
+}
+
+// The combined histogram band and the footer below the layout of a `width`
+// x `height` collage of `count` images, and the height of the collage with
+// them.
+fn bottom_bands(
+    app: &App,
+    image_dir: &Path,
+    name: &OsStr,
+    count: usize,
+    (width, mut height): (u32, u32),
+) -> (Option<Tile>, Option<Label>, u32) {
+    // The combined histogram is a band across the bottom of the collage.
+    let combined_histogram = (app.histogram == Some(HistogramMode::Combined)).then(|| {
+        let band = histogram_strip(width);
+        let tile = Tile {
+            x: app.left_margin.min(width - 1),
+            y: height.saturating_sub(app.top_margin) + app.spacing(),
+            width: width.saturating_sub(2 * app.left_margin).max(1),
+            height: band,
         };
-        out_image.copy_from(image, x, y)?;
-        y += dy;
-        x += dx;
+        height = tile.y + band + app.top_margin;
+        tile
+    });
+    // The footer is a line of text in a band below everything else.
+    let footer = app.footer.as_ref().map(|template| {
+        let folder = image_dir.file_name().unwrap_or(image_dir.as_os_str());
+        let now = metadata::utc_now();
+        let text = template
+            .replace(
+                "{date}",
+                &format!("{:04}-{:02}-{:02}", now.year, now.month, now.day),
+            )
+            .replace("{count}", &count.to_string())
+            .replace("{folder}", &folder.to_string_lossy())
+            .replace("{name}", &name.to_string_lossy());
+        let room = width.saturating_sub(2 * app.left_margin);
+        let scale = (room / text::width(&text, 1).max(1)).clamp(1, 4);
+        let glyph = text::GLYPH_SIZE * scale;
+        let label = Label {
+            x: app.left_margin,
+            y: height.saturating_sub(app.top_margin) + app.spacing().max(glyph / 2),
+            scale,
+            text: text.chars().take((room / glyph) as usize).collect(),
+        };
+        height = label.y + glyph + glyph / 2 + app.top_margin;
+        label
+    });
+    (combined_histogram, footer, height)
+}
+
+// Copy the resized images to their tiles of the output image, blending
+// transparent images over the background in the order of `--z-order`, along
+// with their histograms and rating badges.
+fn draw_images(
+    out_image: &mut RgbaImage,
+    app: &App,
+    images: &[RgbaImage],
+    tiles: &[Tile],
+    paths: &[PathBuf],
+    histogram_tiles: &[Tile],
+    seed: u64,
+) -> Result<()> {
+    let background = hex_to_color(&app.background_color)?;
+    let order = zorder::order(app.z_order, tiles, paths, |path| app.z_of(path), seed);
+    #[cfg(feature = "gpu")]
+    let on_gpu = app.gpu && {
+        let layers: Vec<_> = order
+            .iter()
+            .map(|&i| gpu::Layer {
+                image: &images[i],
+                x: tiles[i].x,
+                y: tiles[i].y,
+                opacity: app.opacity_of(&paths[i]),
+            })
+            .collect();
+        match gpu::composite(out_image, &layers, app.linear) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("Compositing on the CPU: {e:#}");
+                false
+            }
+        }
+    };
+    #[cfg(not(feature = "gpu"))]
+    let on_gpu = false;
+    if !on_gpu {
+        for i in order {
+            let (image, tile, path) = (&images[i], &tiles[i], &paths[i]);
+            let opacity = app.opacity_of(path);
+            if opacity < 1.0 {
+                let mut faded = image.clone();
+                color::fade(&mut faded, opacity);
+                color::overlay(out_image, &faded, tile.x, tile.y, app.linear);
+            } else {
+                color::overlay(out_image, image, tile.x, tile.y, app.linear);
+            }
+        }
+    }
+    for (image, tile) in images.iter().zip(histogram_tiles) {
+        let mut histogram = Histogram::default();
+        histogram.add(image);
+        let band = histogram.render(tile.width, tile.height, background);
+        imageops::replace(out_image, &band, tile.x as i64, tile.y as i64);
     }
+    if app.show_ratings {
+        for (path, tile) in paths.iter().zip(tiles) {
+            let Some(rating) = metadata::rating(path) else {
+                continue;
+            };
+            let size = (tile.width.min(tile.height) / 12).max(8);
+            let (_, badge) = ratings::badge_size(size);
+            let y = (tile.y + tile.height).saturating_sub(badge + size / 2);
+            ratings::draw_badge(out_image, tile.x + size / 2, y, size, rating);
+        }
+    }
+    Ok(())
+}
 
-    info!("Saving the output image.");
-    // Save the output image to the downloads dir as a png.
-    let dirs = UserDirs::new().expect("Failed to get user dirs");
-    let dir = dirs.download_dir().expect("Failed to get download dir");
-    let path = format!(r"{}/{}", dir.to_string_lossy(), "collage");
-    let mut num = 0;
-    let mut sketch = PathBuf::from(format!(r"{path}_{num}"));
-    sketch.set_extension("png");
-    while sketch.exists() {
-        num += 1;
-        sketch = PathBuf::from(format!(r"{path}_{num}"));
-        sketch.set_extension("png");
-    }
-    out_image.save(sketch)?;
+// The finished tile `index` from the checkpoint, if we are resuming and it
+// has the right size.
+fn resumed_tile(
+    checkpoint: Option<&Checkpoint>,
+    app: &App,
+    index: usize,
+    tile: &Tile,
+) -> Option<RgbaImage> {
+    if !app.resume {
+        return None;
+    }
+    checkpoint?
+        .tile(index)
+        .filter(|image| image.dimensions() == (tile.width, tile.height))
+}
+
+// Resize an image to exactly width x height.
+fn resize(image: &DynamicImage, width: u32, height: u32, app: &App) -> RgbaImage {
+    if app.pixel_art {
+        pixel_art(image, width, height)
+    } else if app.linear {
+        color::resize_linear(image, width, height, app.filter)
+    } else {
+        image
+            .resize_exact(width, height, app.filter.filter_type())
+            .into_rgba8()
+    }
+}
+
+// Scale an image by the largest whole factor (or divide it by the smallest)
+// that fits within width x height, with nearest neighbor sampling so that
+// every pixel becomes a sharp square, and center it. The rest of the box is
+// left transparent.
+fn pixel_art(image: &DynamicImage, width: u32, height: u32) -> RgbaImage {
+    let (iw, ih) = (image.width().max(1), image.height().max(1));
+    let (w, h) = match (width / iw).min(height / ih) {
+        0 => {
+            let divisor = iw.div_ceil(width).max(ih.div_ceil(height));
+            ((iw / divisor).max(1), (ih / divisor).max(1))
+        }
+        factor => (iw * factor, ih * factor),
+    };
+    let scaled = image.resize_exact(w, h, FilterType::Nearest).into_rgba8();
+    let mut boxed = RgbaImage::new(width, height);
+    imageops::replace(
+        &mut boxed,
+        &scaled,
+        (width.saturating_sub(w) / 2) as i64,
+        (height.saturating_sub(h) / 2) as i64,
+    );
+    boxed
+}
+
+// Scale an image to fit within width x height in both dimensions, preserving
+// its aspect ratio, and center it. The rest of the box is left transparent so
+// the background shows through.
+fn contain(image: &DynamicImage, width: u32, height: u32, app: &App) -> RgbaImage {
+    let scale = f64::min(
+        width as f64 / image.width() as f64,
+        height as f64 / image.height() as f64,
+    );
+    let w = ((image.width() as f64 * scale).round() as u32).clamp(1, width);
+    let h = ((image.height() as f64 * scale).round() as u32).clamp(1, height);
+    let resized = resize(image, w, h, app);
+    let mut boxed = match app.letterbox {
+        Letterbox::Background => RgbaImage::new(width, height),
+        Letterbox::Edge => {
+            RgbaImage::from_pixel(width, height, color::edge_color(&resized, w < width))
+        }
+        Letterbox::Dominant => {
+            RgbaImage::from_pixel(width, height, color::dominant_color(&resized))
+        }
+    };
+    imageops::replace(
+        &mut boxed,
+        &resized,
+        ((width - w) / 2) as i64,
+        ((height - h) / 2) as i64,
+    );
+    boxed
+}
+
+// Ask the user to confirm `question`. With `--yes` the answer is always yes
+// and with `--no-input` always no. If stdin is not a terminal nobody can
+// answer, so we proceed.
+fn confirm(app: &App, question: &str) -> Result<bool> {
+    if app.yes {
+        return Ok(true);
+    }
+    if app.no_input {
+        return Ok(false);
+    }
+    if !io::stdin().is_terminal() {
+        warn!("Stdin is not a terminal, proceeding without confirmation.");
+        return Ok(true);
+    }
+
+    print!("{question}");
+    io::stdout().flush()?;
+
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+
+    match input.trim().to_lowercase().as_str() {
+        "y" | "yes" => {
+            if !app.quiet {
+                println!("Proceeding...");
+            }
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+// Open an image, reusing the decoded image from the cache if there is one.
+fn open_image(
+    path: &Path,
+    limits: &Limits,
+    cache: Option<&ImageCache>,
+) -> Result<Arc<DynamicImage>> {
+    let Some(cache) = cache else {
+        return Ok(Arc::new(decode::open(path, limits)?));
+    };
+    if let Some(img) = cache.lock().unwrap().get(path) {
+        return Ok(img.clone());
+    }
+    let img = Arc::new(decode::open(path, limits)?);
+    cache
+        .lock()
+        .unwrap()
+        .insert(path.to_path_buf(), img.clone());
+    Ok(img)
+}
+
+// Open an image as an input of the collage, trimmed if `--trim` is given
+// and turned if `--auto-rotate-tiles` is.
+fn open_input(app: &App, path: &Path, cache: Option<&ImageCache>) -> Result<Arc<DynamicImage>> {
+    let mut image = open_image(path, &app.decode_limits(), cache)?;
+    if app.trim {
+        if let Some(trimmed) = trim::trim(&image) {
+            debug!(
+                "Trimmed {} from {}x{} to {}x{}.",
+                path.display(),
+                image.width(),
+                image.height(),
+                trimmed.width(),
+                trimmed.height()
+            );
+            image = Arc::new(trimmed);
+        }
+    }
+    if app.rotates(image.width(), image.height()) {
+        debug!("Turning {} to fit its tile.", path.display());
+        image = Arc::new(image.rotate90());
+    }
+    Ok(image)
+}
+
+// Parse an opacity between 0 and 1.
+fn parse_opacity(opacity: &str) -> Result<f32, String> {
+    match opacity.trim().parse::<f32>() {
+        Ok(o) if (0.0..=1.0).contains(&o) => Ok(o),
+        _ => Err(format!(
+            "Invalid opacity `{opacity}`, expected a number from 0 to 1"
+        )),
+    }
+}
+
+// Parse the opacity of a single image, e.g. `sky.jpg=0.5`.
+fn parse_image_opacity(value: &str) -> Result<(String, f32), String> {
+    let (file, opacity) = value
+        .rsplit_once('=')
+        .ok_or_else(|| format!("Invalid image opacity `{value}`, expected `FILE=OPACITY`"))?;
+    Ok((file.to_string(), parse_opacity(opacity)?))
+}
+
+// Parse how many times a single image appears, e.g. `logo.png=5`.
+fn parse_image_count(value: &str) -> Result<(String, u32), String> {
+    let (file, count) = value
+        .rsplit_once('=')
+        .ok_or_else(|| format!("Invalid image count `{value}`, expected `FILE=COUNT`"))?;
+    let count = count
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid image count `{value}`, expected a whole number"))?;
+    Ok((file.to_string(), count))
+}
+
+// Parse the stacking level of a single image, e.g. `hero.jpg=1`.
+fn parse_image_z(value: &str) -> Result<(String, i32), String> {
+    let (file, z) = value
+        .rsplit_once('=')
+        .ok_or_else(|| format!("Invalid image z `{value}`, expected `FILE=Z`"))?;
+    let z = z
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid image z `{value}`, expected a whole number"))?;
+    Ok((file.to_string(), z))
+}
+
+// Parse a size in bytes with an optional (decimal) unit, e.g. `500MB`.
+fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("Invalid size `{size}`"))?;
+    let scale = match unit.trim().to_uppercase().as_str() {
+        "" | "B" => 1.0,
+        "KB" | "K" => 1e3,
+        "MB" | "M" => 1e6,
+        "GB" | "G" => 1e9,
+        "TB" | "T" => 1e12,
+        _ => {
+            return Err(format!(
+                "Invalid unit `{unit}`, expected B, KB, MB, GB or TB"
+            ))
+        }
+    };
+    Ok((number * scale) as u64)
+}
+
+// Parse a duration with a unit, e.g. `90s`, `30m`, `1h` or `1d`.
+fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let duration = duration.trim();
+    let split = duration
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(duration.len());
+    let (number, unit) = duration.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("Invalid duration `{duration}`"))?;
+    let scale = match unit.trim().to_lowercase().as_str() {
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        "d" => 86400.0,
+        _ => return Err(format!("Invalid unit `{unit}`, expected s, m, h or d")),
+    };
+    if number * scale < 1.0 {
+        return Err(format!(
+            "Invalid duration `{duration}`, expected at least 1s"
+        ));
+    }
+    Duration::try_from_secs_f64(number * scale)
+        .map_err(|_| format!("Invalid duration `{duration}`, it's too long"))
+}
+
+// Parse dimensions in pixels, e.g. `1920x1080`.
+fn parse_dimensions(dimensions: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("Invalid dimensions `{dimensions}`, expected e.g. `1920x1080`");
+    let (width, height) = dimensions
+        .trim()
+        .split_once(['x', 'X'])
+        .ok_or_else(invalid)?;
+    let parse = |n: &str| n.trim().parse::<u32>().ok().filter(|&n| n > 0);
+    Ok((
+        parse(width).ok_or_else(invalid)?,
+        parse(height).ok_or_else(invalid)?,
+    ))
+}
+
+// Convert a hex code to a color.
+pub fn hex_to_color(hex: &str) -> Result<Rgba<u8>> {
+    let hex_code = hex.strip_prefix('#').map_or(hex, |stripped| stripped);
+    ensure!(hex_code.len() == 6, "Invalid hex code length");
+
+    let red = u8::from_str_radix(&hex_code[..2], 16).context("Invalid hex code for red channel")?;
+    let green =
+        u8::from_str_radix(&hex_code[2..4], 16).context("Invalid hex code for green channel")?;
+    let blue =
+        u8::from_str_radix(&hex_code[4..6], 16).context("Invalid hex code for blue channel")?;
+
+    Ok(Rgba([red, green, blue, 255]))
+}
+
+// Initialize the logger at the level given by the verbosity flags, writing
+// one JSON object per line if requested.
+fn init_logger(app: &App) {
+    let format = app.log_format;
+    let mut builder = env_logger::Builder::from_env(
+        env_logger::Env::default().default_filter_or(LevelFilter::Warn.as_str()),
+    );
+    // The verbosity flags take precedence over RUST_LOG.
+    if app.quiet {
+        builder.filter_level(LevelFilter::Error);
+    } else if app.verbose > 0 {
+        builder.filter_level(match app.verbose {
+            1 => LevelFilter::Info,
+            2 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        });
+    }
+    // wgpu logs its probing of every graphics backend, failures included,
+    // which is only of interest when debugging.
+    #[cfg(feature = "gpu")]
+    if app.verbose < 2 {
+        builder.filter_module("wgpu_core", LevelFilter::Off);
+        builder.filter_module("wgpu_hal", LevelFilter::Off);
+    }
+    if format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let line = serde_json::json!({
+                "timestamp": buf.timestamp().to_string(),
+                "level": record.level().to_string(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            });
+            writeln!(buf, "{line}")
+        });
+    }
+    builder.init();
+}
+
+// Parse the command line, using the config file for the defaults.
+fn parse_app() -> Result<App> {
+    parse_args(std::env::args_os().collect())
+}
+
+// Parse the options of `args`, with the defaults of the config file.
+fn parse_args(args: Vec<OsString>) -> Result<App> {
+    let app = App::from_arg_matches(&configured_command(&args)?.get_matches_from(&args))?;
+    Ok(App { args, ..app })
+}
+
+// The settings that only control the run rather than what the collage looks
+// like, along with where its images come from and where it goes, by their
+// options as in `App::settings`.
+const RUN_SETTINGS: &[&str] = &[
+    "image_dir",
+    "album",
+    "seed",
+    "log-format",
+    "name",
+    "name-by-hash",
+    "config",
+    "yes",
+    "no-input",
+    "confirm-over",
+    "dry-run",
+    "resume",
+    "quiet",
+    "verbose",
+    "threads",
+    "gpu",
+    "output",
+    "image-map",
+    "link-template",
+    "manifest",
+    "sidecar",
+    "notify",
+    "notify-url",
+    "upload",
+    "c2pa-cert",
+    "c2pa-key",
+];
+
+// Whether the values of `arg` may name files, URLs or commands or be any
+// text: those of a path or a string other than a color, and `FILE=...` pairs.
+#[cfg(feature = "c2pa")]
+fn is_private(arg: &clap::Arg) -> bool {
+    let type_id = arg.get_value_parser().type_id();
+    let names: Vec<&str> = arg
+        .get_value_names()
+        .into_iter()
+        .flatten()
+        .map(|name| name.as_str())
+        .collect();
+    type_id == std::any::TypeId::of::<PathBuf>()
+        || (type_id == std::any::TypeId::of::<String>() && names != ["COLOR"])
+        || names.iter().any(|name| name.starts_with("FILE="))
+}
+
+// Parse the options of `args` like `parse_args`, but return invalid options
+// as an error rather than exiting.
+fn try_parse_args(args: Vec<OsString>) -> Result<App> {
+    let matches = configured_command(&args)?.try_get_matches_from(&args)?;
+    let app = App::from_arg_matches(&matches)?;
+    Ok(App { args, ..app })
+}
+
+// The command line parser with the defaults of the config file that `args`
+// name with `--config`, or else of the default config file.
+fn configured_command(args: &[OsString]) -> Result<clap::Command> {
+    // A first lenient pass just to find the config file.
+    let matches = config::command().ignore_errors(true).get_matches_from(args);
+    let path = matches
+        .get_one::<PathBuf>("config")
+        .cloned()
+        .or_else(config::default_config_path);
+
+    let command = config::command();
+    match path {
+        Some(path) => config::apply_config(command, &path),
+        None => Ok(command),
+    }
+}
+
+fn main() -> Result<()> {
+    let app = parse_app()?;
+    init_logger(&app);
+
+    match &app.command {
+        Some(Command::Completions { shell }) => {
+            clap_complete::generate(*shell, &mut config::command(), "collage", &mut io::stdout());
+            Ok(())
+        }
+        Some(Command::Run { jobs }) => run_jobs(jobs),
+        Some(Command::Split {
+            image,
+            columns,
+            rows,
+            overlap,
+            output,
+        }) => run_split(image, *columns, *rows, *overlap, output.as_deref()),
+        Some(Command::Append { collage }) => run_append(collage),
+        Some(Command::Bench { dir, args }) => run_bench(dir, args),
+        Some(Command::Daemon {
+            every,
+            watch,
+            config,
+            wallpaper,
+            args,
+        }) => run_daemon(*every, *watch, config.as_deref(), *wallpaper, args),
+        None => run(&app, None).map(drop),
+    }
+}
+
+// Cut the image at `path` into a grid of tiles and save each one.
+fn run_split(
+    path: &Path,
+    columns: u32,
+    rows: u32,
+    overlap: u32,
+    output: Option<&Path>,
+) -> Result<()> {
+    let image = decode::open(path, &Limits::no_limits())
+        .with_context(|| format!("Failed to open {}", path.display()))?
+        .to_rgba8();
+    let tiles = split::split(&image, columns, rows, overlap)?;
+    let dir = output_dir(output)?;
+    let name = path.file_stem().unwrap_or(OsStr::new("tile"));
+    for (i, tile) in tiles.iter().enumerate() {
+        let mut file_name = name.to_os_string();
+        file_name.push(format!("_{}.png", i + 1));
+        output::save_atomic(tile, &dir.join(file_name))?;
+    }
+    println!("Saved {} tiles to {}.", tiles.len(), dir.display());
     Ok(())
 }
+
+// Render the collage of `dir` with every resize filter, on a single thread
+// and on all of them, into a temporary directory, and report the times and
+// the quality against a Lanczos resize in linear light.
+fn run_bench(dir: &Path, args: &[String]) -> Result<()> {
+    let mut command_line = vec![OsString::from("collage"), dir.as_os_str().to_os_string()];
+    command_line.extend(args.iter().map(OsString::from));
+    let base = config::command()
+        .try_get_matches_from(command_line)
+        .and_then(|matches| App::from_arg_matches(&matches))
+        .context("Invalid options for the benchmark")?;
+    base.validate()?;
+    ensure!(!base.batch, "--batch can't be benchmarked");
+    // Every render gets the same seed, so that they all have the same layout.
+    let seed = base.seed.unwrap_or_else(rand::random);
+
+    let tmp = std::env::temp_dir().join(format!("collage_bench_{}", std::process::id()));
+    fs::create_dir_all(&tmp).with_context(|| format!("Failed to create {}", tmp.display()))?;
+    let render =
+        |filter: ResizeFilter, linear: bool, threads: usize| -> Result<(RunResult, RgbaImage)> {
+            let app = App {
+                filter,
+                linear,
+                threads: Some(threads as u32),
+                output: Some(tmp.join("bench.png")),
+                quiet: true,
+                yes: true,
+                resume: false,
+                name_by_hash: false,
+                image_map: None,
+                ..base.clone()
+            };
+            let result = collage(&app, dir, &app.name, seed, None)?
+                .context("The benchmark was cancelled")?;
+            let image = image::open(&result.output)
+                .with_context(|| format!("Failed to read {}", result.output.display()))?
+                .into_rgba8();
+            Ok((result, image))
+        };
+
+    let cpus = thread::available_parallelism().map_or(1, |n| n.get());
+    let mut threads = vec![1, cpus];
+    threads.dedup();
+    let rows = (|| {
+        // The reference is rendered first, which also warms up the file cache
+        // for the timed renders.
+        eprintln!("Rendering the reference.");
+        let (_, reference) = render(ResizeFilter::Lanczos3, true, cpus)?;
+        let mut rows = Vec::new();
+        for filter in ResizeFilter::value_variants() {
+            for &threads in &threads {
+                eprintln!(
+                    "Rendering with {} on {threads} thread(s).",
+                    filter.to_possible_value().unwrap().get_name()
+                );
+                let (result, image) = render(*filter, base.linear, threads)?;
+                rows.push(bench::Row {
+                    filter: *filter,
+                    threads,
+                    total_ms: result.duration_ms,
+                    resize_ms: result.stages.resize_ms,
+                    psnr: bench::psnr(&image, &reference),
+                });
+            }
+        }
+        Ok::<_, anyhow::Error>(rows)
+    })();
+    let _ = fs::remove_dir_all(&tmp);
+    bench::print(&rows?);
+    Ok(())
+}
+
+// Every file in `dir` and its subdirectories, in natural order.
+fn list_files(dir: &Path) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = WalkDir::new(dir)
+        .into_iter()
+        .flatten()
+        .map(|d| d.path().to_path_buf())
+        .collect();
+    paths.retain(|path| path.is_file());
+    paths.sort_by(|a, b| sort::natural_cmp(a, b));
+    paths
+}
+
+// Add the images that are new in the image directory of the collage at
+// `path` to it. The collage is laid out again with the new images after the
+// ones it has, and only the new ones are drawn, which works as long as the
+// images it has stay where they are.
+fn run_append(path: &Path) -> Result<()> {
+    let mut manifest = Manifest::load(path)?;
+    let image_dir = manifest.image_dir.clone();
+    ensure!(
+        image_dir.is_dir(),
+        "The image directory {} does not exist",
+        image_dir.display()
+    );
+    let app = parse_args(manifest.args.iter().map(OsString::from).collect())
+        .context("Invalid options in the manifest")?;
+    let app = App {
+        image_dir: Some(image_dir.clone()),
+        output: Some(path.to_path_buf()),
+        batch: false,
+        name_by_hash: false,
+        resume: false,
+        dry_run: false,
+        ..app
+    };
+    ensure!(
+        !app.shuffle,
+        "{} is shuffled, so new images can't be added to it",
+        path.display()
+    );
+    let old: Vec<PathBuf> = manifest.images.iter().map(|i| i.path.clone()).collect();
+    // Auto spacing stays as it was for the images the collage has.
+    let app = &app.resolve_spacing(&old);
+
+    let known: HashSet<&PathBuf> = old.iter().collect();
+    let new: Vec<PathBuf> = list_files(&image_dir)
+        .into_iter()
+        .filter(|p| !known.contains(&p.canonicalize().unwrap_or_else(|_| p.clone())))
+        .collect();
+    let (inputs, skipped) = decode_images(app, &new, None)?;
+    if inputs.is_empty() {
+        println!("There are no new images in {}.", image_dir.display());
+        return Ok(());
+    }
+
+    let mut all: Vec<_> = manifest
+        .images
+        .iter()
+        .map(|image| {
+            let size = (image.width, image.height);
+            (image.path.clone(), (size, None::<Arc<DynamicImage>>))
+        })
+        .collect();
+    all.extend(repeat_counted(app, inputs));
+    let captions = app
+        .captions_from
+        .as_deref()
+        .map(Captions::load)
+        .transpose()?;
+    let groups = Groups {
+        of: HashMap::new(),
+        mood_board: None,
+    };
+    let mut rng = ChaCha8Rng::seed_from_u64(manifest.seed);
+    let Plan {
+        inputs,
+        layout,
+        histogram_tiles,
+        ..
+    } = plan(
+        app,
+        all,
+        |(size, _)| *size,
+        &mut rng,
+        &groups,
+        captions.as_ref(),
+    )?;
+    let count = old.len();
+    ensure!(
+        inputs.len() > count
+            && inputs[..count].iter().map(|(path, _)| path).eq(&old)
+            && layout.tiles[..count] == manifest.layout.tiles[..]
+            && layout.labels.starts_with(&manifest.layout.labels),
+        "The new images would move the images already in {}, so it has to be made again",
+        path.display()
+    );
+
+    let background = hex_to_color(&app.background_color)?;
+    let mut out_image = match app.texture {
+        Some(t) => texture::render(t, layout.width, layout.height),
+        None => RgbaImage::from_pixel(layout.width, layout.height, background),
+    };
+    let collage = decode::open(path, &Limits::no_limits())
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    imageops::replace(&mut out_image, &collage.to_rgba8(), 0, 0);
+
+    let (paths, sizes): (Vec<PathBuf>, Vec<(u32, u32)>) = inputs
+        .iter()
+        .map(|(path, (size, _))| (path.clone(), *size))
+        .unzip();
+    let state = State {
+        files: Vec::new(),
+        paths: paths.clone(),
+        sizes: sizes.clone(),
+        skipped,
+        layout: layout.clone(),
+        qr_tile: None,
+        histogram_tiles: histogram_tiles.clone(),
+        seed: manifest.seed,
+    };
+    let repeat = paths.iter().collect::<HashSet<_>>().len();
+    let images = inputs
+        .into_iter()
+        .enumerate()
+        .skip(count)
+        .map(|(i, (_, (_, image)))| prepare_tile(app, &state, i, image, repeat, None, None))
+        .collect::<Result<Vec<_>>>()?;
+    draw_images(
+        &mut out_image,
+        app,
+        &images,
+        &layout.tiles[count..],
+        &paths[count..],
+        histogram_tiles.get(count..).unwrap_or_default(),
+        manifest.seed,
+    )?;
+    for label in &layout.labels[manifest.layout.labels.len()..] {
+        let color = text::contrasting(background);
+        text::draw(
+            &mut out_image,
+            &label.text,
+            label.x,
+            label.y,
+            label.scale,
+            color,
+        );
+    }
+
+    if ImageFormat::from_path(path).ok() == Some(ImageFormat::Gif) {
+        output::save_indexed(&quantize::quantize(&out_image, 256, app.dither), path)?;
+    } else {
+        output::save_atomic(&out_image, path)?;
+    }
+    manifest.images = paths
+        .iter()
+        .zip(&sizes)
+        .map(|(path, &(width, height))| manifest::Image {
+            path: path.canonicalize().unwrap_or_else(|_| path.clone()),
+            width,
+            height,
+        })
+        .collect();
+    manifest.layout = layout;
+    manifest.save(path)?;
+    println!("Added {} images to {}.", images.len(), path.display());
+    Ok(())
+}
+
+// The most jobs a `parallel` jobs file runs at once. Each job holds all of
+// its images decoded, and resizes them on every core already.
+const PARALLEL_JOBS: usize = 4;
+
+// Create every collage described in the jobs file, sequentially or in
+// parallel, sharing decoded images between the jobs.
+fn run_jobs(path: &Path) -> Result<()> {
+    let job_file = jobs::JobFile::load(path)?;
+    let base = path.parent().unwrap_or(Path::new("."));
+    let apps = job_file
+        .jobs
+        .iter()
+        .enumerate()
+        .map(|(i, job)| {
+            jobs::job_args(job, base)
+                .and_then(try_parse_args)
+                .with_context(|| format!("Invalid job {}", i + 1))
+        })
+        .collect::<Result<Vec<App>>>()?;
+
+    // Each worker takes the next job until there are none left. Once a job
+    // is done, the images that no job still to finish reads are dropped from
+    // the cache, so that it holds the images of a few jobs at most.
+    let workers = if job_file.parallel {
+        apps.len().min(PARALLEL_JOBS)
+    } else {
+        1
+    };
+    let cache = ImageCache::default();
+    let next = AtomicUsize::new(0);
+    let done = Mutex::new(vec![false; apps.len()]);
+    thread::scope(|s| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                s.spawn(|| -> Result<()> {
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(app) = apps.get(i) else {
+                            return Ok(());
+                        };
+                        run(app, Some(&cache))?;
+                        let mut done = done.lock().unwrap();
+                        done[i] = true;
+                        let pending: Vec<&App> = apps
+                            .iter()
+                            .zip(done.iter())
+                            .filter(|(_, &done)| !done)
+                            .map(|(app, _)| app)
+                            .collect();
+                        cache
+                            .lock()
+                            .unwrap()
+                            .retain(|path, _| pending.iter().any(|app| app.reads(path)));
+                    }
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().expect("Job thread panicked"))
+            .collect::<Result<Vec<_>>>()
+    })?;
+    Ok(())
+}
+
+// How often `collage daemon --watch` looks for changes to the images.
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+// Make the collage described by `args` and the config file every `every` and,
+// with `watch`, whenever the files in its image directory change, until the
+// process is stopped. A collage that fails is only a warning, so that the
+// daemon outlives an unreadable photo or a failed upload, and so are settings
+// that no longer parse, e.g. while the config file is being edited: the last
+// good ones are kept until they're fixed.
+fn run_daemon(
+    every: Option<Duration>,
+    watch: bool,
+    config: Option<&Path>,
+    wallpaper: bool,
+    args: &[String],
+) -> Result<()> {
+    let mut command_line = vec![OsString::from("collage")];
+    if let Some(config) = config {
+        command_line.push(OsString::from("--config"));
+        command_line.push(config.as_os_str().to_os_string());
+    }
+    command_line.extend(args.iter().map(OsString::from));
+
+    let (mut app, mut output) = daemon_settings(&command_line)?;
+    let mut next = Instant::now();
+    let mut last_files = None;
+    loop {
+        let files = watch.then(|| {
+            // An album exported from Photos can't be watched, but the folder
+            // of one can.
+            let album = app
+                .album
+                .as_deref()
+                .filter(|_| !cfg!(target_os = "macos"))
+                .and_then(|album| library::folder(album).ok());
+            let dirs: Vec<&PathBuf> = app
+                .compare
+                .iter()
+                .flatten()
+                .chain(&app.image_dir)
+                .chain(&album)
+                .collect();
+            fingerprint(&dirs, &output)
+        });
+        let due = every.is_some() && Instant::now() >= next;
+        if due || files != last_files {
+            if let Some(every) = every {
+                next = Instant::now() + every;
+            }
+            match run(&app, None) {
+                Ok(saved) if wallpaper => {
+                    saved.iter().take(1).for_each(|p| desktop::set_wallpaper(p))
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Failed to make the collage: {e:#}"),
+            }
+            last_files = files;
+        }
+        let wait = match every {
+            Some(_) => next.saturating_duration_since(Instant::now()),
+            None => WATCH_INTERVAL,
+        };
+        thread::sleep(if watch {
+            wait.min(WATCH_INTERVAL)
+        } else {
+            wait
+        });
+
+        match daemon_settings(&command_line) {
+            Ok(settings) => (app, output) = settings,
+            Err(e) => warn!("Keeping the last good settings: {e:#}"),
+        }
+    }
+}
+
+// The settings of the collage of `collage daemon` and the file it replaces.
+fn daemon_settings(command_line: &[OsString]) -> Result<(App, PathBuf)> {
+    let app = try_parse_args(command_line.to_vec())?;
+    match &app.output {
+        Some(output) if !app.batch && !output.is_dir() => {
+            let output = output.clone();
+            Ok((app, output))
+        }
+        _ => bail!("collage daemon needs --output to be a file, which each collage replaces"),
+    }
+}
+
+// A hash of the path, size and modification time of every file in `dirs`,
+// which changes whenever one is added, removed or changed. The collage at
+// `output` and hidden files, like the temporary file it is written to, are
+// left out, so that saving the collage doesn't count as a change.
+fn fingerprint(dirs: &[&PathBuf], output: &Path) -> u64 {
+    let canonical = |path: &Path| fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let output = match (output.parent(), output.file_name()) {
+        (Some(parent), Some(name)) if !parent.as_os_str().is_empty() => {
+            canonical(parent).join(name)
+        }
+        _ => canonical(Path::new(".")).join(output),
+    };
+    let mut hasher = DefaultHasher::new();
+    for dir in dirs {
+        let entries = WalkDir::new(canonical(dir))
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|e| e.depth() == 0 || !e.file_name().to_string_lossy().starts_with('.'));
+        for entry in entries
+            .flatten()
+            .filter(|e| e.file_type().is_file() && e.path() != output)
+        {
+            entry.path().hash(&mut hasher);
+            if let Ok(metadata) = entry.metadata() {
+                metadata.len().hash(&mut hasher);
+                metadata.modified().ok().hash(&mut hasher);
+            }
+        }
+    }
+    hasher.finish()
+}
+
+// Create the collage (or collages in batch mode) described by `app`,
+// returning where they were saved.
+fn run(app: &App, cache: Option<&ImageCache>) -> Result<Vec<PathBuf>> {
+    let album = app.album.as_deref().map(library::open).transpose()?;
+    let with_album;
+    let app = match &album {
+        Some(album) => {
+            ensure!(
+                !(album.exported && app.manifest),
+                "--manifest can't be used with an album exported from Photos, since the \
+                 export is removed after the collage is made"
+            );
+            with_album = App {
+                image_dir: Some(album.dir.clone()),
+                ..app.clone()
+            };
+            &with_album
+        }
+        None => app,
+    };
+    app.validate()?;
+    let image_dir = match &app.compare {
+        Some(dirs) => &dirs[0],
+        None => app.image_dir.as_ref().context("No image directory given")?,
+    };
+
+    let seed = app.seed.unwrap_or_else(rand::random);
+    if app.seed.is_none() && app.is_stochastic() && !app.quiet {
+        eprintln!("Using seed {seed}.");
+    }
+
+    // In batch mode every immediate subdirectory becomes its own collage,
+    // named after the folder.
+    let jobs: Vec<(PathBuf, OsString)> = if app.batch {
+        let mut dirs: Vec<PathBuf> = WalkDir::new(image_dir)
+            .min_depth(1)
+            .max_depth(1)
+            .into_iter()
+            .flatten()
+            .filter(|d| d.file_type().is_dir())
+            .map(|d| d.path().to_path_buf())
+            .collect();
+        dirs.sort_by(|a, b| sort::natural_cmp(a, b));
+        dirs.into_iter()
+            .map(|d| {
+                let name = d.file_name().unwrap_or_default().to_os_string();
+                (d, name)
+            })
+            .collect()
+    } else {
+        vec![(image_dir.clone(), app.name.clone())]
+    };
+
+    ensure!(
+        !jobs.is_empty(),
+        "--batch needs subdirectories in {}, but there are none",
+        image_dir.display()
+    );
+
+    let mut saved = Vec::new();
+    for (dir, name) in jobs {
+        info!(
+            "Creating collage {} from {}.",
+            name.to_string_lossy(),
+            dir.display()
+        );
+        let result = collage(app, &dir, &name, seed, cache);
+        // A dry run makes no collage, so there is nothing to notify of, and a
+        // run without one was cancelled.
+        #[cfg(feature = "notify")]
+        if let Some(url) = app.notify_url.as_ref().filter(|_| !app.dry_run) {
+            notify::notify(url, &result);
+        }
+        if app.notify {
+            match &result {
+                Ok(Some(result)) => {
+                    desktop::notify("Collage done", &result.output.display().to_string())
+                }
+                Ok(None) => {}
+                Err(e) => desktop::notify("Collage failed", &format!("{e:#}")),
+            }
+        }
+        let Some(result) = result? else {
+            continue;
+        };
+        if app.log_format == LogFormat::Json {
+            println!("{}", serde_json::to_string(&result)?);
+        } else if app.quiet {
+            println!("{}", result.output.display());
+        } else {
+            result.print_summary();
+        }
+        #[cfg(feature = "upload")]
+        if let Some(target) = &app.upload {
+            let url = upload::upload(&result.output, target)?;
+            if app.log_format == LogFormat::Text && !app.quiet {
+                println!("Uploaded the collage to {url}.");
+            }
+        }
+        saved.push(result.output);
+    }
+    Ok(saved)
+}
+
+// Files that are removed when dropped.
+#[derive(Debug, Default)]
+struct TempFiles(Vec<PathBuf>);
+
+impl Drop for TempFiles {
+    fn drop(&mut self) {
+        for path in &self.0 {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+// The inputs of a nested collage: the files directly in `image_dir` and, in
+// the place of each subdirectory, a collage of it made with the same settings
+// (and nested in turn), saved to a temporary file added to `rendered`.
+fn nested_paths(
+    app: &App,
+    image_dir: &Path,
+    seed: u64,
+    cache: Option<&ImageCache>,
+    rendered: &mut TempFiles,
+) -> Result<Vec<PathBuf>> {
+    let mut entries: Vec<PathBuf> = WalkDir::new(image_dir)
+        .min_depth(1)
+        .max_depth(1)
+        .into_iter()
+        .flatten()
+        .map(|d| d.path().to_path_buf())
+        .collect();
+    entries.sort_by(|a, b| sort::natural_cmp(a, b));
+
+    let mut paths = Vec::with_capacity(entries.len());
+    for entry in entries {
+        if entry.is_file() {
+            paths.push(entry);
+            continue;
+        }
+        if !entry.is_dir() {
+            continue;
+        }
+        // Every nested collage of the process gets its own name, so that the
+        // image cache can't mix them up.
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let count = COUNT.fetch_add(1, Ordering::Relaxed);
+        let name = format!("collage_nested_{}_{count}", std::process::id());
+        let file = output::reserve_unique_path(&std::env::temp_dir(), OsStr::new(&name), "png")?;
+        rendered.0.push(file.clone());
+        let inner = App {
+            output: Some(file),
+            name_by_hash: false,
+            ..app.clone()
+        };
+        info!("Creating the nested collage of {}.", entry.display());
+        let name = entry.file_name().unwrap_or_default().to_os_string();
+        match collage(&inner, &entry, &name, seed, cache) {
+            Ok(Some(result)) => paths.push(result.output),
+            Ok(None) => bail!("Cancelled the nested collage of {}", entry.display()),
+            // A subdirectory without images is left out, like a file that
+            // can't be decoded.
+            Err(e) if !app.strict => warn!("Leaving out {}: {e:#}", entry.display()),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(paths)
+}
+
+// Create a single collage from the images in `image_dir` and save it to the
+// downloads dir as `<name>_<num>.png`. Returns `None` if the user cancelled.
+fn collage(
+    app: &App,
+    image_dir: &Path,
+    name: &OsStr,
+    seed: u64,
+    cache: Option<&ImageCache>,
+) -> Result<Option<RunResult>> {
+    let start = Instant::now();
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+
+    if let (Some(w), Some(h)) = (app.image_width, app.image_height) {
+        if w as u64 * h as u64 > 100_000_000 {
+            warn!("The product of the width and height is greater than 100,000Mb.");
+        }
+    }
+
+    info!("Opening images.");
+    // We need to read the images before we can create the model.
+    let mut paths = list_files(image_dir);
+
+    // Nested, every subdirectory is a collage of its own in the place of the
+    // subdirectory, removed again when this collage is done.
+    let mut nested = TempFiles::default();
+    if app.nested {
+        paths = nested_paths(app, image_dir, seed, cache, &mut nested)?;
+    }
+
+    // A mood board picks its own images, so its bursts are left out of
+    // those below.
+    if let (Some(seconds), None) = (app.dedup_bursts, &app.mood_board) {
+        let before = paths.len();
+        paths = bursts::dedup(paths, seconds);
+        info!("Left out {} photos of bursts.", before - paths.len());
+    }
+
+    // Comparing uses the pairs of files with the same name, before then
+    // after.
+    if let Some(dirs) = &app.compare {
+        paths = compare::pairs(&dirs[0], &dirs[1])?;
+    }
+
+    // A year in review or a mood board only uses the images it picks, each
+    // in its month or section, so nothing else needs to be decoded.
+    let mut groups = Groups {
+        of: HashMap::new(),
+        mood_board: app.mood_board.as_deref().map(MoodBoard::load).transpose()?,
+    };
+    let selected = match &groups.mood_board {
+        Some(board) => {
+            let mut selected = board.select(image_dir);
+            if let Some(seconds) = app.dedup_bursts {
+                let before = selected.len();
+                let kept: HashSet<PathBuf> = bursts::dedup(
+                    selected.iter().map(|(path, _)| path.clone()).collect(),
+                    seconds,
+                )
+                .into_iter()
+                .collect();
+                selected.retain(|(path, _)| kept.contains(path));
+                info!("Left out {} photos of bursts.", before - selected.len());
+            }
+            Some(selected)
+        }
+        None if app.year_in_review => Some(review::select(&paths, app.per_month as usize)?),
+        None => None,
+    };
+    if let Some(selected) = selected {
+        paths = selected.iter().map(|(path, _)| path.clone()).collect();
+        groups.of.extend(selected);
+    }
+
+    // Auto spacing depends on how many images there are.
+    let app = &app.resolve_spacing(&paths);
+
+    // A collage named after the hash of its inputs only needs to be made
+    // once.
+    let hashed_name = if app.name_by_hash {
+        let mut hashed = name.to_os_string();
+        hashed.push(format!("_{}", app.output_hash(&paths, seed)?));
+        let path = output_dir(app.output.as_deref())?
+            .join(&hashed)
+            .with_extension("png");
+        if path.exists() {
+            info!("{} already exists, nothing changed.", path.display());
+            let (width, height) = image::image_dimensions(&path).unwrap_or_default();
+            return Ok(Some(RunResult {
+                file_size: fs::metadata(&path).map_or(0, |m| m.len()),
+                output: path,
+                width,
+                height,
+                images: paths.len(),
+                skipped: Vec::new(),
+                seed,
+                duration_ms: start.elapsed().as_millis(),
+                stages: Stages::default(),
+                up_to_date: true,
+            }));
+        }
+        Some(path)
+    } else {
+        None
+    };
+
+    let captions = app
+        .captions_from
+        .as_deref()
+        .map(Captions::load)
+        .transpose()?;
+
+    info!("Estimating the size of the collage.");
+    // Only the image headers are read here, so we can ask before decoding.
+    let estimate = estimate(
+        app,
+        image_dir,
+        name,
+        &paths,
+        &rng,
+        &groups,
+        captions.as_ref(),
+    );
+    if app.dry_run {
+        estimate.print();
+        return Ok(None);
+    }
+
+    // If the collage would take more memory than the threshold, then ask the
+    // user if they want to proceed.
+    if estimate.peak_bytes > app.confirm_over
+        && !confirm(
+            app,
+            &format!(
+                "The collage needs about {}MB of memory. Do you want to proceed? [Y/n]: ",
+                estimate.peak_bytes / 1_000_000
+            ),
+        )?
+    {
+        warn!("Operation cancelled.");
+        return Ok(None);
+    }
+
+    // Long runs are checkpointed, so that an interrupted run can be resumed.
+    let checkpoint = if app.resume || paths.len() >= CHECKPOINT_MIN_IMAGES {
+        Some(Checkpoint::new(&app.checkpoint_key(image_dir, name)?)?)
+    } else {
+        None
+    };
+    let resumed = checkpoint
+        .as_ref()
+        .filter(|_| app.resume)
+        .and_then(Checkpoint::load)
+        .filter(|state| state.files == paths);
+
+    // The decoded images in the order of the tiles. When resuming they are
+    // only decoded if their tile wasn't finished.
+    let mut decoded: Vec<Option<Arc<DynamicImage>>> = Vec::new();
+    let mut stages = Stages::default();
+    let state = match resumed {
+        Some(state) => {
+            info!(
+                "Resuming from the checkpoint in {}.",
+                checkpoint.as_ref().unwrap().dir().display()
+            );
+            decoded.resize(state.paths.len(), None);
+            state
+        }
+        None => {
+            info!("Decoding the images.");
+            let timer = Instant::now();
+            let (mut inputs, skipped) = decode_images(app, &paths, cache)?;
+            if app.compare.is_some() {
+                inputs = compare::complete_pairs(inputs, &paths);
+            }
+
+            // Without any images there is nothing to lay out.
+            let files = inputs.len() + skipped.len();
+            ensure!(
+                !inputs.is_empty(),
+                "No usable images in {}: {files} files seen, none could be decoded",
+                image_dir.display()
+            );
+
+            // Images with a count appear that many times, next to each other
+            // until they are shuffled.
+            if !app.image_count.is_empty() {
+                inputs = repeat_counted(app, inputs);
+                ensure!(!inputs.is_empty(), "--image-count leaves out every image");
+            }
+
+            stages.decode_ms = timer.elapsed().as_millis();
+
+            let timer = Instant::now();
+            let Plan {
+                inputs,
+                layout,
+                qr_tile,
+                histogram_tiles,
+            } = plan(
+                app,
+                inputs,
+                |(size, _)| *size,
+                &mut rng,
+                &groups,
+                captions.as_ref(),
+            )?;
+            for ((path, _), tile) in inputs.iter().zip(&layout.tiles) {
+                if tile.width < MIN_USEFUL_SIZE || tile.height < MIN_USEFUL_SIZE {
+                    warn!(
+                        "{} is resized to only {}x{} pixels.",
+                        path.display(),
+                        tile.width,
+                        tile.height
+                    );
+                }
+            }
+            stages.layout_ms = timer.elapsed().as_millis();
+
+            let (paths_used, images): (Vec<PathBuf>, Vec<_>) = inputs.into_iter().unzip();
+            let sizes = images.iter().map(|(size, _)| *size).collect();
+            decoded = images.into_iter().map(|(_, image)| image).collect();
+            State {
+                files: paths,
+                paths: paths_used,
+                sizes,
+                skipped,
+                layout,
+                qr_tile,
+                histogram_tiles,
+                seed,
+            }
+        }
+    };
+
+    let width = state.layout.width;
+    let (combined_histogram, footer, height) = bottom_bands(
+        app,
+        image_dir,
+        name,
+        state.paths.len(),
+        (width, state.layout.height),
+    );
+    let pixels = width as u64 * height as u64;
+    ensure!(
+        pixels <= app.max_output_pixels,
+        "The output image would be {width}x{height} ({pixels} pixels), more than the \
+         maximum of {} pixels. Use a smaller --width/--height, less spacing or fewer \
+         images, or raise --max-output-pixels",
+        app.max_output_pixels
+    );
+    if let Some(checkpoint) = &checkpoint {
+        if let Err(e) = checkpoint.save_state(&state) {
+            warn!("Failed to write the checkpoint: {e:#}");
+        }
+    }
+
+    // The number of images before they repeat.
+    let repeat = state.paths.iter().collect::<HashSet<_>>().len().max(1);
+
+    info!("Resizing images if necessary.");
+    let timer = Instant::now();
+    // Resize every image to the size of its tile, reusing the tiles of the
+    // checkpoint and adding to it as we go. The tiles are split into a run of
+    // consecutive tiles for each thread.
+    decoded.resize(state.layout.tiles.len(), None);
+    let chunk = state.layout.tiles.len().div_ceil(app.threads()).max(1);
+    let state_ref = &state;
+    let checkpoint_ref = checkpoint.as_ref();
+    let images: Vec<RgbaImage> = thread::scope(|s| {
+        let handles: Vec<_> = decoded
+            .chunks_mut(chunk)
+            .enumerate()
+            .map(|(c, decoded)| {
+                s.spawn(move || {
+                    decoded
+                        .iter_mut()
+                        .enumerate()
+                        .map(|(j, image)| {
+                            let i = c * chunk + j;
+                            prepare_tile(
+                                app,
+                                state_ref,
+                                i,
+                                image.take(),
+                                repeat,
+                                checkpoint_ref,
+                                cache,
+                            )
+                        })
+                        .collect::<Result<Vec<_>>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().expect("Resize thread panicked"))
+            .collect::<Result<Vec<_>>>()
+    })?
+    .into_iter()
+    .flatten()
+    .collect();
+    stages.resize_ms = timer.elapsed().as_millis();
+    let State {
+        paths,
+        sizes,
+        layout,
+        skipped,
+        qr_tile,
+        histogram_tiles,
+        seed,
+        ..
+    } = state;
+
+    // Create the model.
+    let model = Model { images, layout };
+
+    let n = model.images.len();
+
+    let timer = Instant::now();
+    info!(
+        "Creating the blank output image with color {}.",
+        app.background_color
+    );
+    let background = hex_to_color(&app.background_color)?;
+    let mut out_image = match (app.texture, app.background) {
+        (Some(t), _) => texture::render(t, width, height),
+        (_, Some(Background::Generative(style))) => {
+            let palette: Vec<_> = model.images.iter().map(color::dominant_color).collect();
+            generative::render(style, width, height, &palette, seed)
+        }
+        (None, None) => RgbaImage::from_pixel(width, height, background),
+    };
+
+    info!("Copying the {n} images to the output image.");
+    draw_images(
+        &mut out_image,
+        app,
+        &model.images,
+        &model.layout.tiles,
+        &paths,
+        &histogram_tiles,
+        seed,
+    )?;
+    if let Some(tile) = combined_histogram {
+        let mut histogram = Histogram::default();
+        model.images.iter().for_each(|image| histogram.add(image));
+        let band = histogram.render(tile.width, tile.height, background);
+        imageops::replace(&mut out_image, &band, tile.x as i64, tile.y as i64);
+    }
+    if let Some(url) = &app.qr {
+        add_qr(&mut out_image, url, app, qr_tile)?;
+    }
+    for label in model.layout.labels.iter().chain(&footer) {
+        let color = text::contrasting(background);
+        text::draw(
+            &mut out_image,
+            &label.text,
+            label.x,
+            label.y,
+            label.scale,
+            color,
+        );
+    }
+
+    if app.debug_overlay {
+        debug_overlay::draw(
+            &mut out_image,
+            &model.layout,
+            &paths,
+            app.left_margin,
+            app.top_margin,
+        );
+    }
+
+    // On an e-ink panel the tiles are scaled and moved with the collage.
+    let mut tiles = model.layout.tiles.clone();
+    let (width, height) = match app.eink {
+        Some(panel) => {
+            let (scale, left, top) = eink::placement(out_image.dimensions(), panel);
+            let scaled = |v: u32| (v as f64 * scale).round() as u32;
+            for tile in &mut tiles {
+                *tile = Tile {
+                    x: left + scaled(tile.x),
+                    y: top + scaled(tile.y),
+                    width: scaled(tile.width).max(1),
+                    height: scaled(tile.height).max(1),
+                };
+            }
+            out_image = eink::prepare(&out_image, panel, background);
+            panel
+        }
+        None => (width, height),
+    };
+
+    stages.composite_ms = timer.elapsed().as_millis();
+
+    info!("Saving the output image.");
+    let timer = Instant::now();
+    // Save the output image to the `--output` file, or as a png in the
+    // output dir.
+    let sketch = match (hashed_name, &app.output) {
+        (Some(path), _) => path,
+        (None, Some(file)) if !app.batch && !file.is_dir() => file.clone(),
+        _ => output::reserve_unique_path(&output_dir(app.output.as_deref())?, name, "png")?,
+    };
+    // GIFs are reduced to a palette here rather than by the encoder, to
+    // dither them.
+    let gif = ImageFormat::from_path(&sketch).ok() == Some(ImageFormat::Gif);
+    let saved = match app.colors {
+        _ if app.eink.is_some() => output::save_indexed(
+            &quantize::to_palette(&out_image, &eink::palette(app.eink_levels), app.dither),
+            &sketch,
+        ),
+        Some(colors) => output::save_indexed(
+            &quantize::quantize(&out_image, colors as usize, app.dither),
+            &sketch,
+        ),
+        None if gif => {
+            output::save_indexed(&quantize::quantize(&out_image, 256, app.dither), &sketch)
+        }
+        None => output::save_atomic(&out_image, &sketch),
+    };
+    if let Err(e) = saved {
+        // Don't leave the reserved name behind.
+        if app.output.as_ref() != Some(&sketch) {
+            let _ = std::fs::remove_file(&sketch);
+        }
+        return Err(e);
+    }
+
+    if let Some(format) = app.image_map {
+        let map = image_map::write(
+            &sketch,
+            (width, height),
+            &tiles,
+            &paths,
+            &app.link_template,
+            format,
+        )?;
+        info!("Saved the image map to {}.", map.display());
+    }
+    // A checkpoint from before the sizes were recorded has none.
+    if app.manifest && sizes.len() != paths.len() {
+        warn!("No manifest written: the checkpoint resumed from lacks the image sizes.");
+    } else if app.manifest {
+        let manifest = Manifest {
+            args: app
+                .args
+                .iter()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect(),
+            image_dir: image_dir
+                .canonicalize()
+                .unwrap_or_else(|_| image_dir.to_path_buf()),
+            seed,
+            images: paths
+                .iter()
+                .zip(&sizes)
+                .map(|(path, &(width, height))| manifest::Image {
+                    path: path.canonicalize().unwrap_or_else(|_| path.clone()),
+                    width,
+                    height,
+                })
+                .collect(),
+            layout: model.layout.clone(),
+        };
+        let path = manifest.save(&sketch)?;
+        info!("Saved the manifest to {}.", path.display());
+    }
+
+    #[cfg(feature = "c2pa")]
+    if let (Some(cert), Some(key)) = (&app.c2pa_cert, &app.c2pa_key) {
+        let signer = c2pa::Signer::load(cert, key)?;
+        // The credentials are published with the collage, so they name the
+        // inputs by file name only and keep only the settings of the recipe.
+        let inputs: Vec<_> = sidecar::inputs(&paths)?
+            .into_iter()
+            .map(|input| {
+                serde_json::json!({
+                    "file": input.path.file_name().map(|name| name.to_string_lossy()),
+                    "sha256": input.sha256,
+                })
+            })
+            .collect();
+        let settings = app.recipe_settings()?;
+        let recipe = serde_json::json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "settings": settings,
+            "seed": seed,
+            "inputs": inputs,
+        });
+        c2pa::embed(&sketch, &signer, &recipe)?;
+        info!("Embedded content credentials in {}.", sketch.display());
+    }
+
+    if app.sidecar {
+        let inputs = sidecar::inputs(&paths)?;
+        let config = app
+            .config
+            .clone()
+            .or_else(config::default_config_path)
+            .and_then(|path| Some((path.clone(), fs::read_to_string(&path).ok()?)));
+        let sidecar = Sidecar {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            args: app
+                .args
+                .iter()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect(),
+            config,
+            settings: app.settings()?,
+            seed,
+            inputs,
+            output_sha256: sidecar::sha256(&sketch)?,
+        };
+        let path = sidecar.save(&sketch)?;
+        info!("Saved the sidecar to {}.", path.display());
+    }
+
+    stages.save_ms = timer.elapsed().as_millis();
+    let file_size = std::fs::metadata(&sketch).map_or(0, |m| m.len());
+
+    if let Some(checkpoint) = &checkpoint {
+        if let Err(e) = checkpoint.remove() {
+            warn!("Failed to remove the checkpoint: {e:#}");
+        }
+    }
+
+    Ok(Some(RunResult {
+        output: sketch,
+        width,
+        height,
+        images: model.images.len(),
+        skipped,
+        seed,
+        file_size,
+        duration_ms: start.elapsed().as_millis(),
+        stages,
+        up_to_date: false,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_size_units() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("100B"), Ok(100));
+        assert_eq!(parse_size("500MB"), Ok(500_000_000));
+        assert_eq!(parse_size("1.5gb"), Ok(1_500_000_000));
+        assert_eq!(parse_size(" 2 K "), Ok(2_000));
+        assert_eq!(parse_size("1T"), Ok(1_000_000_000_000));
+    }
+
+    #[test]
+    fn parse_size_rejects_garbage() {
+        assert!(parse_size("").is_err());
+        assert!(parse_size("MB").is_err());
+        assert!(parse_size("10XB").is_err());
+        assert!(parse_size("-5MB").is_err());
+    }
+
+    #[test]
+    fn parse_duration_units() {
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("1.5h"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration(" 2 D "), Ok(Duration::from_secs(172_800)));
+    }
+
+    #[test]
+    fn parse_duration_rejects_garbage() {
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("30").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("5w").is_err());
+        assert!(parse_duration("0.5s").is_err());
+        assert!(parse_duration("-5m").is_err());
+    }
+
+    #[test]
+    fn parse_duration_rejects_out_of_range() {
+        assert!(parse_duration("1e30d").is_err());
+        assert!(parse_duration("99999999999999999999999d").is_err());
+        assert!(parse_duration("-1e30d").is_err());
+        assert!(parse_duration("-0.5s").is_err());
+        assert!(parse_duration("NaNs").is_err());
+    }
+
+    fn checkpoint_key(args: &[&str]) -> String {
+        let args = ["collage", "photos"].iter().chain(args).map(OsString::from);
+        let app = try_parse_args(args.collect()).unwrap();
+        app.checkpoint_key(Path::new("photos"), OsStr::new("collage"))
+            .unwrap()
+    }
+
+    #[test]
+    fn checkpoint_key_changes_with_the_tiles() {
+        let key = checkpoint_key(&[]);
+        assert_ne!(key, checkpoint_key(&["--max-image-dimension", "4000"]));
+        assert_ne!(key, checkpoint_key(&["--max-decode-size", "2GB"]));
+        assert_ne!(key, checkpoint_key(&["--spacing", "12"]));
+        assert_ne!(key, checkpoint_key(&["--filter", "lanczos3"]));
+    }
+
+    #[test]
+    fn checkpoint_key_ignores_how_the_run_goes() {
+        let key = checkpoint_key(&[]);
+        assert_eq!(key, checkpoint_key(&["--resume"]));
+        assert_eq!(key, checkpoint_key(&["-v", "--yes", "--threads", "2"]));
+        assert_eq!(key, checkpoint_key(&["--seed", "7"]));
+    }
+
+    #[test]
+    fn checkpoint_key_changes_with_the_captions() {
+        let dir = std::env::temp_dir().join(format!("collage-captions-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("captions.csv");
+        let captions = path.to_str().unwrap();
+        fs::write(&path, "a.jpg,Beach\n").unwrap();
+        let key = checkpoint_key(&["--captions-from", captions]);
+        fs::write(&path, "a.jpg,Party\n").unwrap();
+        assert_ne!(key, checkpoint_key(&["--captions-from", captions]));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "c2pa")]
+    fn recipe_settings(args: &[&str]) -> Vec<String> {
+        let args = ["collage", "photos"].iter().chain(args).map(OsString::from);
+        let app = try_parse_args(args.collect()).unwrap();
+        app.recipe_settings().unwrap().keys().cloned().collect()
+    }
+
+    #[cfg(feature = "c2pa")]
+    #[test]
+    fn recipe_settings_keep_how_the_collage_looks() {
+        let settings = recipe_settings(&[
+            "--width",
+            "300",
+            "--color",
+            "#000000",
+            "--chroma-key",
+            "#00ff00",
+            "--opacity",
+            "0.5",
+            "--background",
+            "generative:blobs",
+        ]);
+        for long in [
+            "width",
+            "color",
+            "chroma-key",
+            "opacity",
+            "background",
+            "spacing",
+            "z-order",
+            "filter",
+        ] {
+            assert!(settings.contains(&long.to_string()), "{long}");
+        }
+    }
+
+    #[cfg(feature = "c2pa")]
+    #[test]
+    fn recipe_settings_leave_out_files_text_and_the_run() {
+        let settings = recipe_settings(&[
+            "--footer",
+            "Private",
+            "--qr",
+            "https://example.com",
+            "--captions-from",
+            "captions.csv",
+            "--image-z",
+            "secret.jpg=1",
+            "--image-opacity",
+            "secret.jpg=0.5",
+            "--layout-engine",
+            "./layout",
+            "--cutout",
+            "rembg",
+            "--cutout-arg",
+            "i",
+            "--output",
+            "out",
+            "--seed",
+            "7",
+            "--yes",
+            "--verbose",
+        ]);
+        for long in [
+            "image_dir",
+            "footer",
+            "qr",
+            "captions-from",
+            "image-z",
+            "image-opacity",
+            "layout-engine",
+            "cutout",
+            "cutout-arg",
+            "output",
+            "seed",
+            "yes",
+            "verbose",
+            "dry-run",
+            "log-format",
+        ] {
+            assert!(!settings.contains(&long.to_string()), "{long}");
+        }
+    }
+
+    #[test]
+    fn parse_dimensions_of_a_canvas() {
+        assert_eq!(parse_dimensions("1920x1080"), Ok((1920, 1080)));
+        assert_eq!(parse_dimensions(" 800 X 600 "), Ok((800, 600)));
+        assert!(parse_dimensions("1920").is_err());
+        assert!(parse_dimensions("0x100").is_err());
+        assert!(parse_dimensions("100x").is_err());
+        assert!(parse_dimensions("-1x100").is_err());
+    }
+}
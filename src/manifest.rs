@@ -0,0 +1,100 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::layout::Layout;
+
+// An image of a collage and its size before it was resized to its tile.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub path: PathBuf,
+    pub width: u32,
+    pub height: u32,
+}
+
+// What `--manifest` writes next to a collage, so that `collage append` can
+// add new images to it without making it again.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Manifest {
+    // The command line the collage was made with.
+    pub args: Vec<String>,
+    pub image_dir: PathBuf,
+    pub seed: u64,
+    // The images in the order of the tiles.
+    pub images: Vec<Image>,
+    pub layout: Layout,
+}
+
+impl Manifest {
+    // The manifest of the collage at `collage`: `photo.png` has
+    // `photo.manifest.json`.
+    pub fn path(collage: &Path) -> PathBuf {
+        collage.with_extension("manifest.json")
+    }
+
+    pub fn load(collage: &Path) -> Result<Self> {
+        let path = Self::path(collage);
+        let json = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read the manifest {}", path.display()))?;
+        serde_json::from_str(&json).with_context(|| format!("Invalid manifest {}", path.display()))
+    }
+
+    // Write the manifest next to `collage`, returning its path.
+    pub fn save(&self, collage: &Path) -> Result<PathBuf> {
+        let path = Self::path(collage);
+        fs::write(&path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::Tile;
+
+    #[test]
+    fn round_trips_next_to_the_collage() {
+        let dir = std::env::temp_dir().join(format!("collage-manifest-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let collage = dir.join("photo.png");
+        assert_eq!(Manifest::path(&collage), dir.join("photo.manifest.json"));
+        assert!(Manifest::load(&collage).is_err());
+
+        let manifest = Manifest {
+            args: vec!["collage".into(), "photos".into()],
+            image_dir: "photos".into(),
+            seed: 42,
+            images: vec![Image {
+                path: "photos/a.jpg".into(),
+                width: 4000,
+                height: 3000,
+            }],
+            layout: Layout {
+                width: 400,
+                height: 300,
+                tiles: vec![Tile {
+                    x: 0,
+                    y: 0,
+                    width: 400,
+                    height: 300,
+                }],
+                labels: Vec::new(),
+            },
+        };
+        assert_eq!(manifest.save(&collage).unwrap(), Manifest::path(&collage));
+        let loaded = Manifest::load(&collage).unwrap();
+        assert_eq!(loaded.args, manifest.args);
+        assert_eq!(loaded.image_dir, manifest.image_dir);
+        assert_eq!(loaded.seed, 42);
+        assert_eq!(loaded.images, manifest.images);
+        assert_eq!(loaded.layout, manifest.layout);
+
+        fs::write(Manifest::path(&collage), "{}").unwrap();
+        assert!(Manifest::load(&collage).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,269 @@
+use exif::{Context, Exif, In, Reader, Tag, Value};
+use std::{
+    fs::{self, File},
+    io::{BufReader, Read},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// When a photo was taken, in the camera's local time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl Date {
+    // The number of seconds since 1970-01-01 00:00:00, treating the date as
+    // UTC, to tell how far apart two dates are.
+    pub fn timestamp(&self) -> i64 {
+        // Days from civil, from Howard Hinnant's date algorithms.
+        let (month, day) = (self.month as i64, self.day as i64);
+        let year = self.year as i64 - i64::from(month <= 2);
+        let era = year.div_euclid(400);
+        let yoe = year.rem_euclid(400);
+        let doy = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146_097 + doe - 719_468;
+        days * 86_400 + self.hour as i64 * 3600 + self.minute as i64 * 60 + self.second as i64
+    }
+}
+
+// The EXIF metadata of an image, if it has any.
+fn read_exif(path: &Path) -> Option<Exif> {
+    let file = File::open(path).ok()?;
+    Reader::new()
+        .read_from_container(&mut BufReader::new(file))
+        .ok()
+}
+
+// The date an image was taken from its EXIF metadata, preferring the original
+// capture time over the time the file was last changed.
+pub fn date_taken(path: &Path) -> Option<Date> {
+    let exif = read_exif(path)?;
+    [Tag::DateTimeOriginal, Tag::DateTimeDigitized, Tag::DateTime]
+        .iter()
+        .find_map(|&tag| match &exif.get_field(tag, In::PRIMARY)?.value {
+            Value::Ascii(values) => {
+                let date = exif::DateTime::from_ascii(values.first()?).ok()?;
+                (1..=12).contains(&date.month).then_some(Date {
+                    year: date.year,
+                    month: date.month,
+                    day: date.day,
+                    hour: date.hour,
+                    minute: date.minute,
+                    second: date.second,
+                })
+            }
+            _ => None,
+        })
+}
+
+// The make and model of the camera a photo was taken with, from its EXIF
+// metadata.
+pub fn camera(path: &Path) -> Option<String> {
+    let exif = read_exif(path)?;
+    let text = |tag| match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(values) => Some(String::from_utf8_lossy(values.first()?).trim().to_string()),
+        _ => None,
+    };
+    match (text(Tag::Make), text(Tag::Model)) {
+        (None, None) => None,
+        (make, model) => Some(format!(
+            "{} {}",
+            make.unwrap_or_default(),
+            model.unwrap_or_default()
+        )),
+    }
+}
+
+// The camera settings of a photo from its EXIF metadata, e.g.
+// `f/2.8  1/250s  ISO 100  50mm`, leaving out the ones it doesn't have.
+pub fn shooting_info(path: &Path) -> Option<String> {
+    let exif = read_exif(path)?;
+    let number = |tag| match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Rational(values) => values.first().map(|v| v.to_f64()),
+        Value::Short(values) => values.first().map(|&v| v as f64),
+        Value::Long(values) => values.first().map(|&v| v as f64),
+        _ => None,
+    };
+    let mut parts = Vec::new();
+    if let Some(f) = number(Tag::FNumber) {
+        parts.push(format!("f/{}", (f * 10.0).round() / 10.0));
+    }
+    if let Some(t) = number(Tag::ExposureTime).filter(|&t| t > 0.0) {
+        parts.push(if t < 1.0 {
+            format!("1/{}s", (1.0 / t).round())
+        } else {
+            format!("{}s", (t * 10.0).round() / 10.0)
+        });
+    }
+    if let Some(iso) = number(Tag::PhotographicSensitivity) {
+        parts.push(format!("ISO {iso}"));
+    }
+    if let Some(mm) = number(Tag::FocalLength) {
+        parts.push(format!("{}mm", mm.round()));
+    }
+    (!parts.is_empty()).then(|| parts.join("  "))
+}
+
+// The focal length of a photo in 35mm film terms from its EXIF metadata, which
+// unlike the actual focal length says how wide its lens is.
+pub fn focal_length_35mm(path: &Path) -> Option<f64> {
+    let exif = read_exif(path)?;
+    match &exif
+        .get_field(Tag::FocalLengthIn35mmFilm, In::PRIMARY)?
+        .value
+    {
+        Value::Short(values) => values.first().map(|&v| v as f64).filter(|&v| v > 0.0),
+        _ => None,
+    }
+}
+
+// The current date and time in UTC.
+pub fn utc_now() -> Date {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (days, rest) = (secs / 86_400, secs % 86_400);
+    // Civil from days, from Howard Hinnant's date algorithms.
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    Date {
+        year: year as u16,
+        month: month as u8,
+        day: day as u8,
+        hour: (rest / 3600) as u8,
+        minute: (rest % 3600 / 60) as u8,
+        second: (rest % 60) as u8,
+    }
+}
+
+// The star rating of an image from 0 to 5, from the `Rating` tag of its EXIF
+// metadata, or else the `xmp:Rating` of its XMP metadata, embedded or in a
+// sidecar file (`photo.xmp` or `photo.jpg.xmp`). Rejected images (-1) count
+// as 0.
+pub fn rating(path: &Path) -> Option<u8> {
+    // Windows' rating tag, which the `exif` crate doesn't name.
+    const RATING: Tag = Tag(Context::Tiff, 0x4746);
+    let from_exif =
+        read_exif(path).and_then(|exif| exif.get_field(RATING, In::PRIMARY)?.value.get_uint(0));
+    if let Some(rating) = from_exif {
+        return Some(rating.min(5) as u8);
+    }
+
+    // XMP packets are near the start of a file, so only that is searched.
+    let mut head = Vec::new();
+    File::open(path)
+        .ok()?
+        .take(256 * 1024)
+        .read_to_end(&mut head)
+        .ok()?;
+    let mut sidecar = path.as_os_str().to_os_string();
+    sidecar.push(".xmp");
+    [
+        Some(head),
+        fs::read(path.with_extension("xmp")).ok(),
+        fs::read(sidecar).ok(),
+    ]
+    .into_iter()
+    .flatten()
+    .find_map(|data| xmp_rating(&String::from_utf8_lossy(&data)))
+}
+
+// The rating of an XMP packet, written as either `xmp:Rating="3"` or
+// `<xmp:Rating>3</xmp:Rating>`.
+fn xmp_rating(xmp: &str) -> Option<u8> {
+    let start = xmp.find("xmp:Rating")? + "xmp:Rating".len();
+    let value: String = xmp[start..]
+        .trim_start_matches(['=', '"', '\'', '>', ' '])
+        .chars()
+        .take_while(|c| c.is_ascii_digit() || *c == '-')
+        .collect();
+    let rating: i32 = value.parse().ok()?;
+    Some(rating.clamp(0, 5) as u8)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use exif::{experimental::Writer, Field};
+    use std::io::Cursor;
+
+    // A TIFF holding nothing but the given text EXIF fields, which is enough
+    // for the EXIF reader.
+    pub fn exif_tiff(fields: &[(Tag, &str)]) -> Vec<u8> {
+        let fields: Vec<Field> = fields
+            .iter()
+            .map(|&(tag, text)| Field {
+                tag,
+                ifd_num: In::PRIMARY,
+                value: Value::Ascii(vec![text.as_bytes().to_vec()]),
+            })
+            .collect();
+        let mut writer = Writer::new();
+        for field in &fields {
+            writer.push_field(field);
+        }
+        let mut tiff = Cursor::new(Vec::new());
+        writer.write(&mut tiff, false).unwrap();
+        tiff.into_inner()
+    }
+
+    fn date(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Date {
+        Date {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        }
+    }
+
+    #[test]
+    fn timestamps_count_seconds_since_the_epoch() {
+        assert_eq!(date(1970, 1, 1, 0, 0, 0).timestamp(), 0);
+        assert_eq!(date(2000, 3, 1, 0, 0, 0).timestamp(), 951_868_800);
+        assert_eq!(date(2024, 2, 29, 23, 59, 59).timestamp(), 1_709_251_199);
+        assert_eq!(date(1969, 12, 31, 23, 59, 59).timestamp(), -1);
+    }
+
+    #[test]
+    fn reads_xmp_ratings() {
+        assert_eq!(xmp_rating(r#"<rdf:Description xmp:Rating="3"/>"#), Some(3));
+        assert_eq!(xmp_rating("<xmp:Rating>4</xmp:Rating>"), Some(4));
+        assert_eq!(xmp_rating("xmp:Rating='9'"), Some(5));
+        assert_eq!(xmp_rating("xmp:Rating=\"-1\""), Some(0));
+        assert_eq!(xmp_rating("xmp:Rating=\"\""), None);
+        assert_eq!(xmp_rating("<x:xmpmeta/>"), None);
+    }
+
+    #[test]
+    fn ratings_come_from_the_file_or_a_sidecar() {
+        let dir = std::env::temp_dir().join(format!("collage-rating-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let embedded = dir.join("embedded.jpg");
+        fs::write(&embedded, "<x:xmpmeta xmp:Rating=\"2\"/>").unwrap();
+        assert_eq!(rating(&embedded), Some(2));
+        let photo = dir.join("photo.jpg");
+        fs::write(&photo, b"no metadata").unwrap();
+        assert_eq!(rating(&photo), None);
+        fs::write(dir.join("photo.jpg.xmp"), "<xmp:Rating>5</xmp:Rating>").unwrap();
+        assert_eq!(rating(&photo), Some(5));
+        fs::write(dir.join("photo.xmp"), "<xmp:Rating>1</xmp:Rating>").unwrap();
+        assert_eq!(rating(&photo), Some(1));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,165 @@
+use anyhow::{ensure, Context, Result};
+use log::warn;
+use serde::Deserialize;
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+use walkdir::WalkDir;
+
+use crate::{layout::Row, sort};
+
+// A mood board spec maps groups of images to named sections, e.g.
+//
+// ```toml
+// columns = 3
+//
+// [[section]]
+// name = "Typography"
+// images = ["type", "logo.png"]
+// ```
+//
+// The images of a section are files or directories relative to the image
+// directory; the files of a directory are used in their natural order.
+#[derive(Deserialize, Debug, Clone)]
+pub struct MoodBoard {
+    #[serde(default = "default_columns")]
+    pub columns: usize,
+    #[serde(default, rename = "section")]
+    pub sections: Vec<Section>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Section {
+    pub name: String,
+    #[serde(default)]
+    pub images: Vec<PathBuf>,
+}
+
+fn default_columns() -> usize {
+    4
+}
+
+impl MoodBoard {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read mood board {}", path.display()))?;
+        let board: MoodBoard = toml::from_str(&text)
+            .with_context(|| format!("Failed to parse mood board {}", path.display()))?;
+        ensure!(
+            board.columns > 0,
+            "The mood board {} needs at least one column",
+            path.display()
+        );
+        Ok(board)
+    }
+
+    // The files of every section in order, each with the index of its
+    // section. A file listed more than once is only used the first time.
+    pub fn select(&self, image_dir: &Path) -> Vec<(PathBuf, usize)> {
+        let mut seen = HashSet::new();
+        let mut selected = Vec::new();
+        for (i, section) in self.sections.iter().enumerate() {
+            for entry in &section.images {
+                let path = image_dir.join(entry);
+                let mut files: Vec<PathBuf> = WalkDir::new(&path)
+                    .into_iter()
+                    .flatten()
+                    .filter(|d| d.file_type().is_file())
+                    .map(|d| d.path().to_path_buf())
+                    .collect();
+                if files.is_empty() {
+                    warn!(
+                        "No images found at {} for {}.",
+                        path.display(),
+                        section.name
+                    );
+                }
+                files.sort_by(|a, b| sort::natural_cmp(a, b));
+                for file in files {
+                    if seen.insert(file.clone()) {
+                        selected.push((file, i));
+                    }
+                }
+            }
+        }
+        selected
+    }
+
+    // A row per section, holding the images of the given sections in order.
+    pub fn rows(&self, sections: impl Iterator<Item = usize>) -> Vec<Row> {
+        let mut rows: Vec<Row> = self
+            .sections
+            .iter()
+            .map(|section| Row {
+                label: section.name.clone(),
+                count: 0,
+            })
+            .collect();
+        for section in sections {
+            rows[section].count += 1;
+        }
+        rows
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("collage-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn loads_a_spec() {
+        let dir = temp_dir("moodboard-load");
+        let spec = dir.join("board.toml");
+        fs::write(&spec, "[[section]]\nname = \"Color\"\n").unwrap();
+        let board = MoodBoard::load(&spec).unwrap();
+        assert_eq!(board.columns, 4);
+        assert_eq!(board.sections[0].name, "Color");
+        assert!(board.sections[0].images.is_empty());
+        fs::write(&spec, "columns = 0\n").unwrap();
+        assert!(MoodBoard::load(&spec).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn selects_the_images_of_each_section_once() {
+        let dir = temp_dir("moodboard-select");
+        fs::create_dir_all(dir.join("type")).unwrap();
+        for name in ["type/b10.png", "type/b2.png", "logo.png"] {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+        let board: MoodBoard = toml::from_str(
+            "[[section]]\n\
+             name = \"Type\"\n\
+             images = [\"type\", \"missing\"]\n\
+             [[section]]\n\
+             name = \"Brand\"\n\
+             images = [\"logo.png\", \"type/b2.png\"]\n",
+        )
+        .unwrap();
+        let selected = board.select(&dir);
+        assert_eq!(
+            selected,
+            [
+                (dir.join("type/b2.png"), 0),
+                (dir.join("type/b10.png"), 0),
+                (dir.join("logo.png"), 1),
+            ]
+        );
+        let counts: Vec<(String, usize)> = board
+            .rows(selected.iter().map(|(_, section)| *section))
+            .into_iter()
+            .map(|row| (row.label, row.count))
+            .collect();
+        assert_eq!(counts, [("Type".to_string(), 2), ("Brand".to_string(), 1)]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
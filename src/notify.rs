@@ -0,0 +1,62 @@
+use anyhow::Result;
+use log::{info, warn};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+// POST the outcome of a run to `url` as JSON: the result with a `status` of
+// `ok`, or a `status` of `cancelled` or `error` (with the `error` message).
+// A failed notification is only a warning, since the collage itself is done.
+pub fn notify<T: Serialize>(url: &str, result: &Result<Option<T>>) {
+    let agent = ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(10))
+        .build();
+    let request = agent.post(url).set("Content-Type", "application/json");
+    match request.send_string(&payload(result).to_string()) {
+        Ok(_) => info!("Notified {url}."),
+        Err(e) => warn!("Failed to notify {url}: {e}"),
+    }
+}
+
+// The JSON the outcome of a run is POSTed as.
+fn payload<T: Serialize>(result: &Result<Option<T>>) -> Value {
+    match result {
+        Ok(Some(result)) => {
+            let mut payload = serde_json::to_value(result).unwrap_or_else(|_| json!({}));
+            if let Value::Object(fields) = &mut payload {
+                fields.insert("status".to_string(), json!("ok"));
+            }
+            payload
+        }
+        Ok(None) => json!({ "status": "cancelled" }),
+        Err(e) => json!({ "status": "error", "error": format!("{e:#}") }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Context};
+
+    #[test]
+    fn payloads_carry_the_status() {
+        #[derive(Serialize)]
+        struct Outcome {
+            output: String,
+        }
+        let ok: Result<Option<Outcome>> = Ok(Some(Outcome {
+            output: "collage_0.png".into(),
+        }));
+        assert_eq!(
+            payload(&ok),
+            json!({ "status": "ok", "output": "collage_0.png" })
+        );
+        let cancelled: Result<Option<Outcome>> = Ok(None);
+        assert_eq!(payload(&cancelled), json!({ "status": "cancelled" }));
+        let failed: Result<Option<Outcome>> = Err(anyhow!("disk full")).context("Failed to save");
+        assert_eq!(
+            payload(&failed),
+            json!({ "status": "error", "error": "Failed to save: disk full" })
+        );
+    }
+}
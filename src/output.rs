@@ -0,0 +1,239 @@
+use anyhow::{Context, Result};
+use image::{ImageFormat, RgbaImage};
+use std::{
+    ffi::{OsStr, OsString},
+    fs::{self, File, OpenOptions},
+    io::{BufWriter, ErrorKind},
+    path::{Path, PathBuf},
+    process,
+};
+
+use crate::quantize::Indexed;
+
+// Reserve the first free `<name>_<num>.<extension>` in `dir` by creating it
+// empty. Creating with `create_new` is atomic, so concurrent runs can never
+// pick the same name.
+pub fn reserve_unique_path(dir: &Path, name: &OsStr, extension: &str) -> Result<PathBuf> {
+    for num in 0.. {
+        let mut file_name = name.to_os_string();
+        file_name.push(format!("_{num}.{extension}"));
+        let path = dir.join(file_name);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(_) => return Ok(path),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to create {}", path.display()))
+            }
+        }
+    }
+    unreachable!()
+}
+
+// Save an image by writing it to a temporary file next to `path` and then
+// renaming it, so `path` never holds a partially written image.
+pub fn save_atomic(image: &RgbaImage, path: &Path) -> Result<()> {
+    let format = ImageFormat::from_path(path)
+        .with_context(|| format!("Unknown image format for {}", path.display()))?;
+    write_atomic(path, |tmp| {
+        image
+            .save_with_format(tmp, format)
+            .map_err(anyhow::Error::from)
+    })
+}
+
+// Save an image reduced to a palette as a GIF or a palette PNG, atomically
+// like `save_atomic`. Other formats can't hold a palette, so they get the
+// colors of the palette.
+pub fn save_indexed(image: &Indexed, path: &Path) -> Result<()> {
+    let format = ImageFormat::from_path(path)
+        .with_context(|| format!("Unknown image format for {}", path.display()))?;
+    write_atomic(path, |tmp| match format {
+        ImageFormat::Gif => write_gif(image, tmp),
+        ImageFormat::Png => write_png(image, tmp),
+        _ => Ok(image.to_rgba().save_with_format(tmp, format)?),
+    })
+}
+
+fn write_gif(image: &Indexed, path: &Path) -> Result<()> {
+    let palette: Vec<u8> = image
+        .palette
+        .iter()
+        .flat_map(|c| [c[0], c[1], c[2]])
+        .collect();
+    let (width, height) = (
+        u16::try_from(image.width).context("The collage is too wide for a GIF")?,
+        u16::try_from(image.height).context("The collage is too tall for a GIF")?,
+    );
+    let mut encoder = gif::Encoder::new(File::create(path)?, width, height, &palette)?;
+    let frame = gif::Frame::from_indexed_pixels(width, height, &image.indices, None);
+    encoder.write_frame(&frame)?;
+    Ok(())
+}
+
+// Write a palette PNG with as few bits per pixel as the palette allows.
+fn write_png(image: &Indexed, path: &Path) -> Result<()> {
+    let (depth, bits) = match image.palette.len() {
+        0..=2 => (png::BitDepth::One, 1),
+        3..=4 => (png::BitDepth::Two, 2),
+        5..=16 => (png::BitDepth::Four, 4),
+        _ => (png::BitDepth::Eight, 8),
+    };
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        image.width,
+        image.height,
+    );
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(depth);
+    encoder.set_palette(
+        image
+            .palette
+            .iter()
+            .flat_map(|c| [c[0], c[1], c[2]])
+            .collect::<Vec<u8>>(),
+    );
+    if image.palette.iter().any(|c| c[3] < 255) {
+        encoder.set_trns(image.palette.iter().map(|c| c[3]).collect::<Vec<u8>>());
+    }
+    // Each row starts on a new byte, with the pixels packed from the high bits.
+    let per_byte = 8 / bits;
+    let data: Vec<u8> = image
+        .indices
+        .chunks(image.width as usize)
+        .flat_map(|row| {
+            row.chunks(per_byte).map(|pixels| {
+                pixels
+                    .iter()
+                    .enumerate()
+                    .fold(0u8, |byte, (i, &p)| byte | p << (8 - bits * (i + 1)))
+            })
+        })
+        .collect();
+    encoder.write_header()?.write_image_data(&data)?;
+    Ok(())
+}
+
+// Write `path` by calling `write` with a temporary file next to it, then
+// renaming the temporary file to `path`.
+pub fn write_atomic(path: &Path, write: impl FnOnce(&Path) -> Result<()>) -> Result<()> {
+    let file_name = path
+        .file_name()
+        .context("The output path has no file name")?;
+    let mut tmp_name = OsString::from(".");
+    tmp_name.push(file_name);
+    tmp_name.push(format!(".{}.tmp", process::id()));
+    let tmp = path.with_file_name(tmp_name);
+
+    let result = write(&tmp).and_then(|_| fs::rename(&tmp, path).map_err(anyhow::Error::from));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result.with_context(|| format!("Failed to save {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("collage-{name}-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn files(dir: &Path) -> Vec<String> {
+        let mut files: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn reserves_the_first_free_name() {
+        let dir = temp_dir("reserve");
+        let name = OsStr::new("collage");
+        for num in 0..3 {
+            let path = reserve_unique_path(&dir, name, "png").unwrap();
+            assert_eq!(path, dir.join(format!("collage_{num}.png")));
+        }
+        fs::remove_file(dir.join("collage_1.png")).unwrap();
+        let path = reserve_unique_path(&dir, name, "png").unwrap();
+        assert_eq!(path, dir.join("collage_1.png"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn saves_in_place_of_the_reserved_file() {
+        let dir = temp_dir("save");
+        let path = reserve_unique_path(&dir, OsStr::new("collage"), "png").unwrap();
+        let image = RgbaImage::from_pixel(3, 2, Rgba([1, 2, 3, 255]));
+        save_atomic(&image, &path).unwrap();
+        assert_eq!(image::open(&path).unwrap().into_rgba8(), image);
+        assert_eq!(files(&dir), ["collage_0.png"]);
+        assert!(save_atomic(&image, &dir.join("collage.unknown")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_failed_write_leaves_nothing_behind() {
+        let dir = temp_dir("atomic");
+        let path = dir.join("out.gif");
+        let result = write_atomic(&path, |tmp| {
+            fs::write(tmp, b"partial")?;
+            anyhow::bail!("interrupted")
+        });
+        assert!(result.is_err());
+        assert!(files(&dir).is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn saves_a_gif_with_the_palette() {
+        let dir = temp_dir("gif");
+        let palette = vec![Rgba([255, 0, 0, 255]), Rgba([0, 0, 255, 255])];
+        let indexed = Indexed {
+            width: 3,
+            height: 2,
+            palette,
+            indices: vec![0, 1, 0, 1, 1, 0],
+        };
+        let path = dir.join("out.gif");
+        save_indexed(&indexed, &path).unwrap();
+        assert_eq!(image::open(&path).unwrap().into_rgba8(), indexed.to_rgba());
+        assert_eq!(files(&dir), ["out.gif"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn saves_palette_pngs_at_every_bit_depth() {
+        let dir = temp_dir("png");
+        for (colors, depth) in [(2, 1), (4, 2), (16, 4), (200, 8)] {
+            let palette: Vec<Rgba<u8>> = (0..colors)
+                .map(|i| Rgba([i as u8, 255 - i as u8, 7, if i == 1 { 128 } else { 255 }]))
+                .collect();
+            // A width that doesn't fill the last byte of each row.
+            let (width, height) = (5, 3);
+            let indexed = Indexed {
+                width,
+                height,
+                palette,
+                indices: (0..width * height)
+                    .map(|i| (i * 7 % colors) as u8)
+                    .collect(),
+            };
+            let path = dir.join(format!("out-{colors}.png"));
+            save_indexed(&indexed, &path).unwrap();
+            let reader = png::Decoder::new(File::open(&path).unwrap())
+                .read_info()
+                .unwrap();
+            assert_eq!(reader.info().color_type, png::ColorType::Indexed);
+            assert_eq!(reader.info().bit_depth as u32, depth);
+            assert_eq!(image::open(&path).unwrap().into_rgba8(), indexed.to_rgba());
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
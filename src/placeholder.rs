@@ -0,0 +1,93 @@
+use image::{Rgba, RgbaImage};
+
+use crate::text;
+
+const FILL: Rgba<u8> = Rgba([236, 208, 208, 255]);
+const INK: Rgba<u8> = Rgba([176, 32, 32, 255]);
+
+// A `width` x `height` tile standing in for an image that couldn't be
+// decoded: a pale red box crossed from corner to corner, with a `!` in the
+// middle and the name of the file along the bottom.
+pub fn render(width: u32, height: u32, name: &str) -> RgbaImage {
+    let mut tile = RgbaImage::from_pixel(width, height, FILL);
+    let line = (width.min(height) / 100).max(1);
+
+    // The border and the cross.
+    for (x, y, pixel) in tile.enumerate_pixels_mut() {
+        let border = x < line || y < line || x >= width - line || y >= height - line;
+        // The distance to each diagonal, scaled to pixels along the shorter side.
+        let (u, v) = (x as f32 / width as f32, y as f32 / height as f32);
+        let diagonal =
+            (u - v).abs().min((u + v - 1.0).abs()) * width.min(height) as f32 / 2.0_f32.sqrt();
+        if border || diagonal < line as f32 / 2.0 {
+            *pixel = INK;
+        }
+    }
+
+    // The error glyph, a quarter of the height of the tile, on a plain
+    // square so that it stands out from the cross.
+    let scale = (height / 4 / text::GLYPH_SIZE).max(1);
+    let glyph = text::GLYPH_SIZE * scale;
+    let (gx, gy) = (
+        width.saturating_sub(glyph) / 2,
+        height.saturating_sub(glyph) / 2,
+    );
+    let pad = scale * 2;
+    for y in gy.saturating_sub(pad)..(gy + glyph + pad).min(height) {
+        for x in gx.saturating_sub(pad)..(gx + glyph + pad).min(width) {
+            tile.put_pixel(x, y, FILL);
+        }
+    }
+    text::draw(&mut tile, "!", gx, gy, scale, INK);
+
+    // The name, cut short to fit.
+    let room = width.saturating_sub(4 * line);
+    let scale = (room / text::width(name, 1).max(1)).clamp(1, 3);
+    let name: String = name
+        .chars()
+        .take((room / (text::GLYPH_SIZE * scale)) as usize)
+        .collect();
+    let x = width.saturating_sub(text::width(&name, scale)) / 2;
+    let y = height.saturating_sub(text::GLYPH_SIZE * scale + 3 * line);
+    for py in y.saturating_sub(line)..(y + text::GLYPH_SIZE * scale + line).min(height) {
+        for px in x.saturating_sub(line)..(x + text::width(&name, scale) + line).min(width) {
+            tile.put_pixel(px, py, FILL);
+        }
+    }
+    text::draw(&mut tile, &name, x, y, scale, INK);
+    tile
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draws_a_crossed_box_with_the_name() {
+        let tile = render(200, 100, "broken.jpg");
+        assert_eq!(tile.dimensions(), (200, 100));
+        // The border, and the cross through the corners.
+        for (x, y) in [(0, 0), (199, 50), (100, 99), (50, 25), (150, 25)] {
+            assert_eq!(tile.get_pixel(x, y), &INK, "({x}, {y})");
+        }
+        assert_eq!(tile.get_pixel(100, 10), &FILL);
+        // The `!` on its own square in the middle, and the name along the
+        // bottom.
+        let ink = |xs: std::ops::Range<u32>, ys: std::ops::Range<u32>| {
+            ys.flat_map(|y| xs.clone().map(move |x| (x, y)))
+                .filter(|&(x, y)| tile.get_pixel(x, y) == &INK)
+                .count()
+        };
+        assert!(ink(88..112, 38..62) > 0);
+        assert_eq!(ink(82..88, 38..62), 0);
+        assert!(ink(10..190, 70..97) > 0);
+    }
+
+    #[test]
+    fn tiny_tiles_and_long_names_fit() {
+        for (width, height) in [(1, 1), (3, 2), (40, 300)] {
+            let tile = render(width, height, &"x".repeat(100));
+            assert_eq!(tile.dimensions(), (width, height));
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use image::{Rgba, RgbaImage};
+use qrcode::{Color, QrCode};
+use serde::Serialize;
+
+// The number of light modules around a QR code, so scanners can find it.
+const QUIET_ZONE: u32 = 4;
+
+// Where the QR code of `--qr` goes.
+#[derive(ValueEnum, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum QrPosition {
+    // As an extra tile after the images.
+    Tile,
+    // As a badge in a corner of the collage.
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+// Render `data` as a black on white QR code with its quiet zone, as large as
+// fits in a `size` x `size` square with whole pixels per module (but never
+// less than one pixel per module).
+pub fn render(data: &str, size: u32) -> Result<RgbaImage> {
+    let code = QrCode::new(data).context("Failed to encode the QR code")?;
+    let modules = code.width() as u32;
+    let scale = (size / (modules + 2 * QUIET_ZONE)).max(1);
+    let side = (modules + 2 * QUIET_ZONE) * scale;
+    let mut image = RgbaImage::from_pixel(side, side, Rgba([255, 255, 255, 255]));
+    for (i, color) in code.to_colors().into_iter().enumerate() {
+        if color == Color::Light {
+            continue;
+        }
+        let x = (i as u32 % modules + QUIET_ZONE) * scale;
+        let y = (i as u32 / modules + QUIET_ZONE) * scale;
+        for dy in 0..scale {
+            for dx in 0..scale {
+                image.put_pixel(x + dx, y + dy, Rgba([0, 0, 0, 255]));
+            }
+        }
+    }
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "https://example.com";
+
+    // The side of the code of `URL` with its quiet zone, in modules.
+    fn side() -> u32 {
+        QrCode::new(URL).unwrap().width() as u32 + 2 * QUIET_ZONE
+    }
+
+    #[test]
+    fn renders_whole_modules_with_a_quiet_zone() {
+        let scale = 100 / side();
+        assert!(scale > 1);
+        let image = render(URL, 100).unwrap();
+        assert_eq!(image.dimensions(), (side() * scale, side() * scale));
+        let white = Rgba([255, 255, 255, 255]);
+        let black = Rgba([0, 0, 0, 255]);
+        let zone = QUIET_ZONE * scale;
+        let end = image.width() - 1;
+        for i in 0..image.width() {
+            for (x, y) in [(i, 0), (i, zone - 1), (0, i), (end, i), (end - zone + 1, i)] {
+                assert_eq!(image.get_pixel(x, y), &white);
+            }
+        }
+        // The top left finder pattern starts with a dark module.
+        for d in 0..scale {
+            assert_eq!(image.get_pixel(zone + d, zone + d), &black);
+        }
+        assert!(image.pixels().all(|p| p == &white || p == &black));
+    }
+
+    #[test]
+    fn never_less_than_a_pixel_per_module() {
+        let image = render(URL, 10).unwrap();
+        assert_eq!(image.dimensions(), (side(), side()));
+    }
+}
@@ -0,0 +1,235 @@
+use clap::ValueEnum;
+use color_quant::NeuQuant;
+use image::{Rgba, RgbaImage};
+use serde::Serialize;
+
+// How the colors of an image are spread over a limited palette, so that
+// gradients don't break up into bands.
+#[derive(ValueEnum, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum Dither {
+    // Each pixel gets the nearest color of the palette.
+    None,
+    // A fixed 8x8 Bayer pattern nudges each pixel before picking its color,
+    // for an even, regular texture.
+    Ordered,
+    // The error of each pixel is passed on to its neighbors, for the most
+    // faithful result.
+    FloydSteinberg,
+}
+
+// An image of indices into a palette of at most 256 colors.
+#[derive(Debug, Clone)]
+pub struct Indexed {
+    pub width: u32,
+    pub height: u32,
+    pub palette: Vec<Rgba<u8>>,
+    pub indices: Vec<u8>,
+}
+
+// The 8x8 Bayer matrix, each threshold out of 64.
+const BAYER: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+// Reduce `image` to a palette of `colors` (2 to 256) chosen for it.
+pub fn quantize(image: &RgbaImage, colors: usize, dither: Dither) -> Indexed {
+    let quant = NeuQuant::new(10, colors.clamp(2, 256), image.as_raw());
+    let palette: Vec<Rgba<u8>> = quant
+        .color_map_rgba()
+        .chunks_exact(4)
+        .map(|c| Rgba([c[0], c[1], c[2], c[3]]))
+        .collect();
+    let spread = 256.0 / (colors.clamp(2, 256) as f32).cbrt();
+    let indices = map(image, &palette, dither, spread, |color| {
+        quant.index_of(&color.0) as u8
+    });
+    Indexed {
+        width: image.width(),
+        height: image.height(),
+        palette,
+        indices,
+    }
+}
+
+// Reduce `image` to the fixed `palette`, e.g. the grays of an e-ink panel.
+pub fn to_palette(image: &RgbaImage, palette: &[Rgba<u8>], dither: Dither) -> Indexed {
+    let spread = 256.0 / palette.len().max(2) as f32;
+    let indices = map(image, palette, dither, spread, |color| {
+        nearest(palette, color)
+    });
+    Indexed {
+        width: image.width(),
+        height: image.height(),
+        palette: palette.to_vec(),
+        indices,
+    }
+}
+
+// The index of the color of `palette` nearest to `color`.
+fn nearest(palette: &[Rgba<u8>], color: Rgba<u8>) -> u8 {
+    let distance = |p: &Rgba<u8>| {
+        (0..3)
+            .map(|c| (p[c] as i32 - color[c] as i32).pow(2))
+            .sum::<i32>()
+    };
+    (0..palette.len())
+        .min_by_key(|&i| distance(&palette[i]))
+        .unwrap_or(0) as u8
+}
+
+impl Indexed {
+    // The image with every index replaced by its color.
+    pub fn to_rgba(&self) -> RgbaImage {
+        let pixels = self
+            .indices
+            .iter()
+            .flat_map(|&i| self.palette[i as usize].0)
+            .collect();
+        RgbaImage::from_raw(self.width, self.height, pixels).unwrap()
+    }
+}
+
+// The index into `palette` of every pixel of `image`, as picked by
+// `index_of` after dithering. `spread` is the size of the steps between the
+// colors of the palette, which ordered dithering nudges each pixel by up to
+// half of.
+fn map(
+    image: &RgbaImage,
+    palette: &[Rgba<u8>],
+    dither: Dither,
+    spread: f32,
+    index_of: impl Fn(Rgba<u8>) -> u8,
+) -> Vec<u8> {
+    let width = image.width() as usize;
+    let mut indices = Vec::with_capacity(width * image.height() as usize);
+    match dither {
+        Dither::None => indices.extend(image.pixels().map(|&p| index_of(p))),
+        Dither::Ordered => {
+            for (x, y, pixel) in image.enumerate_pixels() {
+                let threshold = BAYER[y as usize % 8][x as usize % 8] as f32 / 64.0 - 0.5;
+                let mut color = *pixel;
+                for c in 0..3 {
+                    color[c] = (pixel[c] as f32 + threshold * spread).clamp(0.0, 255.0) as u8;
+                }
+                indices.push(index_of(color));
+            }
+        }
+        Dither::FloydSteinberg => {
+            // The error passed on to the pixels of this row and the next,
+            // with a pixel of room on either side.
+            let mut errors = vec![[0.0f32; 3]; width + 2];
+            let mut next = vec![[0.0f32; 3]; width + 2];
+            for row in image.rows() {
+                for (x, pixel) in row.enumerate() {
+                    // The error is clamped too, so that a color the palette
+                    // can't get near doesn't pile up error without end.
+                    let mut color = *pixel;
+                    for c in 0..3 {
+                        color[c] = (pixel[c] as f32 + errors[x + 1][c]).clamp(0.0, 255.0) as u8;
+                    }
+                    let index = index_of(color);
+                    indices.push(index);
+                    let got = palette[index as usize];
+                    for c in 0..3 {
+                        let error = color[c] as f32 - got[c] as f32;
+                        errors[x + 2][c] += error * 7.0 / 16.0;
+                        next[x][c] += error * 3.0 / 16.0;
+                        next[x + 1][c] += error * 5.0 / 16.0;
+                        next[x + 2][c] += error / 16.0;
+                    }
+                }
+                errors = std::mem::replace(&mut next, vec![[0.0; 3]; width + 2]);
+            }
+        }
+    }
+    indices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+    const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+    fn gray(level: u8) -> RgbaImage {
+        RgbaImage::from_pixel(16, 16, Rgba([level, level, level, 255]))
+    }
+
+    // The share of the pixels of a mid gray that are mapped to white by
+    // `dither` over a palette of black and white.
+    fn white_share(dither: Dither) -> f32 {
+        let indices = map(&gray(127), &[BLACK, WHITE], dither, 256.0, |color| {
+            (color[0] >= 128) as u8
+        });
+        indices.iter().filter(|&&i| i == 1).count() as f32 / indices.len() as f32
+    }
+
+    #[test]
+    fn map_without_dithering_keeps_each_pixel() {
+        assert_eq!(white_share(Dither::None), 0.0);
+    }
+
+    #[test]
+    fn map_with_dithering_mixes_the_palette() {
+        for dither in [Dither::Ordered, Dither::FloydSteinberg] {
+            let share = white_share(dither);
+            assert!((0.4..=0.6).contains(&share), "{dither:?}: {share}");
+        }
+    }
+
+    #[test]
+    fn quantize_keeps_the_colors_of_a_two_color_image() {
+        let red = Rgba([200, 30, 30, 255]);
+        let blue = Rgba([20, 40, 220, 255]);
+        let image = RgbaImage::from_fn(16, 16, |x, y| if (x + y) % 2 == 0 { red } else { blue });
+        let indexed = quantize(&image, 16, Dither::None);
+        for (got, want) in indexed.to_rgba().pixels().zip(image.pixels()) {
+            for c in 0..4 {
+                assert!(got[c].abs_diff(want[c]) <= 8, "{got:?} != {want:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn quantize_indexes_into_the_palette() {
+        let image = RgbaImage::from_fn(32, 8, |x, y| {
+            Rgba([(x * 8) as u8, (y * 32) as u8, 128, 255])
+        });
+        for dither in [Dither::None, Dither::Ordered, Dither::FloydSteinberg] {
+            let indexed = quantize(&image, 16, dither);
+            assert!(indexed.palette.len() <= 16);
+            assert_eq!((indexed.width, indexed.height), (32, 8));
+            assert_eq!(indexed.indices.len(), 32 * 8);
+            assert!(indexed
+                .indices
+                .iter()
+                .all(|&i| (i as usize) < indexed.palette.len()));
+        }
+    }
+
+    #[test]
+    fn nearest_picks_the_closest_color() {
+        let palette = [BLACK, Rgba([128, 128, 128, 255]), WHITE];
+        assert_eq!(nearest(&palette, Rgba([10, 20, 0, 255])), 0);
+        assert_eq!(nearest(&palette, Rgba([100, 150, 120, 255])), 1);
+        assert_eq!(nearest(&palette, Rgba([250, 200, 255, 255])), 2);
+    }
+
+    #[test]
+    fn to_palette_keeps_the_palette() {
+        let palette = [BLACK, WHITE];
+        let indexed = to_palette(&gray(200), &palette, Dither::None);
+        assert_eq!(indexed.palette, palette);
+        assert!(indexed.indices.iter().all(|&i| i == 1));
+        assert_eq!(indexed.to_rgba(), RgbaImage::from_pixel(16, 16, WHITE));
+    }
+}
@@ -0,0 +1,105 @@
+use image::{Rgba, RgbaImage};
+use std::f32::consts::PI;
+
+const STAR: Rgba<u8> = Rgba([255, 200, 0, 255]);
+const EMPTY: Rgba<u8> = Rgba([110, 110, 110, 255]);
+const BADGE: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+// The width and height of a badge of stars `size` pixels across.
+pub fn badge_size(size: u32) -> (u32, u32) {
+    let pad = (size / 4).max(1);
+    (5 * size + 6 * pad, size + 2 * pad)
+}
+
+// Draw a badge of five stars, `rating` of them filled, with its top left
+// corner at `x`, `y` and each star `size` pixels across.
+pub fn draw_badge(image: &mut RgbaImage, x: u32, y: u32, size: u32, rating: u8) {
+    let pad = (size / 4).max(1);
+    let (width, height) = badge_size(size);
+    for by in y..(y + height).min(image.height()) {
+        for bx in x..(x + width).min(image.width()) {
+            image.put_pixel(bx, by, BADGE);
+        }
+    }
+    for i in 0..5 {
+        let color = if i < rating as u32 { STAR } else { EMPTY };
+        draw_star(image, x + pad + i * (size + pad), y + pad, size, color);
+    }
+}
+
+// Draw a five pointed star filling a `size` x `size` square at `x`, `y`.
+fn draw_star(image: &mut RgbaImage, x: u32, y: u32, size: u32, color: Rgba<u8>) {
+    let r = size as f32 / 2.0;
+    let (cx, cy) = (x as f32 + r, y as f32 + r * 1.1);
+    let points: Vec<(f32, f32)> = (0..10)
+        .map(|i| {
+            let radius = if i % 2 == 0 { r } else { r * 0.4 };
+            let angle = -PI / 2.0 + i as f32 * PI / 5.0;
+            (cx + radius * angle.cos(), cy + radius * angle.sin())
+        })
+        .collect();
+    for py in y..(y + size).min(image.height()) {
+        for px in x..(x + size).min(image.width()) {
+            if inside(&points, px as f32 + 0.5, py as f32 + 0.5) {
+                image.put_pixel(px, py, color);
+            }
+        }
+    }
+}
+
+// Whether (x, y) is inside the polygon, by the even-odd rule.
+fn inside(polygon: &[(f32, f32)], x: f32, y: f32) -> bool {
+    let mut inside = false;
+    let mut j = polygon.len() - 1;
+    for (i, &(xi, yi)) in polygon.iter().enumerate() {
+        let (xj, yj) = polygon[j];
+        if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn badge_fits_five_stars_and_padding() {
+        assert_eq!(badge_size(16), (5 * 16 + 6 * 4, 16 + 2 * 4));
+        assert_eq!(badge_size(2), (5 * 2 + 6, 2 + 2));
+    }
+
+    #[test]
+    fn fills_the_rated_stars() {
+        let size = 20;
+        let (width, height) = badge_size(size);
+        let mut image = RgbaImage::new(width + 10, height + 10);
+        draw_badge(&mut image, 10, 10, size, 3);
+        let count = |color| image.pixels().filter(|&&p| p == color).count();
+        // Every star has the same shape.
+        assert!(count(STAR) > 0);
+        assert_eq!(count(STAR) * 2, count(EMPTY) * 3);
+        assert_eq!(
+            count(STAR) + count(EMPTY) + count(BADGE),
+            (width * height) as usize
+        );
+        assert_eq!(image.get_pixel(9, 9), &Rgba([0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn badges_are_clipped_to_the_image() {
+        let mut image = RgbaImage::new(10, 10);
+        draw_badge(&mut image, 5, 5, 20, 5);
+        assert_eq!(image.get_pixel(9, 9), &BADGE);
+    }
+
+    #[test]
+    fn inside_a_polygon() {
+        let square = [(0.0, 0.0), (2.0, 0.0), (2.0, 2.0), (0.0, 2.0)];
+        assert!(inside(&square, 1.0, 1.0));
+        assert!(!inside(&square, 3.0, 1.0));
+        assert!(!inside(&square, 1.0, -0.5));
+    }
+}
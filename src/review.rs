@@ -0,0 +1,122 @@
+use anyhow::{ensure, Result};
+use log::info;
+use std::path::PathBuf;
+
+use crate::{layout::Row, metadata};
+
+// The labels of the rows of a year in review.
+pub const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+// Pick the images of a year in review: the first `per_month` images taken in
+// each month of the most recent year any image was taken in, in the order
+// they were taken, each with its month (January is 0). Images without an
+// EXIF date are left out.
+pub fn select(paths: &[PathBuf], per_month: usize) -> Result<Vec<(PathBuf, usize)>> {
+    let mut dated: Vec<_> = paths
+        .iter()
+        .filter_map(|path| Some((metadata::date_taken(path)?, path)))
+        .collect();
+    if dated.len() < paths.len() {
+        info!(
+            "Leaving out {} files without an EXIF date.",
+            paths.len() - dated.len()
+        );
+    }
+    let year = dated.iter().map(|(date, _)| date.year).max();
+    ensure!(
+        year.is_some(),
+        "None of the images has an EXIF date, so they can't be sorted into months"
+    );
+    info!("Creating the year in review of {}.", year.unwrap());
+    dated.retain(|(date, _)| Some(date.year) == year);
+    dated.sort();
+
+    let mut counts = [0; 12];
+    let mut selected = Vec::new();
+    for (date, path) in dated {
+        let month = date.month as usize - 1;
+        if counts[month] < per_month {
+            counts[month] += 1;
+            selected.push((path.clone(), month));
+        }
+    }
+    Ok(selected)
+}
+
+// One row per month, holding the images of the given months in order.
+pub fn rows(months: impl Iterator<Item = usize>) -> Vec<Row> {
+    let mut rows: Vec<Row> = MONTHS
+        .iter()
+        .map(|month| Row {
+            label: month.to_string(),
+            count: 0,
+        })
+        .collect();
+    for month in months {
+        rows[month].count += 1;
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::tests::exif_tiff;
+    use exif::Tag;
+    use std::fs;
+
+    #[test]
+    fn selects_the_first_images_of_each_month_of_the_last_year() {
+        let dir = std::env::temp_dir().join(format!("collage-review-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let dates = [
+            ("march-2", "2023:03:20 12:00:00"),
+            ("old", "2022:12:25 09:00:00"),
+            ("january", "2023:01:05 08:00:00"),
+            ("march-1", "2023:03:02 18:30:00"),
+            ("march-3", "2023:03:31 23:59:59"),
+        ];
+        let mut paths: Vec<PathBuf> = dates
+            .iter()
+            .map(|(name, date)| {
+                let path = dir.join(format!("{name}.tif"));
+                fs::write(&path, exif_tiff(&[(Tag::DateTimeOriginal, date)])).unwrap();
+                path
+            })
+            .collect();
+        let undated = dir.join("undated.tif");
+        fs::write(&undated, b"not an image").unwrap();
+        paths.push(undated);
+
+        let selected = select(&paths, 2).unwrap();
+        let names: Vec<(&str, usize)> = selected
+            .iter()
+            .map(|(p, month)| (p.file_stem().unwrap().to_str().unwrap(), *month))
+            .collect();
+        assert_eq!(names, [("january", 0), ("march-1", 2), ("march-2", 2)]);
+        assert!(select(&paths[5..], 2).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rows_count_the_images_of_each_month() {
+        let rows = rows([0, 2, 2, 11].into_iter());
+        assert_eq!(rows.len(), 12);
+        assert_eq!(rows[2].label, "March");
+        let counts: Vec<usize> = rows.iter().map(|row| row.count).collect();
+        assert_eq!(counts, [1, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+    }
+}
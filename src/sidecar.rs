@@ -0,0 +1,134 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+// An input of the collage and the SHA-256 of its contents.
+#[derive(Serialize, Debug, Clone)]
+pub struct Input {
+    pub path: PathBuf,
+    pub sha256: String,
+}
+
+// What `--sidecar` writes next to a collage: everything needed to audit it
+// and to make it again bit for bit.
+#[derive(Serialize, Debug, Clone)]
+pub struct Sidecar {
+    pub version: String,
+    // The command line the collage was made with.
+    pub args: Vec<String>,
+    // The config file whose values were the defaults, and its contents.
+    pub config: Option<(PathBuf, String)>,
+    // Every setting, after the command line and config file were applied.
+    pub settings: serde_json::Map<String, serde_json::Value>,
+    pub seed: u64,
+    pub inputs: Vec<Input>,
+    pub output_sha256: String,
+}
+
+impl Sidecar {
+    // The sidecar of the collage at `collage`: `photo.png` has
+    // `photo.sidecar.json`.
+    pub fn path(collage: &Path) -> PathBuf {
+        collage.with_extension("sidecar.json")
+    }
+
+    // Write the sidecar next to `collage`, returning its path.
+    pub fn save(&self, collage: &Path) -> Result<PathBuf> {
+        let path = Self::path(collage);
+        fs::write(&path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(path)
+    }
+}
+
+// Every input of `paths` once, with its SHA-256.
+pub fn inputs(paths: &[PathBuf]) -> Result<Vec<Input>> {
+    let mut seen = HashSet::new();
+    paths
+        .iter()
+        .filter(|path| seen.insert(*path))
+        .map(|path| {
+            Ok(Input {
+                path: path.clone(),
+                sha256: sha256(path)?,
+            })
+        })
+        .collect()
+}
+
+// The SHA-256 of the contents of the file at `path`, in hex.
+pub fn sha256(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    io::copy(&mut file, &mut hasher)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_each_input_once() {
+        let dir = std::env::temp_dir().join(format!("collage-sidecar-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (a, b) = (dir.join("a.jpg"), dir.join("b.jpg"));
+        fs::write(&a, b"abc").unwrap();
+        fs::write(&b, b"").unwrap();
+        let inputs = inputs(&[a.clone(), b.clone(), a.clone()]).unwrap();
+        let hashes: Vec<(&PathBuf, &str)> = inputs
+            .iter()
+            .map(|input| (&input.path, input.sha256.as_str()))
+            .collect();
+        assert_eq!(
+            hashes,
+            [
+                (
+                    &a,
+                    "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+                ),
+                (
+                    &b,
+                    "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+                ),
+            ]
+        );
+        assert!(sha256(&dir.join("missing.jpg")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn saves_next_to_the_collage() {
+        let dir = std::env::temp_dir().join(format!("collage-sidecar-save-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let collage = dir.join("photo.png");
+        let sidecar = Sidecar {
+            version: "1.0.0".into(),
+            args: vec!["collage".into()],
+            config: None,
+            settings: serde_json::Map::new(),
+            seed: 7,
+            inputs: Vec::new(),
+            output_sha256: "00".into(),
+        };
+        let path = sidecar.save(&collage).unwrap();
+        assert_eq!(path, dir.join("photo.sidecar.json"));
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(json["seed"], 7);
+        assert_eq!(json["config"], serde_json::Value::Null);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,131 @@
+use std::{cmp::Ordering, path::Path};
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+// A piece of a file name: a run of digits or a run of anything else.
+#[derive(Debug, PartialEq, Eq)]
+enum Chunk {
+    Number(String),
+    Text(String),
+}
+
+impl Ord for Chunk {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            // Compare numbers by value without parsing (they can be longer
+            // than any integer type): fewer significant digits is smaller.
+            (Chunk::Number(a), Chunk::Number(b)) => {
+                let a = a.trim_start_matches('0');
+                let b = b.trim_start_matches('0');
+                a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+            }
+            (Chunk::Number(_), Chunk::Text(_)) => Ordering::Less,
+            (Chunk::Text(_), Chunk::Number(_)) => Ordering::Greater,
+            (Chunk::Text(a), Chunk::Text(b)) => a.cmp(b),
+        }
+    }
+}
+
+impl PartialOrd for Chunk {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Split a name into chunks, folding case and accents so that text sorts the
+// way people expect, e.g. `École` next to `ecole` rather than after `zebra`.
+fn chunks(name: &str) -> Vec<Chunk> {
+    let folded: String = name
+        .nfd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .collect();
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    let mut digits = false;
+    for c in folded.chars() {
+        if c.is_ascii_digit() != digits && !current.is_empty() {
+            let chunk = std::mem::take(&mut current);
+            chunks.push(if digits {
+                Chunk::Number(chunk)
+            } else {
+                Chunk::Text(chunk)
+            });
+        }
+        digits = c.is_ascii_digit();
+        current.push(c);
+    }
+    if !current.is_empty() {
+        chunks.push(if digits {
+            Chunk::Number(current)
+        } else {
+            Chunk::Text(current)
+        });
+    }
+    chunks
+}
+
+// Compare two paths in natural order, so `img2` comes before `img10`. Ties,
+// e.g. names differing only in case, are broken by the raw path so the order
+// is always total.
+pub fn natural_cmp(a: &Path, b: &Path) -> Ordering {
+    chunks(&a.to_string_lossy())
+        .cmp(&chunks(&b.to_string_lossy()))
+        .then_with(|| a.as_os_str().cmp(b.as_os_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn sorted(names: &[&str]) -> Vec<String> {
+        let mut paths: Vec<PathBuf> = names.iter().map(PathBuf::from).collect();
+        paths.sort_by(|a, b| natural_cmp(a, b));
+        paths
+            .iter()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn numbers_by_value() {
+        assert_eq!(
+            sorted(&["img10.jpg", "img2.jpg", "img1.jpg"]),
+            ["img1.jpg", "img2.jpg", "img10.jpg"]
+        );
+        assert_eq!(sorted(&["a010", "a9", "a0011"]), ["a9", "a010", "a0011"]);
+    }
+
+    #[test]
+    fn numbers_longer_than_any_integer() {
+        let big = format!("{}1", "9".repeat(40));
+        let bigger = format!("1{}", "0".repeat(41));
+        assert_eq!(sorted(&[&bigger, &big]), [big, bigger]);
+    }
+
+    #[test]
+    fn numbers_before_text() {
+        assert_eq!(sorted(&["b", "1", "a"]), ["1", "a", "b"]);
+    }
+
+    #[test]
+    fn case_and_accents_are_folded() {
+        assert_eq!(
+            sorted(&["zebra", "École", "apple"]),
+            ["apple", "École", "zebra"]
+        );
+        assert_eq!(
+            natural_cmp(Path::new("Photo"), Path::new("photo")),
+            Ordering::Less
+        );
+    }
+
+    #[test]
+    fn order_is_total() {
+        assert_eq!(natural_cmp(Path::new("a"), Path::new("a")), Ordering::Equal);
+        assert_ne!(
+            natural_cmp(Path::new("e\u{301}"), Path::new("\u{e9}")),
+            Ordering::Equal
+        );
+    }
+}
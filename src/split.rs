@@ -0,0 +1,107 @@
+use anyhow::{ensure, Result};
+use image::{imageops, RgbaImage};
+
+use crate::layout::Tile;
+
+// The regions of a `width` x `height` image cut into a `columns` x `rows`
+// grid, left to right and top to bottom. Cells differ in size by at most a
+// pixel when the image doesn't divide evenly, and each one extends `overlap`
+// pixels into its neighbors, but never past the edge of the image.
+pub fn grid(width: u32, height: u32, columns: u32, rows: u32, overlap: u32) -> Result<Vec<Tile>> {
+    ensure!(
+        columns <= width && rows <= height,
+        "Can't split a {width}x{height} image into {columns}x{rows} tiles"
+    );
+    let edge = |i: u32, count: u32, size: u32| (i as u64 * size as u64 / count as u64) as u32;
+    let mut tiles = Vec::with_capacity((columns * rows) as usize);
+    for row in 0..rows {
+        let top = edge(row, rows, height).saturating_sub(overlap);
+        let bottom = edge(row + 1, rows, height)
+            .saturating_add(overlap)
+            .min(height);
+        for column in 0..columns {
+            let left = edge(column, columns, width).saturating_sub(overlap);
+            let right = edge(column + 1, columns, width)
+                .saturating_add(overlap)
+                .min(width);
+            tiles.push(Tile {
+                x: left,
+                y: top,
+                width: right - left,
+                height: bottom - top,
+            });
+        }
+    }
+    Ok(tiles)
+}
+
+// Cut `image` into a `columns` x `rows` grid of separate images.
+pub fn split(image: &RgbaImage, columns: u32, rows: u32, overlap: u32) -> Result<Vec<RgbaImage>> {
+    Ok(grid(image.width(), image.height(), columns, rows, overlap)?
+        .iter()
+        .map(|tile| imageops::crop_imm(image, tile.x, tile.y, tile.width, tile.height).to_image())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tile(x: u32, y: u32, width: u32, height: u32) -> Tile {
+        Tile {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn even_grid() {
+        assert_eq!(
+            grid(200, 100, 2, 2, 0).unwrap(),
+            [
+                tile(0, 0, 100, 50),
+                tile(100, 0, 100, 50),
+                tile(0, 50, 100, 50),
+                tile(100, 50, 100, 50),
+            ]
+        );
+    }
+
+    #[test]
+    fn uneven_cells_differ_by_a_pixel_at_most() {
+        let tiles = grid(10, 1, 3, 1, 0).unwrap();
+        assert_eq!(
+            tiles,
+            [tile(0, 0, 3, 1), tile(3, 0, 3, 1), tile(6, 0, 4, 1)]
+        );
+    }
+
+    #[test]
+    fn overlap_stops_at_the_edges() {
+        assert_eq!(
+            grid(300, 50, 3, 1, 10).unwrap(),
+            [
+                tile(0, 0, 110, 50),
+                tile(90, 0, 120, 50),
+                tile(190, 0, 110, 50)
+            ]
+        );
+    }
+
+    #[test]
+    fn more_cells_than_pixels() {
+        assert!(grid(2, 2, 3, 1, 0).is_err());
+        assert!(grid(2, 2, 1, 3, 0).is_err());
+    }
+
+    #[test]
+    fn split_crops_the_cells() {
+        let image = RgbaImage::from_fn(4, 2, |x, _| image::Rgba([x as u8, 0, 0, 255]));
+        let parts = split(&image, 2, 1, 0).unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[1].dimensions(), (2, 2));
+        assert_eq!(parts[1].get_pixel(0, 0)[0], 2);
+    }
+}
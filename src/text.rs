@@ -0,0 +1,108 @@
+use font8x8::{UnicodeFonts, BASIC_FONTS};
+use image::{Rgba, RgbaImage};
+
+// The height of a line of text drawn at `scale` 1, in pixels.
+pub const GLYPH_SIZE: u32 = 8;
+
+// The width of `text` drawn at `scale`, in pixels.
+pub fn width(text: &str, scale: u32) -> u32 {
+    text.chars().count() as u32 * GLYPH_SIZE * scale
+}
+
+// Draw `text` with its top left corner at `x`, `y`, each pixel of the 8x8
+// font becoming a `scale` x `scale` square. Characters the font doesn't have
+// are drawn as `?` and anything outside of the image is clipped.
+pub fn draw(image: &mut RgbaImage, text: &str, x: u32, y: u32, scale: u32, color: Rgba<u8>) {
+    for (i, c) in text.chars().enumerate() {
+        let glyph = BASIC_FONTS
+            .get(c)
+            .or_else(|| BASIC_FONTS.get('?'))
+            .unwrap_or_default();
+        let left = x as u64 + (i as u64 * (GLYPH_SIZE * scale) as u64);
+        for (row, bits) in glyph.iter().enumerate() {
+            for column in 0..GLYPH_SIZE {
+                if bits & (1 << column) == 0 {
+                    continue;
+                }
+                for dy in 0..scale as u64 {
+                    for dx in 0..scale as u64 {
+                        let px = left + column as u64 * scale as u64 + dx;
+                        let py = y as u64 + row as u64 * scale as u64 + dy;
+                        if px < image.width() as u64 && py < image.height() as u64 {
+                            image.put_pixel(px as u32, py as u32, color);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Black or white, whichever stands out more against `background`.
+pub fn contrasting(background: Rgba<u8>) -> Rgba<u8> {
+    let [r, g, b, _] = background.0;
+    let luma = 0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32;
+    if luma > 128.0 {
+        Rgba([0, 0, 0, 255])
+    } else {
+        Rgba([255, 255, 255, 255])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draws_scaled_glyphs() {
+        let white = Rgba([255, 255, 255, 255]);
+        let mut image = RgbaImage::new(40, 20);
+        draw(&mut image, "I", 0, 0, 2, white);
+        let lit: Vec<(u32, u32)> = image
+            .enumerate_pixels()
+            .filter(|(_, _, p)| p[3] > 0)
+            .map(|(x, y, _)| (x, y))
+            .collect();
+        assert!(!lit.is_empty());
+        // Every pixel of the font is a 2x2 square, within the first glyph.
+        assert!(lit.iter().all(|&(x, y)| x < 16 && y < 16));
+        for &(x, y) in &lit {
+            let (x, y) = (x & !1, y & !1);
+            assert!([(x, y), (x + 1, y), (x, y + 1), (x + 1, y + 1)]
+                .iter()
+                .all(|&(x, y)| image.get_pixel(x, y) == &white));
+        }
+        assert_eq!(width("I", 2), 16);
+        assert_eq!(width("héllo", 1), 40);
+    }
+
+    #[test]
+    fn unknown_characters_are_question_marks() {
+        let color = Rgba([9, 9, 9, 255]);
+        let mut unknown = RgbaImage::new(8, 8);
+        draw(&mut unknown, "\u{2603}", 0, 0, 1, color);
+        let mut question = RgbaImage::new(8, 8);
+        draw(&mut question, "?", 0, 0, 1, color);
+        assert_eq!(unknown, question);
+    }
+
+    #[test]
+    fn text_is_clipped_to_the_image() {
+        let mut image = RgbaImage::new(4, 4);
+        draw(&mut image, "WWW", u32::MAX - 4, 0, 3, Rgba([1, 1, 1, 255]));
+        draw(&mut image, "WWW", 2, 2, 3, Rgba([1, 1, 1, 255]));
+        assert!(image.pixels().any(|p| p[3] > 0));
+    }
+
+    #[test]
+    fn contrasting_colors() {
+        assert_eq!(
+            contrasting(Rgba([250, 250, 200, 255])),
+            Rgba([0, 0, 0, 255])
+        );
+        assert_eq!(
+            contrasting(Rgba([0, 0, 255, 255])),
+            Rgba([255, 255, 255, 255])
+        );
+    }
+}
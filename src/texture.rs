@@ -0,0 +1,126 @@
+use clap::ValueEnum;
+use image::{Rgba, RgbaImage};
+use serde::Serialize;
+
+// A background texture, drawn procedurally in its own colors.
+#[derive(ValueEnum, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum Texture {
+    // Off-white paper with a fine grain and faint blotches.
+    Paper,
+    // Beige linen, woven of uneven threads.
+    Linen,
+    // Cream artist's canvas with a tight, regular weave.
+    Canvas,
+    // Brown cork board, granular with dark specks.
+    CorkBoard,
+}
+
+// A pseudo random number in [0, 1) for the point `x`, `y`, different for
+// each `salt`, so that a texture is the same every time it is drawn.
+pub fn hash(x: u32, y: u32, salt: u32) -> f32 {
+    let mut h = x
+        .wrapping_mul(0x8da6_b343)
+        .wrapping_add(y.wrapping_mul(0xd816_3841))
+        .wrapping_add(salt.wrapping_mul(0xcb1a_b31f));
+    h ^= h >> 13;
+    h = h.wrapping_mul(0x5bd1_e995);
+    h ^= h >> 15;
+    (h >> 8) as f32 / (1 << 24) as f32
+}
+
+// Smooth noise in [0, 1) with features about `scale` pixels across.
+pub fn value_noise(x: u32, y: u32, scale: u32, salt: u32) -> f32 {
+    let (cx, cy) = (x / scale, y / scale);
+    let fx = (x % scale) as f32 / scale as f32;
+    let fy = (y % scale) as f32 / scale as f32;
+    let (sx, sy) = (fx * fx * (3.0 - 2.0 * fx), fy * fy * (3.0 - 2.0 * fy));
+    let top = hash(cx, cy, salt) * (1.0 - sx) + hash(cx + 1, cy, salt) * sx;
+    let bottom = hash(cx, cy + 1, salt) * (1.0 - sx) + hash(cx + 1, cy + 1, salt) * sx;
+    top * (1.0 - sy) + bottom * sy
+}
+
+// A `width` x `height` image of `texture`. Each pixel only depends on where
+// it is, so a larger image of the same texture extends a smaller one.
+pub fn render(texture: Texture, width: u32, height: u32) -> RgbaImage {
+    let (base, shade): ([f32; 3], fn(u32, u32) -> f32) = match texture {
+        Texture::Paper => ([245.0, 241.0, 230.0], |x, y| {
+            (hash(x, y, 1) - 0.5) * 10.0 + (value_noise(x, y, 64, 2) - 0.5) * 8.0
+        }),
+        Texture::Linen => ([226.0, 216.0, 196.0], |x, y| {
+            // Every row and column of thread has its own brightness.
+            let weft = hash(0, y, 3) - 0.5;
+            let warp = hash(x, 0, 4) - 0.5;
+            let thread = if (x + y) % 2 == 0 { weft } else { warp };
+            thread * 22.0 + (hash(x, y, 5) - 0.5) * 6.0
+        }),
+        Texture::Canvas => ([236.0, 229.0, 212.0], |x, y| {
+            // Threads two pixels wide cross over and under each other, each
+            // lit on one side.
+            let over = (x / 2 + y / 2) % 2 == 0;
+            let lit = if over { x % 2 } else { y % 2 };
+            let weave = if over { 6.0 } else { -6.0 } + if lit == 0 { 4.0 } else { -4.0 };
+            weave + (hash(x, y, 6) - 0.5) * 8.0
+        }),
+        Texture::CorkBoard => ([178.0, 130.0, 84.0], |x, y| {
+            let granules = (value_noise(x, y, 3, 7) - 0.5) * 50.0;
+            let specks = if hash(x / 2, y / 2, 8) < 0.02 {
+                -55.0
+            } else {
+                0.0
+            };
+            granules + specks + (value_noise(x, y, 40, 9) - 0.5) * 16.0
+        }),
+    };
+    RgbaImage::from_fn(width, height, |x, y| {
+        let s = shade(x, y);
+        let [r, g, b] = base.map(|c| (c + s).clamp(0.0, 255.0) as u8);
+        Rgba([r, g, b, 255])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn noise_is_in_range_and_repeatable() {
+        for (x, y) in [(0, 0), (1, 0), (u32::MAX, 7), (123, 456)] {
+            let h = hash(x, y, 3);
+            assert!((0.0..1.0).contains(&h));
+            assert_eq!(h, hash(x, y, 3));
+            assert!((0.0..1.0).contains(&value_noise(x, y, 16, 3)));
+        }
+        assert_ne!(hash(5, 5, 1), hash(5, 5, 2));
+        // On the lattice, value noise is the hash of the cell.
+        assert_eq!(value_noise(32, 48, 16, 9), hash(2, 3, 9));
+    }
+
+    #[test]
+    fn larger_textures_extend_smaller_ones() {
+        for &texture in Texture::value_variants() {
+            let small = render(texture, 20, 10);
+            let large = render(texture, 40, 30);
+            let crop = image::imageops::crop_imm(&large, 0, 0, 20, 10).to_image();
+            assert_eq!(small, crop, "{texture:?}");
+        }
+    }
+
+    #[test]
+    fn textures_keep_their_color() {
+        for (texture, base) in [
+            (Texture::Paper, [245, 241, 230]),
+            (Texture::Linen, [226, 216, 196]),
+            (Texture::Canvas, [236, 229, 212]),
+            (Texture::CorkBoard, [178, 130, 84]),
+        ] {
+            let image = render(texture, 64, 64);
+            let n = (64 * 64) as f32;
+            for c in 0..3 {
+                let mean = image.pixels().map(|p| p[c] as f32).sum::<f32>() / n;
+                assert!((mean - base[c] as f32).abs() < 12.0, "{texture:?} {mean}");
+            }
+            assert!(image.pixels().any(|p| p != image.get_pixel(0, 0)));
+        }
+    }
+}
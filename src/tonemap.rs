@@ -0,0 +1,125 @@
+use clap::ValueEnum;
+use image::{DynamicImage, Rgba32FImage, RgbaImage};
+use serde::Serialize;
+
+use crate::color;
+
+// How the linear light of an HDR image (EXR or Radiance HDR) is mapped to the
+// displayable range.
+#[derive(ValueEnum, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum ToneMap {
+    // The filmic curve of the ACES reference rendering, with a gentle
+    // shoulder and some added contrast.
+    Aces,
+    // Compress the luminance with L / (1 + L), keeping the hue.
+    Reinhard,
+    // Cut off everything brighter than white.
+    Clamp,
+}
+
+// The average brightness an HDR image is exposed to, as in photography's
+// middle gray.
+const KEY: f32 = 0.18;
+
+// Tone map an HDR image to 8 bit sRGB. Returns `None` for images that are not
+// HDR, which need no tone mapping. Except with `clamp`, the image is first
+// exposed so that its log-average luminance becomes middle gray, since renders
+// and captures come in any range.
+pub fn tone_map(image: &DynamicImage, operator: ToneMap) -> Option<RgbaImage> {
+    if !matches!(
+        image,
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_)
+    ) {
+        return None;
+    }
+    let mut linear = image.to_rgba32f();
+    let exposure = match operator {
+        ToneMap::Clamp => 1.0,
+        ToneMap::Aces | ToneMap::Reinhard => KEY / log_average_luminance(&linear),
+    };
+    for pixel in linear.pixels_mut() {
+        let rgb = [pixel[0], pixel[1], pixel[2]].map(|c| c.max(0.0) * exposure);
+        let mapped = match operator {
+            ToneMap::Aces => rgb.map(aces),
+            ToneMap::Reinhard => {
+                let l = luminance(rgb);
+                rgb.map(|c| c / (1.0 + l))
+            }
+            ToneMap::Clamp => rgb,
+        };
+        pixel[0] = mapped[0];
+        pixel[1] = mapped[1];
+        pixel[2] = mapped[2];
+    }
+    Some(color::from_linear(&linear))
+}
+
+fn luminance([r, g, b]: [f32; 3]) -> f32 {
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+// The geometric mean of the luminance of the image, which unlike the plain mean
+// isn't dominated by a few very bright pixels such as the sun.
+fn log_average_luminance(image: &Rgba32FImage) -> f32 {
+    let n = (image.width() as f64 * image.height() as f64).max(1.0);
+    let sum: f64 = image
+        .pixels()
+        .map(|p| (luminance([p[0], p[1], p[2]]).max(0.0) as f64 + 1e-4).ln())
+        .sum();
+    ((sum / n).exp() as f32).max(1e-4)
+}
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve.
+fn aces(x: f32) -> f32 {
+    let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+    (x * (a * x + b) / (x * (c * x + d) + e)).clamp(0.0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, Rgb32FImage};
+
+    fn hdr(values: &[f32]) -> DynamicImage {
+        let mut image = Rgb32FImage::new(values.len() as u32, 1);
+        for (pixel, &v) in image.pixels_mut().zip(values) {
+            *pixel = Rgb([v, v, v]);
+        }
+        DynamicImage::ImageRgb32F(image)
+    }
+
+    #[test]
+    fn only_hdr_images_are_tone_mapped() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::new(2, 2));
+        assert!(tone_map(&image, ToneMap::Aces).is_none());
+    }
+
+    #[test]
+    fn clamp_cuts_off_above_white() {
+        let mapped = tone_map(&hdr(&[0.0, 0.5, 1.0, 8.0]), ToneMap::Clamp).unwrap();
+        let values: Vec<u8> = mapped.pixels().map(|p| p[0]).collect();
+        assert_eq!(values, [0, 188, 255, 255]);
+        assert!(mapped.pixels().all(|p| p[3] == 255));
+    }
+
+    #[test]
+    fn exposes_to_middle_gray() {
+        // Any uniform brightness is exposed to 0.18, which Reinhard maps to
+        // 0.18 / 1.18, 109 in sRGB.
+        for v in [0.01, 1.0, 500.0] {
+            let mapped = tone_map(&hdr(&[v; 4]), ToneMap::Reinhard).unwrap();
+            assert!(mapped.pixels().all(|p| p[0].abs_diff(109) <= 1), "{v}");
+        }
+    }
+
+    #[test]
+    fn aces_keeps_the_order_and_the_range() {
+        let mapped = tone_map(&hdr(&[0.0, 0.1, 1.0, 10.0, 1000.0]), ToneMap::Aces).unwrap();
+        let values: Vec<u8> = mapped.pixels().map(|p| p[0]).collect();
+        assert_eq!(values[0], 0);
+        assert!(values.windows(2).all(|w| w[0] < w[1]), "{values:?}");
+        assert_eq!(aces(1e6), 1.0);
+        assert_eq!(aces(0.0), 0.0);
+    }
+}
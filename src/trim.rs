@@ -0,0 +1,104 @@
+use image::{DynamicImage, GenericImageView, Rgba};
+
+// How far apart (in levels of 0 to 255, per channel) two colors may be and
+// still count as the same border color, which allows for JPEG noise.
+const TOLERANCE: u8 = 24;
+
+// Crop the borders of a single uniform color off `image`: scanner edges,
+// letterboxing and the like. The top and left borders are taken to be the
+// color of the top left pixel and the bottom and right ones the color of the
+// bottom right pixel. Returns `None` if there is nothing to crop, or if the
+// whole image is a single color.
+pub fn trim(image: &DynamicImage) -> Option<DynamicImage> {
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return None;
+    }
+    let same = |a: Rgba<u8>, b: Rgba<u8>| (0..4).all(|c| a[c].abs_diff(b[c]) <= TOLERANCE);
+    let row_is = |y: u32, color: Rgba<u8>| (0..width).all(|x| same(image.get_pixel(x, y), color));
+    let column_is = |x: u32, top: u32, bottom: u32, color: Rgba<u8>| {
+        (top..bottom).all(|y| same(image.get_pixel(x, y), color))
+    };
+
+    let first = image.get_pixel(0, 0);
+    let last = image.get_pixel(width - 1, height - 1);
+    let top = (0..height).find(|&y| !row_is(y, first))?;
+    let bottom = (top..height).rev().find(|&y| !row_is(y, last))? + 1;
+    let left = (0..width).find(|&x| !column_is(x, top, bottom, first))?;
+    let right = (left..width)
+        .rev()
+        .find(|&x| !column_is(x, top, bottom, last))?
+        + 1;
+    if (left, top, right, bottom) == (0, 0, width, height) {
+        return None;
+    }
+    Some(image.crop_imm(left, top, right - left, bottom - top))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    // A `width` by `height` image of `border`, with a red rectangle from
+    // (left, top) to (right, bottom).
+    fn framed(
+        width: u32,
+        height: u32,
+        border: [u8; 4],
+        inner: (u32, u32, u32, u32),
+    ) -> DynamicImage {
+        let (left, top, right, bottom) = inner;
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, y| {
+            if (left..right).contains(&x) && (top..bottom).contains(&y) {
+                Rgba([200, 0, 0, 255])
+            } else {
+                Rgba(border)
+            }
+        }))
+    }
+
+    #[test]
+    fn trims_uniform_borders() {
+        let image = framed(10, 8, [255, 255, 255, 255], (2, 1, 7, 6));
+        let trimmed = trim(&image).unwrap();
+        assert_eq!(trimmed.dimensions(), (5, 5));
+        assert!(trimmed
+            .to_rgba8()
+            .pixels()
+            .all(|p| p == &Rgba([200, 0, 0, 255])));
+    }
+
+    #[test]
+    fn tolerates_noise_in_the_border() {
+        let mut image = framed(6, 6, [0, 0, 0, 255], (2, 2, 4, 4)).into_rgba8();
+        image.put_pixel(0, 3, Rgba([20, 10, 0, 255]));
+        let trimmed = trim(&DynamicImage::ImageRgba8(image)).unwrap();
+        assert_eq!(trimmed.dimensions(), (2, 2));
+    }
+
+    #[test]
+    fn trims_only_the_sides_with_a_border() {
+        // A white border on the top and left of a gradient, which runs into
+        // the bottom and right edges.
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(6, 6, |x, y| {
+            if x < 2 || y < 2 {
+                Rgba([255, 255, 255, 255])
+            } else {
+                Rgba([x as u8 * 40, y as u8 * 40, 0, 255])
+            }
+        }));
+        let trimmed = trim(&image).unwrap();
+        assert_eq!(trimmed.dimensions(), (4, 4));
+        assert_eq!(trimmed.get_pixel(0, 0), Rgba([80, 80, 0, 255]));
+    }
+
+    #[test]
+    fn nothing_to_trim() {
+        let uniform = framed(4, 4, [9, 9, 9, 255], (0, 0, 0, 0));
+        assert!(trim(&uniform).is_none());
+        let borderless = framed(4, 4, [9, 9, 9, 255], (0, 0, 4, 4));
+        assert!(trim(&borderless).is_none());
+        assert!(trim(&DynamicImage::new_rgba8(0, 0)).is_none());
+    }
+}
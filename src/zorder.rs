@@ -0,0 +1,114 @@
+use clap::ValueEnum;
+use rand::{seq::SliceRandom, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::Serialize;
+use std::path::PathBuf;
+
+use crate::{layout::Tile, metadata};
+
+// The order overlapping images are stacked in, from the bottom to the top.
+#[derive(ValueEnum, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum ZOrder {
+    // In the order of the images, each on top of the ones before it.
+    Input,
+    // A random order, from the seed.
+    Random,
+    // The largest tiles at the bottom and the smallest on top.
+    LargestFirst,
+    // By the date each photo was taken, the newest on top. Photos without a
+    // date go at the bottom.
+    Date,
+}
+
+// The indices of the tiles in the order they are drawn in. Images with a
+// higher `z` from `z_of` always go on top of those with a lower one; `mode`
+// orders the images with the same `z`.
+pub fn order(
+    mode: ZOrder,
+    tiles: &[Tile],
+    paths: &[PathBuf],
+    z_of: impl Fn(&PathBuf) -> i32,
+    seed: u64,
+) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..tiles.len().min(paths.len())).collect();
+    match mode {
+        ZOrder::Input => {}
+        ZOrder::Random => order.shuffle(&mut ChaCha8Rng::seed_from_u64(seed)),
+        ZOrder::LargestFirst => order
+            .sort_by_key(|&i| std::cmp::Reverse(tiles[i].width as u64 * tiles[i].height as u64)),
+        ZOrder::Date => order.sort_by_cached_key(|&i| metadata::date_taken(&paths[i])),
+    }
+    // The sort is stable, so images with the same z keep the order of `mode`.
+    order.sort_by_cached_key(|&i| z_of(&paths[i]));
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tiles(sizes: &[u32]) -> Vec<Tile> {
+        sizes
+            .iter()
+            .map(|&size| Tile {
+                x: 0,
+                y: 0,
+                width: size,
+                height: size,
+            })
+            .collect()
+    }
+
+    fn paths(n: usize) -> Vec<PathBuf> {
+        (0..n).map(|i| PathBuf::from(format!("{i}.jpg"))).collect()
+    }
+
+    #[test]
+    fn orders_by_mode() {
+        let (tiles, paths) = (tiles(&[10, 30, 20, 30]), paths(4));
+        let flat = |_: &PathBuf| 0;
+        assert_eq!(order(ZOrder::Input, &tiles, &paths, flat, 1), [0, 1, 2, 3]);
+        assert_eq!(
+            order(ZOrder::LargestFirst, &tiles, &paths, flat, 1),
+            [1, 3, 2, 0]
+        );
+        // None of the paths has a date, so they keep their order.
+        assert_eq!(order(ZOrder::Date, &tiles, &paths, flat, 1), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn random_order_comes_from_the_seed() {
+        let (tiles, paths) = (tiles(&[1; 20]), paths(20));
+        let random = |seed| order(ZOrder::Random, &tiles, &paths, |_| 0, seed);
+        assert_eq!(random(7), random(7));
+        assert_ne!(random(7), random(8));
+        let mut sorted = random(7);
+        sorted.sort();
+        assert_eq!(sorted, (0..20).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn z_comes_before_the_mode() {
+        let (tiles, paths) = (tiles(&[10, 30, 20, 30]), paths(4));
+        // 1 on top and 2 at the bottom, the rest largest first.
+        let z = |path: &PathBuf| match path.to_str() {
+            Some("1.jpg") => 1,
+            Some("2.jpg") => -1,
+            _ => 0,
+        };
+        assert_eq!(
+            order(ZOrder::LargestFirst, &tiles, &paths, z, 1),
+            [2, 3, 0, 1]
+        );
+    }
+
+    #[test]
+    fn extra_tiles_or_paths_are_left_out() {
+        assert_eq!(
+            order(ZOrder::Input, &tiles(&[1, 1, 1]), &paths(2), |_| 0, 1),
+            [0, 1]
+        );
+        assert_eq!(order(ZOrder::Input, &tiles(&[1]), &paths(2), |_| 0, 1), [0]);
+    }
+}